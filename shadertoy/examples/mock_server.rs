@@ -0,0 +1,26 @@
+//! Runs a local stand-in for the Shadertoy API serving shaders from a fixtures directory,
+//! see `shadertoy::MockServer` for the expected layout.
+//!
+//! ```sh
+//! $ cargo run --features mock-server --example mock_server -- tests/fixtures 127.0.0.1:8080 mock
+//! $ shadertoy-browser --baseurl http://127.0.0.1:8080 --apikey mock --headless
+//! ```
+
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let fixtures_dir = args.get(1).map(String::as_str).unwrap_or("tests/fixtures");
+    let addr = args.get(2).map(String::as_str).unwrap_or("127.0.0.1:8080");
+    let api_key = args.get(3).map(String::as_str).unwrap_or("mock");
+
+    let server = shadertoy::MockServer::bind(addr, fixtures_dir, api_key)
+        .expect("failed starting mock server");
+    println!(
+        "Serving {} on {} with API key \"{}\"",
+        fixtures_dir,
+        server.url(),
+        api_key
+    );
+    server.wait();
+}
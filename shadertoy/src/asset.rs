@@ -0,0 +1,46 @@
+use crate::types::*;
+
+/// A downloaded asset file, such as a texture, used as input for a render pass.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    /// Path of the asset on the site, e.g. `/media/a/52d2a8f5.jpg`.
+    pub src: String,
+    /// MIME type of the asset, if reported by the server.
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl ChannelType {
+    /// Returns true if inputs of this type reference asset files that can be downloaded.
+    pub fn has_asset(&self) -> bool {
+        matches!(
+            self,
+            ChannelType::Texture | ChannelType::Volume | ChannelType::Cubemap | ChannelType::Buffer
+        )
+    }
+}
+
+impl RenderPassInput {
+    /// Paths of all the asset files of this input, empty if it has no assets.
+    ///
+    /// Cubemaps consist of six files, `src` is the first face and the other five faces
+    /// have `_1` to `_5` appended to the file name, e.g. `/media/a/cube_1.png`.
+    pub fn asset_paths(&self) -> Vec<String> {
+        if !self.ctype.has_asset() || self.src.is_empty() {
+            return vec![];
+        }
+
+        if self.ctype != ChannelType::Cubemap {
+            return vec![self.src.clone()];
+        }
+
+        let (stem, extension) = match self.src.rfind('.') {
+            Some(index) if !self.src[index..].contains('/') => self.src.split_at(index),
+            _ => (self.src.as_str(), ""),
+        };
+
+        std::iter::once(self.src.clone())
+            .chain((1..6).map(|face| format!("{}_{}{}", stem, face, extension)))
+            .collect()
+    }
+}
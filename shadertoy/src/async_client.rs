@@ -0,0 +1,213 @@
+use crate::client::*;
+use crate::errors::*;
+use crate::transport::*;
+use crate::types::*;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Boxed future returned by `AsyncTransport`.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Default maximum number of requests an `AsyncClient` has in flight at the same time.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Asynchronous HTTP transport used by `AsyncClient` to issue requests.
+pub trait AsyncTransport: Send + Sync {
+    /// Issue a GET request for the given absolute URL.
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Response>>;
+}
+
+/// `AsyncTransport` implemented on top of an async `reqwest` client.
+pub struct ReqwestAsyncTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestAsyncTransport {
+    /// Create a new transport with an optional request timeout and the user agent to send.
+    pub fn new(timeout: Option<Duration>, user_agent: &str) -> Result<ReqwestAsyncTransport> {
+        let mut builder = reqwest::Client::builder().user_agent(user_agent);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(ReqwestAsyncTransport {
+            client: builder.build()?,
+        })
+    }
+}
+
+impl AsyncTransport for ReqwestAsyncTransport {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let response = self.client.get(url).send().await?;
+
+            let status = response.status().as_u16();
+            let headers = response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.as_str().to_string(), v.to_string()))
+                })
+                .collect();
+            let body = response.bytes().await?.to_vec();
+
+            Ok(Response {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
+
+impl ClientBuilder {
+    /// Use a custom transport for `AsyncClient` instead of the default `ReqwestAsyncTransport`.
+    pub fn async_transport<T: AsyncTransport + 'static>(mut self, transport: T) -> ClientBuilder {
+        self.async_transport = Some(Box::new(transport));
+        self
+    }
+
+    /// Set the maximum number of requests an `AsyncClient` has in flight at the same time.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> ClientBuilder {
+        self.max_concurrent_requests = max_concurrent_requests;
+        self
+    }
+
+    /// Create an asynchronous client.
+    pub fn build_async(self) -> Result<AsyncClient> {
+        let transport = match self.async_transport {
+            Some(transport) => transport,
+            None => Box::new(ReqwestAsyncTransport::new(self.timeout, &self.user_agent)?),
+        };
+
+        Ok(AsyncClient::new(
+            self.api_key,
+            self.base_url,
+            transport,
+            self.max_concurrent_requests,
+        ))
+    }
+}
+
+/// Asynchronous version of `Client`, created with `ClientBuilder::build_async`.
+///
+/// At most `ClientBuilder::max_concurrent_requests` requests are in flight at the same time,
+/// no matter how many futures are polled concurrently.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() {
+/// let client = shadertoy::ClientBuilder::new("Bd8tWD") // insert your own API key here
+///     .max_concurrent_requests(4)
+///     .build_async()
+///     .unwrap();
+/// let shaders = client.get_shaders(&["4d2BDy", "XsX3RB"]);
+/// # drop(shaders);
+/// # }
+/// ```
+pub struct AsyncClient {
+    pub api_key: String,
+    /// Base URL of the site, without trailing slash, e.g. `https://www.shadertoy.com`.
+    pub base_url: String,
+    /// Transport all requests are issued through.
+    pub transport: Box<dyn AsyncTransport>,
+
+    max_concurrent_requests: usize,
+    request_slots: Semaphore,
+}
+
+impl AsyncClient {
+    fn new(
+        api_key: String,
+        base_url: String,
+        transport: Box<dyn AsyncTransport>,
+        max_concurrent_requests: usize,
+    ) -> AsyncClient {
+        let max_concurrent_requests = max_concurrent_requests.max(1);
+        AsyncClient {
+            api_key,
+            base_url,
+            transport,
+            max_concurrent_requests,
+            request_slots: Semaphore::new(max_concurrent_requests),
+        }
+    }
+
+    /// Issues a search query for shadertoys, see `Client::search`.
+    pub async fn search(&self, params: &SearchParams<'_>) -> Result<Vec<String>> {
+        Ok(self.search_page(params).await?.ids)
+    }
+
+    /// Issues a search query for a single page of shadertoys, see `Client::search_page`.
+    pub async fn search_page(&self, params: &SearchParams<'_>) -> Result<SearchPage> {
+        let response = self
+            .get(&search_url(&self.base_url, &self.api_key, params))
+            .await?;
+        parse_search_page(&response.body)
+    }
+
+    /// Lazily streams all shader ids matching a search, see `Client::search_pages`.
+    pub fn search_pages<'a>(
+        &'a self,
+        params: &SearchParams<'a>,
+    ) -> impl Stream<Item = Result<String>> + 'a {
+        let mut params = params.clone();
+        params.page_size = Some(params.page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE));
+
+        let state = (params, VecDeque::<String>::new(), false);
+        stream::unfold(state, move |(mut params, mut ids, done)| async move {
+            if let Some(id) = ids.pop_front() {
+                return Some((Ok(id), (params, ids, done)));
+            }
+            if done {
+                return None;
+            }
+
+            match self.search_page(&params).await {
+                Ok(page) => {
+                    let page_size = params.page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE);
+                    params.offset += page.ids.len();
+                    let done = page.ids.len() < page_size || params.offset as u64 >= page.total;
+                    ids.extend(page.ids);
+                    ids.pop_front().map(|id| (Ok(id), (params, ids, done)))
+                }
+                Err(err) => Some((Err(err), (params, ids, true))),
+            }
+        })
+    }
+
+    /// Retrives a shader given an id.
+    pub async fn get_shader(&self, shader_id: &str) -> Result<Shader> {
+        let response = self
+            .get(&shader_url(&self.base_url, &self.api_key, shader_id))
+            .await?;
+        parse_shader(&response.body, shader_id)
+    }
+
+    /// Retrieves multiple shaders concurrently, the results are in the same order as `shader_ids`.
+    pub async fn get_shaders<S: AsRef<str>>(&self, shader_ids: &[S]) -> Vec<Result<Shader>> {
+        stream::iter(shader_ids)
+            .map(|shader_id| self.get_shader(shader_id.as_ref()))
+            .buffered(self.max_concurrent_requests)
+            .collect()
+            .await
+    }
+
+    /// Issues a GET request through the transport, once a request slot is available,
+    /// and checks the HTTP status and size of the response.
+    async fn get(&self, url: &str) -> Result<Response> {
+        let _slot = self
+            .request_slots
+            .acquire()
+            .await
+            .expect("request semaphore is never closed");
+        check_status(self.transport.get(url).await?).and_then(|response| check_size(url, response))
+    }
+}
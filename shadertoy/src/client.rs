@@ -1,10 +1,22 @@
+use crate::asset::*;
 use crate::errors::*;
+use crate::retry::*;
+use crate::transport::*;
 use crate::types::*;
-use reqwest;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std;
+use std::collections::VecDeque;
 use std::str::FromStr;
+use std::time::Duration;
+
+/// Base URL of the official Shadertoy site, used unless another one is set with `ClientBuilder::base_url`.
+pub const DEFAULT_BASE_URL: &str = "https://www.shadertoy.com";
+
+/// User agent sent by the default transport.
+pub const DEFAULT_USER_AGENT: &str = concat!("shadertoy-rs/", env!("CARGO_PKG_VERSION"));
+
+/// Number of shader ids requested per page by `Client::search_pages` if `SearchParams::page_size` isn't set.
+pub const DEFAULT_SEARCH_PAGE_SIZE: usize = 100;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
 pub enum SearchSortOrder {
@@ -34,12 +46,118 @@ pub struct SearchParams<'a> {
     pub sort_order: SearchSortOrder,
     /// Inclusion filters, only the shadertoys matching this filter will be included in the result.
     pub filters: Vec<SearchFilter>,
+    /// Index of the first result to return.
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of results to return per request, `None` returns all results at once from `Client::search`.
+    #[serde(default)]
+    pub page_size: Option<usize>,
+}
+
+impl<'a> Default for SearchParams<'a> {
+    fn default() -> SearchParams<'a> {
+        SearchParams {
+            string: "",
+            sort_order: SearchSortOrder::Popular,
+            filters: vec![],
+            offset: 0,
+            page_size: None,
+        }
+    }
+}
+
+/// A single page of search results, as returned by `Client::search_page`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchPage {
+    /// Total number of shadertoys matching the search, across all pages.
+    pub total: u64,
+    /// Shader ids in this page.
+    pub ids: Vec<String>,
+}
+
+/// Iterator over the shader ids of a search, fetching one page at a time as needed.
+/// Created with `Client::search_pages`.
+pub struct SearchPages<'a> {
+    client: &'a Client,
+    params: SearchParams<'a>,
+    ids: VecDeque<String>,
+    total: Option<u64>,
+    done: bool,
+}
+
+impl<'a> SearchPages<'a> {
+    /// Total number of shadertoys matching the search, known once the first page is fetched.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+impl<'a> Iterator for SearchPages<'a> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        if let Some(id) = self.ids.pop_front() {
+            return Some(Ok(id));
+        }
+        if self.done {
+            return None;
+        }
+
+        match self.client.search_page(&self.params) {
+            Ok(page) => {
+                let page_size = self.params.page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE);
+                self.params.offset += page.ids.len();
+                self.done = page.ids.len() < page_size || self.params.offset as u64 >= page.total;
+                self.total = Some(page.total);
+                self.ids.extend(page.ids);
+                self.ids.pop_front().map(Ok)
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
 }
 
 /// Client for issuing queries against the Shadertoy API and database
 pub struct Client {
     pub api_key: String,
-    pub rest_client: reqwest::blocking::Client,
+    /// Base URL of the site, without trailing slash, e.g. `https://www.shadertoy.com`.
+    pub base_url: String,
+    /// Transport all requests are issued through.
+    pub transport: Box<dyn Transport>,
+
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// Builder for configuring a `Client`.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() {
+/// let client = shadertoy::ClientBuilder::new("Bd8tWD") // insert your own API key here
+///     .base_url("http://localhost:8080")
+///     .timeout(std::time::Duration::from_secs(10))
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+pub struct ClientBuilder {
+    pub(crate) api_key: String,
+    pub(crate) base_url: String,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) user_agent: String,
+    pub(crate) transport: Option<Box<dyn Transport>>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) requests_per_second: Option<f64>,
+
+    #[cfg(feature = "async")]
+    pub(crate) async_transport: Option<Box<dyn crate::AsyncTransport>>,
+    #[cfg(feature = "async")]
+    pub(crate) max_concurrent_requests: usize,
 }
 
 impl FromStr for SearchSortOrder {
@@ -73,16 +191,93 @@ impl FromStr for SearchFilter {
     }
 }
 
-impl Client {
-    /// Create a new client.
+impl ClientBuilder {
+    /// Create a new builder with default settings.
     /// This requires sending in an API key, one can generate one on https://www.shadertoy.com/profile
-    pub fn new(api_key: &str) -> Client {
-        Client {
+    pub fn new(api_key: &str) -> ClientBuilder {
+        ClientBuilder {
             api_key: api_key.to_string(),
-            rest_client: reqwest::blocking::Client::new(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            timeout: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            transport: None,
+            retry_policy: RetryPolicy::default(),
+            requests_per_second: None,
+
+            #[cfg(feature = "async")]
+            async_transport: None,
+            #[cfg(feature = "async")]
+            max_concurrent_requests: crate::DEFAULT_MAX_CONCURRENT_REQUESTS,
         }
     }
 
+    /// Set the base URL to issue requests against, for example a mirror or a local server.
+    pub fn base_url(mut self, base_url: &str) -> ClientBuilder {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Set the timeout for each request. Ignored if a custom transport is used.
+    pub fn timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the user agent to send. Ignored if a custom transport is used.
+    pub fn user_agent(mut self, user_agent: &str) -> ClientBuilder {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Use a custom transport instead of the default `ReqwestTransport`.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> ClientBuilder {
+        self.transport = Some(Box::new(transport));
+        self
+    }
+
+    /// Set the policy for retrying failed requests, defaults to `RetryPolicy::default()`.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> ClientBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limit the number of requests per second issued by the client, across all threads using it.
+    /// By default requests are not limited, nor are they if this is 0. Requests are never more
+    /// than a day apart.
+    pub fn requests_per_second(mut self, requests_per_second: f64) -> ClientBuilder {
+        self.requests_per_second = Some(requests_per_second);
+        self
+    }
+
+    /// Create the client.
+    pub fn build(self) -> Result<Client> {
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => Box::new(ReqwestTransport::new(self.timeout, &self.user_agent)?),
+        };
+
+        Ok(Client {
+            api_key: self.api_key,
+            base_url: self.base_url,
+            transport,
+            retry_policy: self.retry_policy,
+            rate_limiter: self
+                .requests_per_second
+                .filter(|rps| *rps > 0.0)
+                .map(RateLimiter::new),
+        })
+    }
+}
+
+impl Client {
+    /// Create a new client with default settings, see `ClientBuilder` for more options.
+    /// This requires sending in an API key, one can generate one on https://www.shadertoy.com/profile
+    pub fn new(api_key: &str) -> Client {
+        ClientBuilder::new(api_key)
+            .build()
+            .expect("failed creating default HTTP transport")
+    }
+
     /// Issues a search query for shadertoys.
     /// If the query is successful a list of shader ids will be returned,
     /// which can be used with `get_shader`.
@@ -93,89 +288,265 @@ impl Client {
     /// # fn main() {
     /// let client = shadertoy::Client::new("Bd8tWD"); // insert your own API key here
     /// let search_params = shadertoy::SearchParams {
-    /// 	string: "car",
+    ///     string: "car",
     ///     sort_order: shadertoy::SearchSortOrder::Love,
-    ///     filters: vec![],
+    ///     ..Default::default()
     /// };
     /// match client.search(&search_params) {
-    /// 	Ok(shader_ids) => println!("\"Car\" shadertoys: {:?}", shader_ids),
-    /// 	Err(err) => println!("Search failed: {}", err),
+    ///     Ok(shader_ids) => println!("\"Car\" shadertoys: {:?}", shader_ids),
+    ///     Err(err) => println!("Search failed: {}", err),
     /// }
     /// # }
     /// ```
     pub fn search(&self, params: &SearchParams<'_>) -> Result<Vec<String>> {
-        let query_str = format!(
-            "https://www.shadertoy.com/api/v1/shaders{}?sort={}&{}key={}",
-            if params.string.is_empty() {
-                "".to_string()
-            } else {
-                format!("/query/{}", params.string)
-            },
-            format!("{:?}", params.sort_order).to_lowercase(),
-            params
-                .filters
-                .iter()
-                .map(|f| format!("filter={:?}&", f).to_lowercase())
-                .collect::<String>(),
-            self.api_key
-        );
-
-        let json_str = self.rest_client.get(&query_str).send()?.text()?;
-
-        #[derive(Serialize, Deserialize, Debug)]
-        #[serde(deny_unknown_fields)]
-        struct SearchResult {
-            #[serde(default)]
-            #[serde(rename = "Error")]
-            error: String,
-
-            #[serde(default)]
-            #[serde(rename = "Shaders")]
-            shaders: u64,
-
-            #[serde(default)]
-            #[serde(rename = "Results")]
-            results: Vec<String>,
-        }
+        Ok(self.search_page(params)?.ids)
+    }
 
-        match serde_json::from_str::<SearchResult>(&json_str) {
-            Ok(r) => {
-                if !r.error.is_empty() {
-                    bail!("Shadertoy REST search query returned error: {}", r.error);
-                }
-                Ok(r.results)
-            }
-            Err(err) => {
-                Err(Error::from(err)).chain_err(|| "JSON parsing of Shadertoy search result failed")
-            }
+    /// Issues a search query for a single page of shadertoys, as set by `SearchParams::offset` and `SearchParams::page_size`.
+    pub fn search_page(&self, params: &SearchParams<'_>) -> Result<SearchPage> {
+        let response = self.get(&search_url(&self.base_url, &self.api_key, params))?;
+        parse_search_page(&response.body)
+    }
+
+    /// Lazily iterates over all shader ids matching a search, issuing one query per page of
+    /// `SearchParams::page_size` ids (or `DEFAULT_SEARCH_PAGE_SIZE`) as the iterator advances.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() {
+    /// let client = shadertoy::Client::new("Bd8tWD"); // insert your own API key here
+    /// let search_params = shadertoy::SearchParams {
+    ///     string: "car",
+    ///     ..Default::default()
+    /// };
+    /// for shader_id in client.search_pages(&search_params).take(10) {
+    ///     match shader_id {
+    ///         Ok(shader_id) => println!("{}", shader_id),
+    ///         Err(err) => println!("Search failed: {}", err),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn search_pages<'a>(&'a self, params: &SearchParams<'a>) -> SearchPages<'a> {
+        let mut params = params.clone();
+        params.page_size = Some(params.page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE));
+
+        SearchPages {
+            client: self,
+            params,
+            ids: VecDeque::new(),
+            total: None,
+            done: false,
         }
     }
 
     /// Retrives a shader given an id.
     pub fn get_shader(&self, shader_id: &str) -> Result<Shader> {
-        let json = self
-            .rest_client
-            .get(&format!(
-                "https://www.shadertoy.com/api/v1/shaders/{}?key={}",
-                shader_id, self.api_key
-            ))
-            .send()?
-            .json::<ShaderRoot>()?;
-
-        #[derive(Serialize, Deserialize, Debug)]
-        #[serde(deny_unknown_fields)]
-        struct ShaderRoot {
-            #[serde(default)]
-            #[serde(rename = "Error")]
-            error: String,
-
-            #[serde(rename = "Shader")]
-            shader: Shader,
+        let response = self.get(&shader_url(&self.base_url, &self.api_key, shader_id))?;
+        parse_shader(&response.body, shader_id)
+    }
+
+    /// Downloads a single asset file given its path on the site, such as `RenderPassInput::src`.
+    pub fn get_asset_file(&self, src: &str) -> Result<Asset> {
+        let url = format!("{}/{}", self.base_url, src.trim_start_matches('/'));
+        let response = self.get(&url)?;
+
+        Ok(Asset {
+            src: src.to_string(),
+            content_type: response.header("Content-Type").map(str::to_string),
+            data: response.body,
+        })
+    }
+
+    /// Downloads all asset files of a render pass input, see `RenderPassInput::asset_paths`.
+    /// This is a single file for textures and volumes, six files for cubemaps and none for
+    /// inputs without assets such as keyboard input.
+    pub fn get_asset(&self, input: &RenderPassInput) -> Result<Vec<Asset>> {
+        input
+            .asset_paths()
+            .iter()
+            .map(|src| self.get_asset_file(src))
+            .collect()
+    }
+
+    /// Downloads all asset files of multiple render pass inputs, files referenced by multiple inputs
+    /// are only downloaded once.
+    pub fn get_assets<'a, I>(&self, inputs: I) -> Result<Vec<Asset>>
+    where
+        I: IntoIterator<Item = &'a RenderPassInput>,
+    {
+        let mut paths: Vec<String> = vec![];
+        for input in inputs {
+            for path in input.asset_paths() {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
         }
 
-        if !json.error.is_empty() {
-            bail!("Shadertoy REST shader query returned error: {}", json.error);
+        paths.iter().map(|src| self.get_asset_file(src)).collect()
+    }
+
+    /// Issues a GET request through the transport and checks the HTTP status and size of the response.
+    /// Requests are rate limited and failed requests retried as configured in the `ClientBuilder`.
+    fn get(&self, url: &str) -> Result<Response> {
+        let mut retry = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.wait();
+            }
+
+            let result = self
+                .transport
+                .get(url)
+                .and_then(check_status)
+                .and_then(|response| check_size(url, response));
+
+            let err = match result {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+
+            match self.retry_policy.retry_delay(&err, retry) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(err),
+            }
+            retry += 1;
         }
-        Ok(json.shader)
     }
 }
+
+/// Returns the full URL of an API endpoint, e.g. `shaders/query/car`.
+fn api_url(base_url: &str, endpoint: &str) -> String {
+    format!("{}/api/v1/{}", base_url, endpoint)
+}
+
+/// Returns the full URL of a search query.
+pub(crate) fn search_url(base_url: &str, api_key: &str, params: &SearchParams<'_>) -> String {
+    format!(
+        "{}{}?sort={}&{}{}key={}",
+        api_url(base_url, "shaders"),
+        if params.string.is_empty() {
+            "".to_string()
+        } else {
+            format!("/query/{}", params.string)
+        },
+        format!("{:?}", params.sort_order).to_lowercase(),
+        params
+            .filters
+            .iter()
+            .map(|f| format!("filter={:?}&", f).to_lowercase())
+            .collect::<String>(),
+        match params.page_size {
+            Some(num) => format!("from={}&num={}&", params.offset, num),
+            None if params.offset > 0 => format!("from={}&", params.offset),
+            None => "".to_string(),
+        },
+        api_key
+    )
+}
+
+/// Returns the full URL of a shader query.
+pub(crate) fn shader_url(base_url: &str, api_key: &str, shader_id: &str) -> String {
+    format!(
+        "{}?key={}",
+        api_url(base_url, &format!("shaders/{}", shader_id)),
+        api_key
+    )
+}
+
+/// Parses the response of a shader query.
+pub(crate) fn parse_shader(body: &[u8], shader_id: &str) -> Result<Shader> {
+    #[derive(Serialize, Deserialize, Debug)]
+    struct ShaderRoot {
+        #[serde(default)]
+        #[serde(rename = "Error")]
+        error: String,
+
+        #[serde(default)]
+        #[serde(rename = "Shader")]
+        shader: Option<Shader>,
+    }
+
+    let json = parse_json::<ShaderRoot>(body)?;
+
+    if !json.error.is_empty() {
+        return Err(Error::from_api_message(&json.error));
+    }
+    json.shader
+        .ok_or_else(|| Error::Api(format!("no shader returned for {}", shader_id)))
+}
+
+/// Parses the response of a search query.
+pub(crate) fn parse_search_page(body: &[u8]) -> Result<SearchPage> {
+    #[derive(Serialize, Deserialize, Debug)]
+    struct SearchResult {
+        #[serde(default)]
+        #[serde(rename = "Error")]
+        error: String,
+
+        #[serde(default)]
+        #[serde(rename = "Shaders")]
+        shaders: u64,
+
+        #[serde(default)]
+        #[serde(rename = "Results")]
+        results: Vec<String>,
+    }
+
+    let r = parse_json::<SearchResult>(body)?;
+    if !r.error.is_empty() {
+        return Err(Error::from_api_message(&r.error));
+    }
+    Ok(SearchPage {
+        total: r.shaders,
+        ids: r.results,
+    })
+}
+
+/// Turns unsuccessful HTTP status codes into the matching `Error`.
+pub(crate) fn check_status(response: Response) -> Result<Response> {
+    let body = || String::from_utf8_lossy(&response.body).into_owned();
+
+    match response.status {
+        200..=299 => Ok(response),
+        401 | 403 => Err(Error::Auth(body())),
+        404 => Err(Error::NotFound(body())),
+        429 => Err(Error::RateLimited {
+            retry_after: response
+                .header("Retry-After")
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_secs),
+        }),
+        status => Err(Error::HttpStatus {
+            status,
+            body: body(),
+        }),
+    }
+}
+
+/// Verifies that the whole body was received, if the server reported a `Content-Length`.
+pub(crate) fn check_size(url: &str, response: Response) -> Result<Response> {
+    let expected = response
+        .header("Content-Length")
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    match expected {
+        Some(expected) if expected != response.body.len() as u64 => Err(Error::SizeMismatch {
+            url: url.to_string(),
+            expected,
+            actual: response.body.len() as u64,
+        }),
+        _ => Ok(response),
+    }
+}
+
+/// Deserializes a JSON response body, keeping track of the path to the offending field on failure.
+pub(crate) fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| Error::Deserialize {
+        path: err.path().to_string(),
+        source: err.into_inner(),
+    })
+}
@@ -1,8 +1,114 @@
-error_chain! {
-    foreign_links {
-        Fmt(::std::fmt::Error);
-        Io(::std::io::Error);
-        Json(::serde_json::error::Error);
-        Reqwest(::reqwest::Error);
+use std::fmt;
+use std::time::Duration;
+
+/// Errors that can occur when querying the Shadertoy API.
+#[derive(Debug)]
+pub enum Error {
+    /// The API key was rejected by the server.
+    Auth(String),
+    /// The requested shader doesn't exist or isn't available through the API.
+    NotFound(String),
+    /// The server is rate limiting requests, optionally with a time to wait before retrying.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// The server responded with an unexpected HTTP status code.
+    HttpStatus {
+        status: u16,
+        body: String,
+    },
+    /// The request could not be sent or the response could not be received.
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// The response JSON didn't match the expected schema, `path` is the offending field.
+    Deserialize {
+        path: String,
+        source: serde_json::Error,
+    },
+    /// The response body doesn't have the size reported by the server.
+    SizeMismatch {
+        url: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The API returned an error message not covered by any of the other variants.
+    Api(String),
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Message of the `Error` field of API responses to requests with an invalid API key.
+const INVALID_KEY_MESSAGE: &str = "Invalid key";
+
+impl Error {
+    /// Classify an error message returned in the `Error` field of an API response.
+    pub fn from_api_message(message: &str) -> Error {
+        if message.trim().eq_ignore_ascii_case(INVALID_KEY_MESSAGE) {
+            Error::Auth(message.to_string())
+        } else if message.to_lowercase().contains("not found") {
+            Error::NotFound(message.to_string())
+        } else {
+            Error::Api(message.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Auth(msg) => write!(f, "Shadertoy API key rejected: {}", msg),
+            Error::NotFound(msg) => write!(f, "Shadertoy resource not found: {}", msg),
+            Error::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Shadertoy API rate limited, retry after {} s",
+                retry_after.as_secs()
+            ),
+            Error::RateLimited { retry_after: None } => write!(f, "Shadertoy API rate limited"),
+            Error::HttpStatus { status, body } => {
+                write!(f, "Shadertoy API returned HTTP status {}: {}", status, body)
+            }
+            Error::Transport(err) => write!(f, "Shadertoy request failed: {}", err),
+            Error::Deserialize { path, source } => write!(
+                f,
+                "JSON parsing of Shadertoy response failed at `{}`: {}",
+                path, source
+            ),
+            Error::SizeMismatch {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Received {} bytes from {}, expected {} bytes",
+                actual, url, expected
+            ),
+            Error::Api(msg) => write!(f, "Shadertoy API returned error: {}", msg),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(err) => Some(err.as_ref()),
+            Error::Deserialize { source, .. } => Some(source),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        Error::Transport(Box::new(err))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
     }
 }
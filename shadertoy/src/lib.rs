@@ -3,13 +3,25 @@
 #![warn(clippy::all)]
 #![warn(rust_2018_idioms)]
 
-#[macro_use]
-extern crate error_chain;
-
+mod asset;
+#[cfg(feature = "async")]
+mod async_client;
 mod client;
+mod de;
 mod errors;
+#[cfg(feature = "mock-server")]
+mod mock_server;
+mod retry;
+mod transport;
 mod types;
 
+pub use asset::*;
+#[cfg(feature = "async")]
+pub use async_client::*;
 pub use client::*;
 pub use errors::*;
+#[cfg(feature = "mock-server")]
+pub use mock_server::MockServer;
+pub use retry::RetryPolicy;
+pub use transport::*;
 pub use types::*;
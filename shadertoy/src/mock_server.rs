@@ -0,0 +1,315 @@
+//! Minimal local stand-in for the Shadertoy API, serving shaders from a directory of JSON fixtures.
+//!
+//! This makes it possible to test against the client without network access, see `MockServer`.
+
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// HTTP server implementing the subset of the Shadertoy API used by `Client`.
+///
+/// Serves from a fixtures directory with the following layout:
+///
+/// - `shaders/<id>.json` - shader JSON, as returned in the `Shader` field of `Client::get_shader` queries.
+/// - `media/...` - asset files, served as is.
+///
+/// Requests with an API key other than the one the server was started with get an `Invalid key` error,
+/// and unknown shaders a `Shader not found` error, the same as the real API. Searches match the query
+/// string against shader names, tags and descriptions, search filters are ignored.
+///
+/// The server runs on a background thread until dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() {
+/// let server = shadertoy::MockServer::start("tests/fixtures", "mock").unwrap();
+/// let client = shadertoy::ClientBuilder::new("mock")
+///     .base_url(&server.url())
+///     .build()
+///     .unwrap();
+/// let shader = client.get_shader("4d2BDy");
+/// # }
+/// ```
+pub struct MockServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a server on a free local port.
+    pub fn start<P: AsRef<Path>>(fixtures_dir: P, api_key: &str) -> io::Result<MockServer> {
+        MockServer::bind("127.0.0.1:0", fixtures_dir, api_key)
+    }
+
+    /// Start a server on the given address.
+    pub fn bind<A: ToSocketAddrs, P: AsRef<Path>>(
+        addr: A,
+        fixtures_dir: P,
+        api_key: &str,
+    ) -> io::Result<MockServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let fixtures = Fixtures {
+            dir: fixtures_dir.as_ref().to_path_buf(),
+            api_key: api_key.to_string(),
+        };
+
+        let thread = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // errors only affect the single connection
+                        let _r_ = fixtures.handle_connection(stream);
+                    }
+                }
+            })
+        };
+
+        Ok(MockServer {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Base URL of the server, to be used with `ClientBuilder::base_url`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Blocks the current thread while the server keeps running.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _r_ = thread.join();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        // wake up the listener thread so it notices the shutdown
+        if let Ok(stream) = TcpStream::connect(self.addr) {
+            let _r_ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _r_ = thread.join();
+        }
+    }
+}
+
+struct Fixtures {
+    dir: PathBuf,
+    api_key: String,
+}
+
+struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    fn json(value: Value) -> MockResponse {
+        MockResponse {
+            status: 200,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn not_found() -> MockResponse {
+        MockResponse {
+            status: 404,
+            content_type: "text/plain",
+            body: b"Not Found".to_vec(),
+        }
+    }
+}
+
+impl Fixtures {
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // only the request line is of interest, the headers are skipped
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) => self.respond(target),
+            _ => MockResponse {
+                status: 405,
+                content_type: "text/plain",
+                body: b"Method Not Allowed".to_vec(),
+            },
+        };
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            if response.status == 200 {
+                "OK"
+            } else {
+                "Error"
+            },
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+
+    fn respond(&self, target: &str) -> MockResponse {
+        let (path, query) = match target.find('?') {
+            Some(index) => (&target[..index], &target[index + 1..]),
+            None => (target, ""),
+        };
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| percent_decode(value))
+        };
+
+        if path.starts_with("/media/") {
+            // nothing outside of the fixtures directory is served
+            if path.contains("..") {
+                return MockResponse::not_found();
+            }
+            return match std::fs::read(self.dir.join(path.trim_start_matches('/'))) {
+                Ok(data) => MockResponse {
+                    status: 200,
+                    content_type: "application/octet-stream",
+                    body: data,
+                },
+                Err(_) => MockResponse::not_found(),
+            };
+        }
+
+        let endpoint = match path.strip_prefix("/api/v1/shaders") {
+            Some(endpoint) => endpoint,
+            None => return MockResponse::not_found(),
+        };
+
+        if param("key").as_deref() != Some(self.api_key.as_str()) {
+            return MockResponse::json(serde_json::json!({ "Error": "Invalid key" }));
+        }
+
+        if endpoint.is_empty() || endpoint.starts_with("/query/") {
+            let string = percent_decode(endpoint.trim_start_matches("/query/")).to_lowercase();
+            let mut ids = self.search(&string, param("sort").as_deref() == Some("name"));
+
+            let total = ids.len();
+            let from = param("from").and_then(|v| v.parse().ok()).unwrap_or(0);
+            let num = param("num").and_then(|v| v.parse().ok()).unwrap_or(total);
+            ids = ids.into_iter().skip(from).take(num).collect();
+
+            return MockResponse::json(serde_json::json!({ "Shaders": total, "Results": ids }));
+        }
+
+        let shader_id = endpoint.trim_start_matches('/');
+        match self.load_shader(shader_id) {
+            Some(shader) => MockResponse::json(serde_json::json!({ "Shader": shader })),
+            None => MockResponse::json(serde_json::json!({ "Error": "Shader not found" })),
+        }
+    }
+
+    fn load_shader(&self, shader_id: &str) -> Option<Value> {
+        if shader_id.is_empty() || shader_id.contains(&['/', '.'][..]) {
+            return None;
+        }
+        let path = self.dir.join("shaders").join(format!("{}.json", shader_id));
+        let json_str = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&json_str).ok()
+    }
+
+    /// Ids of all shaders matching the (lowercase) search string, sorted by id or by name.
+    fn search(&self, string: &str, sort_by_name: bool) -> Vec<String> {
+        let entries = match std::fs::read_dir(self.dir.join("shaders")) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        let mut shaders: Vec<(String, String)> = entries
+            .filter_map(|entry| {
+                let file_name = entry.ok()?.file_name().to_string_lossy().into_owned();
+                let shader_id = file_name.strip_suffix(".json")?.to_string();
+                let info = self.load_shader(&shader_id)?.get("info")?.clone();
+
+                let text = |key: &str| info.get(key).and_then(Value::as_str).unwrap_or("");
+                let tags = info
+                    .get("tags")
+                    .and_then(Value::as_array)
+                    .map(|tags| {
+                        tags.iter()
+                            .filter_map(Value::as_str)
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .unwrap_or_default();
+
+                let searchable = format!("{} {} {}", text("name"), text("description"), tags);
+                if searchable.to_lowercase().contains(string) {
+                    Some((shader_id, text("name").to_string()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        if sort_by_name {
+            shaders.sort_by(|a, b| a.1.cmp(&b.1));
+        } else {
+            shaders.sort();
+        }
+        shaders.into_iter().map(|(id, _)| id).collect()
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,98 @@
+use crate::errors::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Policy for retrying failed requests, used by `Client`.
+///
+/// Requests are retried on transport errors, incomplete responses, 5xx server errors and when rate limited (HTTP 429).
+/// The delay between attempts doubles for every retry, unless the server sent a `Retry-After` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the initial attempt, 0 disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that never retries.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns how long to wait before retrying after `err`, or `None` if it shouldn't be retried.
+    /// `retry` is the number of retries done so far.
+    pub(crate) fn retry_delay(&self, err: &Error, retry: u32) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << retry.min(16))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        match err {
+            Error::RateLimited {
+                retry_after: Some(retry_after),
+            } => Some((*retry_after).min(self.max_backoff)),
+            Error::RateLimited { retry_after: None }
+            | Error::Transport(_)
+            | Error::SizeMismatch { .. } => Some(backoff),
+            Error::HttpStatus { status, .. } if *status >= 500 => Some(backoff),
+            _ => None,
+        }
+    }
+}
+
+/// Longest time between requests, lower rates would overflow `Instant`.
+const MAX_RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Spaces out requests to stay within a requests-per-second budget, shared between threads.
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: f64) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::try_from_secs_f64(1.0 / requests_per_second)
+                .map_or(MAX_RATE_LIMIT_INTERVAL, |interval| {
+                    interval.min(MAX_RATE_LIMIT_INTERVAL)
+                }),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the calling thread is allowed to issue a request.
+    pub(crate) fn wait(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot
+        };
+
+        if slot > now {
+            std::thread::sleep(slot - now);
+        }
+    }
+}
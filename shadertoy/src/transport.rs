@@ -0,0 +1,89 @@
+use crate::errors::*;
+use std::time::Duration;
+
+/// Raw HTTP response as returned by a `Transport`.
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    /// HTTP status code, e.g. 200.
+    pub status: u16,
+    /// Response headers as (name, value) pairs.
+    pub headers: Vec<(String, String)>,
+    /// Response body.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Create a successful response with the given body.
+    pub fn ok(body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    /// Look up a header value, header names are matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns true if the status code is in the 2xx range.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// HTTP transport used by `Client` to issue requests.
+///
+/// The default implementation is `ReqwestTransport`, but this can be replaced
+/// with a custom implementation, for example to serve canned responses in tests.
+pub trait Transport: Send + Sync {
+    /// Issue a GET request for the given absolute URL.
+    fn get(&self, url: &str) -> Result<Response>;
+}
+
+/// `Transport` implemented on top of a blocking `reqwest` client.
+pub struct ReqwestTransport {
+    client: reqwest::blocking::Client,
+}
+
+impl ReqwestTransport {
+    /// Create a new transport with an optional request timeout and the user agent to send.
+    pub fn new(timeout: Option<Duration>, user_agent: &str) -> Result<ReqwestTransport> {
+        let mut builder = reqwest::blocking::Client::builder().user_agent(user_agent);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(ReqwestTransport {
+            client: builder.build()?,
+        })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn get(&self, url: &str) -> Result<Response> {
+        let response = self.client.get(url).send()?;
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|v| (name.as_str().to_string(), v.to_string()))
+            })
+            .collect();
+        let body = response.bytes()?.to_vec();
+
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+}
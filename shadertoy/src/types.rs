@@ -1,36 +1,75 @@
 // These are the json types that the Shadertoy API outputs
 // This is manually derived
+//
+// Deserialization is intentionally tolerant: missing fields fall back to defaults and
+// unknown fields are kept in `extra`, so that JSON from both older and newer versions
+// of the API loads and can be written back out without losing anything.
 
-use serde::{Deserialize, Serialize};
+use crate::de::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Version of the shader JSON format these types were written against.
+pub const SHADER_VERSION: &str = "0.1";
+
+/// Unknown fields, retained so they are written back out when serializing.
+pub type ExtraFields = Map<String, Value>;
+
+fn default_shader_version() -> String {
+    SHADER_VERSION.to_string()
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
 pub struct Shader {
+    /// Version of the shader format, as reported by the API.
+    #[serde(default = "default_shader_version")]
     pub ver: String,
     pub info: ShaderInfo,
     pub renderpass: Vec<RenderPass>,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+impl Shader {
+    /// Returns true if `ver` matches the version these types were written against.
+    /// Shaders with other versions still load, but may be missing data.
+    pub fn is_known_version(&self) -> bool {
+        self.ver == SHADER_VERSION
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 #[serde(rename_all = "camelCase")]
 pub struct ShaderInfo {
     pub id: String,
     pub date: String,
+    #[serde(deserialize_with = "u64_from_any")]
     pub viewed: u64,
     pub name: String,
     pub username: String,
     pub description: String,
+    #[serde(deserialize_with = "u64_from_any")]
     pub likes: u64,
+    /// Publishing status: 0 = private, 1 = public, 2 = unlisted, 3 = public + API.
+    #[serde(deserialize_with = "u64_from_any")]
     pub published: u64,
+    #[serde(deserialize_with = "u64_from_any")]
     pub flags: u64,
     pub tags: Vec<String>,
-    pub hasliked: u64,
-    pub use_preview: u64,
+    #[serde(deserialize_with = "bool_from_any")]
+    pub hasliked: bool,
+    #[serde(deserialize_with = "bool_from_any")]
+    pub use_preview: bool,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct RenderPass {
     pub inputs: Vec<RenderPassInput>,
     pub outputs: Vec<RenderPassOutput>,
@@ -39,35 +78,157 @@ pub struct RenderPass {
     pub description: String,
 
     #[serde(rename = "type")]
-    pub pass_type: String,
+    pub pass_type: PassType,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct RenderPassInput {
+    #[serde(deserialize_with = "u64_from_any")]
     pub id: u64,
     pub src: String,
-    pub ctype: String,
+    pub ctype: ChannelType,
+    #[serde(deserialize_with = "u64_from_any")]
     pub channel: u64,
     pub sampler: Sampler,
-    pub published: u64,
+    #[serde(deserialize_with = "bool_from_any")]
+    pub published: bool,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct RenderPassOutput {
+    #[serde(deserialize_with = "u64_from_any")]
     pub id: u64,
+    #[serde(deserialize_with = "u64_from_any")]
     pub channel: u64,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct Sampler {
-    pub filter: String,
-    pub wrap: String,
-    pub vflip: String,
-    pub srgb: String,
-    //pub vflip: bool,
-    //pub srgb: bool,
+    pub filter: FilterMode,
+    pub wrap: WrapMode,
+    #[serde(deserialize_with = "bool_from_any")]
+    pub vflip: bool,
+    #[serde(deserialize_with = "bool_from_any")]
+    pub srgb: bool,
     pub internal: String,
+
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Enums for the string values used by the API. Values not known by this version
+// of the crate are kept in `Unknown` instead of failing deserialization.
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident => $value:expr,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+            Unknown(String),
+        }
+
+        impl $name {
+            /// Returns the string used for this value in the API.
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> $name {
+                match value {
+                    $($value => $name::$variant,)*
+                    _ => $name::Unknown(value.to_string()),
+                }
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> $name {
+                $name::Unknown(String::new())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                Ok($name::from(String::deserialize(deserializer)?.as_str()))
+            }
+        }
+    };
+}
+
+string_enum! {
+    /// Type of a render pass input channel (`RenderPassInput::ctype`).
+    pub enum ChannelType {
+        Texture => "texture",
+        Volume => "volume",
+        Cubemap => "cubemap",
+        Buffer => "buffer",
+        Video => "video",
+        Webcam => "webcam",
+        Keyboard => "keyboard",
+        Music => "music",
+        MusicStream => "musicstream",
+        Mic => "mic",
+    }
+}
+
+string_enum! {
+    /// Type of a render pass (`RenderPass::pass_type`).
+    pub enum PassType {
+        Image => "image",
+        Buffer => "buffer",
+        Common => "common",
+        Sound => "sound",
+        Cubemap => "cubemap",
+    }
+}
+
+string_enum! {
+    /// Texture filtering mode of an input channel sampler.
+    pub enum FilterMode {
+        Nearest => "nearest",
+        Linear => "linear",
+        Mipmap => "mipmap",
+    }
+}
+
+string_enum! {
+    /// Texture wrap mode of an input channel sampler.
+    pub enum WrapMode {
+        Clamp => "clamp",
+        Repeat => "repeat",
+    }
 }
@@ -0,0 +1,121 @@
+extern crate shadertoy;
+
+use shadertoy::{AsyncTransport, BoxFuture, ClientBuilder, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+const SHADER_JSON: &str = include_str!("fixtures/shaders/4d2BDy.json");
+
+/// Future that returns `Pending` once, so concurrent requests get to interleave.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Async transport serving canned responses, keeping track of the peak number of requests in flight.
+#[derive(Clone, Default)]
+struct MockAsyncTransport {
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+impl AsyncTransport for MockAsyncTransport {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, shadertoy::Result<Response>> {
+        Box::pin(async move {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            YieldOnce(false).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let path = url.split('?').next().unwrap();
+            let body = if path.ends_with("/shaders/4d2BDy") {
+                format!("{{\"Shader\": {}}}", SHADER_JSON)
+            } else if path.contains("/shaders/query/") {
+                "{\"Shaders\": 1, \"Results\": [\"4d2BDy\"]}".to_string()
+            } else {
+                "{\"Error\": \"Shader not found\"}".to_string()
+            };
+            Ok(Response::ok(body))
+        })
+    }
+}
+
+#[test]
+fn test_async_search_and_get_shader() {
+    let client = ClientBuilder::new("mock")
+        .base_url("http://localhost:1234")
+        .async_transport(MockAsyncTransport::default())
+        .build_async()
+        .unwrap();
+
+    futures::executor::block_on(async {
+        let query = client
+            .search(&shadertoy::SearchParams {
+                string: "car",
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(query, vec!["4d2BDy".to_string()]);
+
+        let shader = client.get_shader(&query[0]).await.unwrap();
+        assert_eq!(shader.info.id, "4d2BDy");
+
+        match client.get_shader("doesnt_exist").await {
+            Err(shadertoy::Error::NotFound(_)) => (),
+            other => panic!("expected NotFound, got {:?}", other.map(|_| ())),
+        }
+    });
+}
+
+#[test]
+fn test_async_bounded_concurrency() {
+    let transport = MockAsyncTransport::default();
+    let client = ClientBuilder::new("mock")
+        .base_url("http://localhost:1234")
+        .async_transport(transport.clone())
+        .max_concurrent_requests(3)
+        .build_async()
+        .unwrap();
+
+    // all calls are polled at once, only the request slots of the client limit them
+    let shaders = futures::executor::block_on(futures::future::join_all(
+        (0..10).map(|_| client.get_shader("4d2BDy")),
+    ));
+
+    assert_eq!(shaders.len(), 10);
+    assert!(shaders.iter().all(|shader| shader.is_ok()));
+    assert_eq!(transport.max_in_flight.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_async_get_shaders() {
+    let client = ClientBuilder::new("mock")
+        .base_url("http://localhost:1234")
+        .async_transport(MockAsyncTransport::default())
+        .max_concurrent_requests(2)
+        .build_async()
+        .unwrap();
+
+    let ids = ["4d2BDy", "doesnt_exist", "4d2BDy"];
+    let shaders = futures::executor::block_on(client.get_shaders(&ids));
+
+    assert_eq!(shaders.len(), 3);
+    assert_eq!(shaders[0].as_ref().unwrap().info.id, "4d2BDy");
+    assert!(matches!(shaders[1], Err(shadertoy::Error::NotFound(_))));
+    assert_eq!(shaders[2].as_ref().unwrap().info.id, "4d2BDy");
+}
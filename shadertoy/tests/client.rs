@@ -0,0 +1,394 @@
+extern crate shadertoy;
+
+use shadertoy::{ClientBuilder, Response, RetryPolicy, Transport};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SHADER_JSON: &str = include_str!("fixtures/shaders/4d2BDy.json");
+
+/// Transport serving canned responses, recording every requested URL.
+#[derive(Clone)]
+struct MockTransport {
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockTransport {
+    fn new() -> MockTransport {
+        MockTransport {
+            requests: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn get(&self, url: &str) -> shadertoy::Result<Response> {
+        self.requests.lock().unwrap().push(url.to_string());
+
+        let path = url.split('?').next().unwrap();
+        let response = if url.ends_with("key=badkey") {
+            Response::ok("{\"Error\": \"Invalid key\"}")
+        } else if path.ends_with("/shaders/4d2BDy") {
+            Response::ok(format!("{{\"Shader\": {}}}", SHADER_JSON))
+        } else if path.ends_with("/shaders/broken") {
+            Response::ok("{\"Shader\": {\"ver\": \"0.1\", \"info\": {\"id\": 42}}}")
+        } else if path.ends_with("/shaders/ratelimited") {
+            Response {
+                status: 429,
+                headers: vec![("retry-after".to_string(), "3".to_string())],
+                body: vec![],
+            }
+        } else if path.ends_with("/shaders/servererror") {
+            Response {
+                status: 503,
+                ..Response::default()
+            }
+        } else if path.ends_with("/shaders/query/many") {
+            // 250 results, paginated with the `from` & `num` query parameters
+            let param = |name: &str| {
+                url.split(&['?', '&'][..])
+                    .find_map(|p| p.strip_prefix(&format!("{}=", name)))
+                    .map(|v| v.parse::<usize>().unwrap())
+            };
+            let from = param("from").unwrap_or(0);
+            let num = param("num").unwrap_or(250);
+            let ids = (from..(from + num).min(250))
+                .map(|i| format!("\"id{}\"", i))
+                .collect::<Vec<_>>();
+            Response::ok(format!(
+                "{{\"Shaders\": 250, \"Results\": [{}]}}",
+                ids.join(",")
+            ))
+        } else if path.ends_with("/media/a/truncated.png") {
+            Response {
+                status: 200,
+                headers: vec![("Content-Length".to_string(), "1024".to_string())],
+                body: vec![0; 512],
+            }
+        } else if path.contains("/media/") {
+            // asset files contain their own path
+            Response {
+                status: 200,
+                headers: vec![
+                    ("Content-Type".to_string(), "image/png".to_string()),
+                    ("Content-Length".to_string(), path.len().to_string()),
+                ],
+                body: path.as_bytes().to_vec(),
+            }
+        } else if path.contains("/shaders/query/") || path.ends_with("/shaders") {
+            Response::ok("{\"Shaders\": 1, \"Results\": [\"4d2BDy\"]}")
+        } else {
+            Response::ok("{\"Error\": \"Shader not found\"}")
+        };
+        Ok(response)
+    }
+}
+
+/// Client of a `MockTransport`, with a trailing slash in the base URL that requests shouldn't have.
+fn mock_client(api_key: &str) -> (shadertoy::Client, MockTransport) {
+    let transport = MockTransport::new();
+    let client = ClientBuilder::new(api_key)
+        .base_url("http://localhost:1234/")
+        .transport(transport.clone())
+        .retry_policy(RetryPolicy::never())
+        .build()
+        .unwrap();
+    (client, transport)
+}
+
+#[test]
+fn test_mock_search() {
+    let (client, transport) = mock_client("mock");
+
+    let query = client
+        .search(&shadertoy::SearchParams {
+            string: "car",
+            sort_order: shadertoy::SearchSortOrder::Popular,
+            filters: vec![shadertoy::SearchFilter::MultiPass],
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(query, vec!["4d2BDy".to_string()]);
+    assert_eq!(
+        transport.requests(),
+        vec![
+            "http://localhost:1234/api/v1/shaders/query/car?sort=popular&filter=multipass&key=mock"
+                .to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_mock_get_shader() {
+    let (client, transport) = mock_client("mock");
+
+    let shader = client.get_shader("4d2BDy").unwrap();
+    assert_eq!(shader.info.id, "4d2BDy");
+    assert_eq!(shader.renderpass.len(), 1);
+
+    assert!(client.get_shader("doesnt_exist").is_err());
+
+    assert_eq!(
+        transport.requests(),
+        vec![
+            "http://localhost:1234/api/v1/shaders/4d2BDy?key=mock".to_string(),
+            "http://localhost:1234/api/v1/shaders/doesnt_exist?key=mock".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_mock_errors() {
+    let (client, _) = mock_client("mock");
+
+    match client.get_shader("doesnt_exist") {
+        Err(shadertoy::Error::NotFound(_)) => (),
+        other => panic!("expected NotFound, got {:?}", other.map(|_| ())),
+    }
+
+    match client.get_shader("ratelimited") {
+        Err(shadertoy::Error::RateLimited { retry_after }) => {
+            assert_eq!(retry_after, Some(std::time::Duration::from_secs(3)))
+        }
+        other => panic!("expected RateLimited, got {:?}", other.map(|_| ())),
+    }
+
+    match client.get_shader("servererror") {
+        Err(shadertoy::Error::HttpStatus { status, .. }) => assert_eq!(status, 503),
+        other => panic!("expected HttpStatus, got {:?}", other.map(|_| ())),
+    }
+
+    match client.get_shader("broken") {
+        Err(shadertoy::Error::Deserialize { path, .. }) => assert_eq!(path, "Shader.info.id"),
+        other => panic!("expected Deserialize, got {:?}", other.map(|_| ())),
+    }
+
+    match mock_client("badkey").0.get_shader("4d2BDy") {
+        Err(shadertoy::Error::Auth(_)) => (),
+        other => panic!("expected Auth, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_api_error_messages() {
+    use shadertoy::Error;
+
+    assert!(matches!(
+        Error::from_api_message("Invalid key"),
+        Error::Auth(_)
+    ));
+    assert!(matches!(
+        Error::from_api_message("Shader not found"),
+        Error::NotFound(_)
+    ));
+    // only the invalid key message is an authentication error, not every mention of a key
+    assert!(matches!(
+        Error::from_api_message("Unknown key `sort`"),
+        Error::Api(_)
+    ));
+    assert!(matches!(
+        Error::from_api_message("Server busy"),
+        Error::Api(_)
+    ));
+}
+
+#[test]
+fn test_mock_search_pages() {
+    let (client, transport) = mock_client("mock");
+
+    let params = shadertoy::SearchParams {
+        string: "many",
+        page_size: Some(100),
+        ..Default::default()
+    };
+
+    // pages are only requested as the iterator advances
+    let mut pages = client.search_pages(&params);
+    assert_eq!(pages.total(), None);
+    let first: Vec<String> = pages
+        .by_ref()
+        .take(5)
+        .collect::<shadertoy::Result<_>>()
+        .unwrap();
+    assert_eq!(first, vec!["id0", "id1", "id2", "id3", "id4"]);
+    assert_eq!(pages.total(), Some(250));
+    assert_eq!(transport.requests().len(), 1);
+
+    let all: Vec<String> = client
+        .search_pages(&params)
+        .collect::<shadertoy::Result<_>>()
+        .unwrap();
+    assert_eq!(all.len(), 250);
+    assert_eq!(all[249], "id249");
+    assert_eq!(transport.requests().len(), 4);
+    assert!(transport.requests()[3].contains("from=200&num=100"));
+
+    let page = client
+        .search_page(&shadertoy::SearchParams {
+            offset: 240,
+            ..params
+        })
+        .unwrap();
+    assert_eq!(page.total, 250);
+    assert_eq!(page.ids.len(), 10);
+}
+
+/// Transport that fails with the given status a number of times before succeeding.
+struct FlakyTransport {
+    failures: usize,
+    status: u16,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Transport for FlakyTransport {
+    fn get(&self, _url: &str) -> shadertoy::Result<Response> {
+        if self.requests.fetch_add(1, Ordering::SeqCst) < self.failures {
+            Ok(Response {
+                status: self.status,
+                ..Response::default()
+            })
+        } else {
+            Ok(Response::ok(format!("{{\"Shader\": {}}}", SHADER_JSON)))
+        }
+    }
+}
+
+fn flaky_client(
+    failures: usize,
+    status: u16,
+    max_retries: u32,
+) -> (shadertoy::Client, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let client = ClientBuilder::new("mock")
+        .transport(FlakyTransport {
+            failures,
+            status,
+            requests: requests.clone(),
+        })
+        .retry_policy(RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        })
+        .build()
+        .unwrap();
+    (client, requests)
+}
+
+#[test]
+fn test_retry() {
+    // transient server errors and rate limiting are retried
+    let (client, requests) = flaky_client(2, 503, 3);
+    assert!(client.get_shader("4d2BDy").is_ok());
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    let (client, requests) = flaky_client(1, 429, 3);
+    assert!(client.get_shader("4d2BDy").is_ok());
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // until the retries run out
+    let (client, requests) = flaky_client(5, 500, 2);
+    match client.get_shader("4d2BDy") {
+        Err(shadertoy::Error::HttpStatus { status, .. }) => assert_eq!(status, 500),
+        other => panic!("expected HttpStatus, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // client errors are not retried
+    let (client, requests) = flaky_client(1, 403, 3);
+    assert!(client.get_shader("4d2BDy").is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_rate_limit() {
+    let client = ClientBuilder::new("mock")
+        .transport(MockTransport::new())
+        .requests_per_second(50.0)
+        .build()
+        .unwrap();
+
+    let time = Instant::now();
+    for _ in 0..6 {
+        client.get_shader("4d2BDy").unwrap();
+    }
+
+    // the first request is issued immediately, the following ones 20 ms apart
+    assert!(time.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn test_tiny_rate_limit() {
+    // the interval between requests doesn't fit in a `Duration`
+    let client = ClientBuilder::new("mock")
+        .transport(MockTransport::new())
+        .requests_per_second(1e-20)
+        .build()
+        .unwrap();
+
+    let time = Instant::now();
+    client.get_shader("4d2BDy").unwrap();
+    assert!(time.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_get_asset() {
+    let (client, _) = mock_client("mock");
+
+    let texture = shadertoy::RenderPassInput {
+        src: "/media/a/texture.png".to_string(),
+        ctype: shadertoy::ChannelType::Texture,
+        ..Default::default()
+    };
+    let assets = client.get_asset(&texture).unwrap();
+    assert_eq!(assets.len(), 1);
+    assert_eq!(assets[0].src, "/media/a/texture.png");
+    assert_eq!(assets[0].content_type.as_deref(), Some("image/png"));
+    assert_eq!(
+        assets[0].data,
+        b"http://localhost:1234/media/a/texture.png".to_vec()
+    );
+
+    // cubemaps consist of six faces
+    let cubemap = shadertoy::RenderPassInput {
+        src: "/media/a/cube.png".to_string(),
+        ctype: shadertoy::ChannelType::Cubemap,
+        ..Default::default()
+    };
+    assert_eq!(
+        cubemap.asset_paths(),
+        vec![
+            "/media/a/cube.png",
+            "/media/a/cube_1.png",
+            "/media/a/cube_2.png",
+            "/media/a/cube_3.png",
+            "/media/a/cube_4.png",
+            "/media/a/cube_5.png",
+        ]
+    );
+
+    // inputs without assets
+    let keyboard = shadertoy::RenderPassInput {
+        ctype: shadertoy::ChannelType::Keyboard,
+        ..Default::default()
+    };
+    assert!(client.get_asset(&keyboard).unwrap().is_empty());
+
+    // shared files are only downloaded once
+    let assets = client
+        .get_assets(vec![&texture, &cubemap, &keyboard, &texture])
+        .unwrap();
+    assert_eq!(assets.len(), 7);
+
+    match client.get_asset_file("/media/a/truncated.png") {
+        Err(shadertoy::Error::SizeMismatch {
+            expected, actual, ..
+        }) => assert_eq!((expected, actual), (1024, 512)),
+        other => panic!("expected SizeMismatch, got {:?}", other.map(|_| ())),
+    }
+}
@@ -1,16 +1,17 @@
 extern crate shadertoy;
 
-#[test]
-fn test_query() {
-    let client = shadertoy::Client::new("Bd8tWD");
+use shadertoy::MockServer;
+
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
 
-    // try connecting to shadertoy.com and search for all "car" shadertoys
-    // this of course requires one to be online
+fn check_query(client: &shadertoy::Client) {
+    // search for all "car" shadertoys
     let query = client
         .search(&shadertoy::SearchParams {
             string: "car",
             sort_order: shadertoy::SearchSortOrder::Popular,
             filters: vec![shadertoy::SearchFilter::MultiPass],
+            ..Default::default()
         })
         .unwrap();
 
@@ -29,12 +30,104 @@ fn test_query() {
     assert!(shader.is_err());
 }
 
-#[test]
-fn test_invalid_api_key() {
-    let client = shadertoy::Client::new("incorrect");
-
+fn check_invalid_api_key(client: &shadertoy::Client) {
     // try getting a specific shader, this should fail due to the API key
     let shader = client.get_shader("4d2BDy");
     assert!(shader.is_err());
     println!("error: {}", shader.err().unwrap());
 }
+
+#[test]
+fn test_query() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let client = shadertoy::ClientBuilder::new("mock")
+        .base_url(&server.url())
+        .build()
+        .unwrap();
+
+    check_query(&client);
+
+    // searches match names, descriptions and tags
+    let query = client
+        .search(&shadertoy::SearchParams {
+            string: "texture",
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(query, vec!["MdX3Rr".to_string()]);
+
+    // paging through all shaders
+    let all = client
+        .search_pages(&shadertoy::SearchParams {
+            page_size: Some(1),
+            ..Default::default()
+        })
+        .collect::<shadertoy::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(all, vec!["4d2BDy".to_string(), "MdX3Rr".to_string()]);
+
+    // assets are served from the fixtures as well
+    let shader = client.get_shader("MdX3Rr").unwrap();
+    let assets = client
+        .get_assets(
+            shader
+                .renderpass
+                .iter()
+                .flat_map(|pass| &pass.inputs)
+                .filter(|input| input.ctype == shadertoy::ChannelType::Texture),
+        )
+        .unwrap();
+    assert_eq!(assets.len(), 1);
+    assert_eq!(assets[0].data, b"checker".to_vec());
+    assert!(client.get_asset_file("/media/a/missing.png").is_err());
+}
+
+#[test]
+fn test_invalid_api_key() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let client = shadertoy::ClientBuilder::new("incorrect")
+        .base_url(&server.url())
+        .build()
+        .unwrap();
+
+    check_invalid_api_key(&client);
+
+    match client.get_shader("4d2BDy") {
+        Err(shadertoy::Error::Auth(_)) => (),
+        other => panic!("expected Auth, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_mock_server_path_traversal() {
+    use std::io::{Read, Write};
+
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+
+    // sent as is, HTTP clients resolve `..` in URLs before requesting them
+    let get = |target: &str| {
+        let addr = server.url().trim_start_matches("http://").to_string();
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    assert!(get("/media/a/checker.png").starts_with("HTTP/1.1 200"));
+    // only files in the media directory are served
+    assert!(get("/media/../shaders/4d2BDy.json").starts_with("HTTP/1.1 404"));
+}
+
+// try connecting to shadertoy.com, this of course requires one to be online
+#[test]
+#[ignore = "requires network access to shadertoy.com"]
+fn test_query_online() {
+    check_query(&shadertoy::Client::new("Bd8tWD"));
+}
+
+#[test]
+#[ignore = "requires network access to shadertoy.com"]
+fn test_invalid_api_key_online() {
+    check_invalid_api_key(&shadertoy::Client::new("incorrect"));
+}
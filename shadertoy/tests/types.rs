@@ -0,0 +1,90 @@
+extern crate shadertoy;
+
+const SHADER_JSON: &str = include_str!("fixtures/shaders/4d2BDy.json");
+
+#[test]
+fn test_load_fixture() {
+    let shader: shadertoy::Shader = serde_json::from_str(SHADER_JSON).unwrap();
+    assert!(shader.is_known_version());
+    assert_eq!(shader.info.name, "Rusty Car");
+    assert!(!shader.info.hasliked);
+    assert!(shader.extra.is_empty());
+}
+
+#[test]
+fn test_tolerant_fields() {
+    // newer API version with an additional field, string encoded booleans & numbers
+    // and a missing `description` field
+    let json = r#"{
+        "ver": "0.2",
+        "info": { "id": "abc", "name": "test", "viewed": "12", "hasliked": 1, "newField": [1, 2] },
+        "renderpass": [{
+            "inputs": [{
+                "id": 17,
+                "src": "/media/a/test.png",
+                "ctype": "texture",
+                "channel": 0,
+                "sampler": { "filter": "mipmap", "wrap": "repeat", "vflip": "true", "srgb": "false", "internal": "byte" },
+                "published": 1
+            }],
+            "outputs": [],
+            "code": "",
+            "name": "Image",
+            "type": "image"
+        }]
+    }"#;
+
+    let shader: shadertoy::Shader = serde_json::from_str(json).unwrap();
+    assert!(!shader.is_known_version());
+    assert_eq!(shader.info.viewed, 12);
+    assert!(shader.info.hasliked);
+    assert_eq!(shader.info.description, "");
+
+    assert_eq!(shader.renderpass[0].pass_type, shadertoy::PassType::Image);
+
+    let input = &shader.renderpass[0].inputs[0];
+    assert_eq!(input.ctype, shadertoy::ChannelType::Texture);
+    assert_eq!(input.sampler.filter, shadertoy::FilterMode::Mipmap);
+    assert_eq!(input.sampler.wrap, shadertoy::WrapMode::Repeat);
+    assert!(input.published);
+    assert!(input.sampler.vflip);
+    assert!(!input.sampler.srgb);
+
+    // unknown fields survive a round-trip
+    let round_tripped: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string(&shader).unwrap()).unwrap();
+    assert_eq!(round_tripped["ver"], "0.2");
+    assert_eq!(round_tripped["info"]["newField"], serde_json::json!([1, 2]));
+}
+
+#[test]
+fn test_missing_version() {
+    let json = r#"{ "info": { "id": "abc" }, "renderpass": [] }"#;
+    let shader: shadertoy::Shader = serde_json::from_str(json).unwrap();
+    assert_eq!(shader.ver, shadertoy::SHADER_VERSION);
+}
+
+#[test]
+fn test_unknown_enum_values() {
+    let json = r#"{ "inputs": [{ "ctype": "hologram", "sampler": { "wrap": "mirror" } }], "type": "geometry" }"#;
+    let pass: shadertoy::RenderPass = serde_json::from_str(json).unwrap();
+
+    assert_eq!(
+        pass.pass_type,
+        shadertoy::PassType::Unknown("geometry".to_string())
+    );
+    assert_eq!(
+        pass.inputs[0].ctype,
+        shadertoy::ChannelType::Unknown("hologram".to_string())
+    );
+    assert_eq!(
+        pass.inputs[0].sampler.wrap,
+        shadertoy::WrapMode::Unknown("mirror".to_string())
+    );
+
+    // unknown values are written back out unchanged
+    let round_tripped: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string(&pass).unwrap()).unwrap();
+    assert_eq!(round_tripped["type"], "geometry");
+    assert_eq!(round_tripped["inputs"][0]["ctype"], "hologram");
+}
@@ -0,0 +1,299 @@
+//! Single-file archives of downloaded shadertoys, to be able to move a corpus to machines without network access.
+//!
+//! An archive is a tar file containing a `manifest.json` followed by the files from the output directory:
+//! shader JSON in `shader/<id>/<id>.json`, the assets they reference in `media/...` and cached search
+//! results in `query/...`. Importing unpacks these into another output directory, after which the
+//! browser finds everything in its cache and doesn't need to issue any requests.
+
+use crate::cache::{self, list_dir, shader_path, CacheSection};
+use crate::errors::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_PATH: &str = "manifest.json";
+const ARCHIVE_FORMAT: &str = "shadertoy-browser-archive";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    /// Creation time, in RFC 3339 format.
+    pub created: String,
+    pub shaders: Vec<String>,
+    pub assets: Vec<String>,
+    pub queries: Vec<String>,
+}
+
+impl Manifest {
+    /// Collects the shaders, assets and queries to archive from an output directory.
+    fn from_output_dir(root: &Path) -> Result<Manifest> {
+        let mut manifest = Manifest {
+            format: ARCHIVE_FORMAT.to_string(),
+            version: ARCHIVE_VERSION,
+            created: chrono::Local::now().to_rfc3339(),
+            shaders: vec![],
+            assets: vec![],
+            queries: vec![],
+        };
+
+        for shader_id in cache::downloaded_shadertoys(root)? {
+            let shader = cache::load_shader(root, &shader_id)?;
+
+            // only include assets that have actually been downloaded
+            for pass in &shader.renderpass {
+                for input in &pass.inputs {
+                    for src in input.asset_paths() {
+                        let asset = src.trim_start_matches('/').to_string();
+                        if root.join(&asset).is_file() && !manifest.assets.contains(&asset) {
+                            manifest.assets.push(asset);
+                        }
+                    }
+                }
+            }
+
+            manifest.shaders.push(shader_id);
+        }
+
+        manifest.queries = list_dir(&CacheSection::Queries.path(root))?;
+
+        Ok(manifest)
+    }
+
+    /// Paths of all the files in the archive, relative to the output directory.
+    fn files(&self) -> Vec<String> {
+        self.shaders
+            .iter()
+            .map(|id| shader_path(id))
+            .chain(self.assets.iter().cloned())
+            .chain(self.queries.iter().map(|query| format!("query/{}", query)))
+            .collect()
+    }
+}
+
+/// Writes all downloaded shaders in the `root` output directory, their assets and the
+/// cached search queries to an archive file.
+pub fn export(root: &Path, archive_path: &Path) -> Result<Manifest> {
+    profile_scope!("archive_export");
+
+    let manifest = Manifest::from_output_dir(root)?;
+
+    let file = File::create(archive_path)
+        .chain_err(|| format!("failed creating archive {}", archive_path.display()))?;
+    let mut builder = tar::Builder::new(file);
+
+    // the manifest goes first so it can be validated before unpacking anything else
+    let manifest_json = serde_json::to_string_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Local::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_PATH, manifest_json.as_bytes())?;
+
+    for path in manifest.files() {
+        builder
+            .append_path_with_name(root.join(&path), &path)
+            .chain_err(|| format!("failed adding {} to archive", path))?;
+    }
+
+    builder.into_inner()?.flush()?;
+
+    Ok(manifest)
+}
+
+/// Unpacks an archive created with `export` into the `root` output directory,
+/// overwriting existing files with the same name.
+pub fn import(root: &Path, archive_path: &Path) -> Result<Manifest> {
+    profile_scope!("archive_import");
+
+    let file = File::open(archive_path)
+        .chain_err(|| format!("failed opening archive {}", archive_path.display()))?;
+    let mut archive = tar::Archive::new(file);
+    let mut entries = archive.entries()?;
+
+    let manifest: Manifest = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()? != Path::new(MANIFEST_PATH) {
+                bail!("{} is not a shadertoy archive", archive_path.display());
+            }
+            let mut json_str = String::new();
+            entry.read_to_string(&mut json_str)?;
+            serde_json::from_str(&json_str).chain_err(|| "failed parsing archive manifest")?
+        }
+        None => bail!("{} is empty", archive_path.display()),
+    };
+
+    if manifest.format != ARCHIVE_FORMAT || manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "unsupported archive format {} version {}",
+            manifest.format,
+            manifest.version
+        );
+    }
+
+    std::fs::create_dir_all(root)?;
+
+    // only the files listed in the manifest are unpacked, anything else could be e.g. a
+    // pipeline failure or a file outside of the cache sections
+    let files: HashSet<PathBuf> = manifest.files().into_iter().map(PathBuf::from).collect();
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = PathBuf::from(entry.path()?);
+        if !files.contains(&path) {
+            bail!("archive entry {} is not in the manifest", path.display());
+        }
+
+        // `unpack_in` refuses to write outside of the output directory
+        if !entry.unpack_in(root)? {
+            bail!("archive entry {} has an invalid path", path.display());
+        }
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    const SHADER_JSON: &str = r#"{
+        "info": { "id": "abc" },
+        "renderpass": [{
+            "inputs": [
+                { "src": "/media/a/texture.png", "ctype": "texture" },
+                { "src": "/media/a/missing.png", "ctype": "texture" }
+            ],
+            "type": "image"
+        }]
+    }"#;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    /// Writes a tar file with entries named as given, without the checks of `tar::Builder`.
+    fn write_tar(path: &Path, entries: &[(&str, &str)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap();
+    }
+
+    fn manifest_json(version: u32) -> String {
+        serde_json::to_string(&Manifest {
+            format: ARCHIVE_FORMAT.to_string(),
+            version,
+            created: String::new(),
+            shaders: vec![],
+            assets: vec![],
+            queries: vec![],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn export_import() {
+        let dir = test_dir("archive-export-import");
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        write(&src.join("shader/abc/abc.json"), SHADER_JSON);
+        write(&src.join("shader/abc/abcImage.glsl"), "void mainImage() {}");
+        write(&src.join("media/a/texture.png"), "png");
+        write(&src.join("media/a/unused.png"), "png");
+        write(&src.join("query/search"), r#"["abc"]"#);
+
+        let archive_path = dir.join("archive.tar");
+        let manifest = export(&src, &archive_path).unwrap();
+        assert_eq!(manifest.shaders, vec!["abc"]);
+        assert_eq!(manifest.assets, vec!["media/a/texture.png"]);
+        assert_eq!(manifest.queries, vec!["search"]);
+
+        let manifest = import(&dst, &archive_path).unwrap();
+        assert_eq!(manifest.shaders, vec!["abc"]);
+        assert!(dst.join("shader/abc/abc.json").is_file());
+        assert!(dst.join("media/a/texture.png").is_file());
+        assert!(dst.join("query/search").is_file());
+        // only downloads are archived, not build output or unused assets
+        assert!(!dst.join("shader/abc/abcImage.glsl").exists());
+        assert!(!dst.join("media/a/unused.png").exists());
+    }
+
+    #[test]
+    fn import_rejects_path_traversal() {
+        let dir = test_dir("archive-path-traversal");
+        let archive_path = dir.join("archive.tar");
+        write_tar(
+            &archive_path,
+            &[
+                (MANIFEST_PATH, &manifest_json(ARCHIVE_VERSION)),
+                ("../escaped.txt", "outside"),
+            ],
+        );
+
+        assert!(import(&dir.join("dst"), &archive_path).is_err());
+        assert!(!dir.join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn import_rejects_unlisted_files() {
+        let dir = test_dir("archive-unlisted");
+        let archive_path = dir.join("archive.tar");
+        let manifest_json = serde_json::to_string(&Manifest {
+            format: ARCHIVE_FORMAT.to_string(),
+            version: ARCHIVE_VERSION,
+            created: String::new(),
+            shaders: vec!["abc".to_string()],
+            assets: vec![],
+            queries: vec![],
+        })
+        .unwrap();
+        write_tar(
+            &archive_path,
+            &[
+                (MANIFEST_PATH, &manifest_json),
+                ("shader/abc/abc.json", SHADER_JSON),
+                ("pipeline_fail/1/hash", "error"),
+            ],
+        );
+
+        let dst = dir.join("dst");
+        let err = import(&dst, &archive_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "archive entry pipeline_fail/1/hash is not in the manifest"
+        );
+        assert!(dst.join("shader/abc/abc.json").is_file());
+        assert!(!dst.join("pipeline_fail").exists());
+    }
+
+    #[test]
+    fn import_rejects_other_archives() {
+        let dir = test_dir("archive-other");
+        let archive_path = dir.join("archive.tar");
+
+        write_tar(&archive_path, &[("shader/abc/abc.json", SHADER_JSON)]);
+        assert!(import(&dir.join("dst"), &archive_path).is_err());
+
+        write_tar(
+            &archive_path,
+            &[(MANIFEST_PATH, &manifest_json(ARCHIVE_VERSION + 1))],
+        );
+        assert!(import(&dir.join("dst"), &archive_path).is_err());
+
+        write_tar(&archive_path, &[]);
+        assert!(import(&dir.join("dst"), &archive_path).is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//! The on-disk cache of downloaded shadertoys, also called the output directory.
+//!
+//! It defaults to `shadertoy-browser` in the user's cache directory, e.g. `~/.cache/shadertoy-browser`,
+//! and can be set with `--cache-dir` or the `SHADERTOY_CACHE_DIR` environment variable. All paths
+//! within it are constructed in this module, see `CachePaths`. Layout:
+//!
+//! - `query/<hash>` - ids returned by a search, keyed on the search parameters.
+//! - `shader/<id>/<id>.json` - shader JSON, next to the generated GLSL for each pass.
+//! - `media/...` - assets referenced by the shaders, same paths as on the site.
+//! - `pipeline_fail/<code version>/<source hash>` - errors of pipelines that failed to build.
+//! - `output.log` and `profile-startup.json` - log and profile of the last run.
+//!
+//! The age of queries and shaders is the modification time of their files. Assets are never
+//! refreshed as their contents don't change, but are removed by `gc` once no shader uses them.
+
+use crate::errors::*;
+use rust_base58::ToBase58;
+use sha3::{Digest as Sha3Digest, Sha3_256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Version of the pipeline building code, failures are only remembered for the current version.
+/// Bump this if any of the code in `new_pipeline` is changed that could affect the success.
+pub const PIPELINE_CODE_VERSION: u32 = 1;
+
+/// Environment variable that sets the cache directory, unless set on the command-line.
+pub const CACHE_DIR_ENV: &str = "SHADERTOY_CACHE_DIR";
+
+/// Paths of the files in the cache directory.
+#[derive(Debug, Clone)]
+pub struct CachePaths {
+    root: PathBuf,
+}
+
+impl CachePaths {
+    pub fn new<P: Into<PathBuf>>(root: P) -> CachePaths {
+        CachePaths { root: root.into() }
+    }
+
+    /// `shadertoy-browser` in the platform's user cache directory, falling back to
+    /// `output` in the current directory if there is none.
+    pub fn default_root() -> PathBuf {
+        match dirs::cache_dir() {
+            Some(cache_dir) => cache_dir.join("shadertoy-browser"),
+            None => PathBuf::from("output"),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Cached result of a search, `key` identifies the search parameters.
+    pub fn query(&self, key: &str) -> PathBuf {
+        CacheSection::Queries.path(&self.root).join(key)
+    }
+
+    pub fn shader(&self, shader_id: &str) -> PathBuf {
+        self.root.join(shader_path(shader_id))
+    }
+
+    /// Path without extension of the files generated for a render pass, such as the GLSL source.
+    pub fn shader_pass(&self, shader_id: &str, pass_name: &str) -> PathBuf {
+        CacheSection::Shaders
+            .path(&self.root)
+            .join(shader_id)
+            .join(format!("{}{}", shader_id, pass_name))
+    }
+
+    /// Local copy of an asset, `src` is its path on the site, e.g. `/media/a/52d2a8f5.jpg`.
+    pub fn asset(&self, src: &str) -> PathBuf {
+        self.root.join(src.trim_start_matches('/'))
+    }
+
+    /// Error of a pipeline that failed to build, `source_hash` identifies the shader source.
+    pub fn pipeline_failure(&self, source_hash: &str) -> PathBuf {
+        CacheSection::PipelineFailures
+            .path(&self.root)
+            .join(PIPELINE_CODE_VERSION.to_string())
+            .join(source_hash)
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.root.join("output.log")
+    }
+
+    pub fn profile_file(&self) -> PathBuf {
+        self.root.join("profile-startup.json")
+    }
+}
+
+/// A part of the cache that can be inspected and cleared separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheSection {
+    Queries,
+    Shaders,
+    Media,
+    PipelineFailures,
+}
+
+impl CacheSection {
+    pub const ALL: [CacheSection; 4] = [
+        CacheSection::Queries,
+        CacheSection::Shaders,
+        CacheSection::Media,
+        CacheSection::PipelineFailures,
+    ];
+
+    /// Name used on the command-line.
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheSection::Queries => "queries",
+            CacheSection::Shaders => "shaders",
+            CacheSection::Media => "media",
+            CacheSection::PipelineFailures => "failures",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CacheSection> {
+        CacheSection::ALL
+            .iter()
+            .copied()
+            .find(|section| section.name() == name)
+    }
+
+    fn dir_name(self) -> &'static str {
+        match self {
+            CacheSection::Queries => "query",
+            CacheSection::Shaders => "shader",
+            CacheSection::Media => "media",
+            CacheSection::PipelineFailures => "pipeline_fail",
+        }
+    }
+
+    pub fn path(self, root: &Path) -> PathBuf {
+        root.join(self.dir_name())
+    }
+}
+
+/// Number of files and their total size.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiskUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl DiskUsage {
+    fn add_file(&mut self, path: &Path) -> Result<()> {
+        self.files += 1;
+        self.bytes += std::fs::metadata(path)?.len();
+        Ok(())
+    }
+}
+
+/// When cached queries and shaders are considered stale and requested again.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CachePolicy {
+    /// Maximum age of cache entries, `None` keeps them forever.
+    pub max_age: Option<Duration>,
+}
+
+impl CachePolicy {
+    /// Policy that considers everything stale.
+    pub fn refresh() -> CachePolicy {
+        CachePolicy {
+            max_age: Some(Duration::from_secs(0)),
+        }
+    }
+
+    /// Returns true if `path` exists and is not older than the maximum age.
+    pub fn is_fresh(&self, path: &Path) -> bool {
+        let modified = match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+
+        match self.max_age {
+            Some(max_age) => match SystemTime::now().duration_since(modified) {
+                Ok(age) => age < max_age,
+                // modified in the future, e.g. unpacked from an archive created on another machine
+                Err(_) => true,
+            },
+            None => true,
+        }
+    }
+}
+
+/// Parses a duration such as `90s`, `30m`, `12h`, `7d` or `2w`, a plain number is in seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let (number, unit_secs) = match duration.char_indices().last() {
+        Some((index, 's')) => (&duration[..index], 1),
+        Some((index, 'm')) => (&duration[..index], 60),
+        Some((index, 'h')) => (&duration[..index], 60 * 60),
+        Some((index, 'd')) => (&duration[..index], 24 * 60 * 60),
+        Some((index, 'w')) => (&duration[..index], 7 * 24 * 60 * 60),
+        _ => (duration, 1),
+    };
+
+    let number: u64 = number.trim().parse().chain_err(|| {
+        format!(
+            "invalid duration {}, expected e.g. 30s, 12h or 7d",
+            duration
+        )
+    })?;
+    match number.checked_mul(unit_secs) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => bail!("duration {} is too long", duration),
+    }
+}
+
+/// Identity of the source of a pipeline, names the file its failure is recorded in.
+pub fn source_hash(source: &str) -> String {
+    Sha3_256::digest(source.as_bytes()).to_base58()
+}
+
+/// Path of the JSON of a downloaded shader, relative to the output directory.
+pub fn shader_path(shader_id: &str) -> String {
+    format!("shader/{}/{}.json", shader_id, shader_id)
+}
+
+/// Sorted names of the entries in a directory, empty if it doesn't exist.
+pub fn list_dir(path: &Path) -> Result<Vec<String>> {
+    if !path.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut names = vec![];
+    for entry in std::fs::read_dir(path)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Sorted ids of all shadertoys that have been downloaded to the `root` output directory.
+pub fn downloaded_shadertoys(root: &Path) -> Result<Vec<String>> {
+    Ok(list_dir(&CacheSection::Shaders.path(root))?
+        .into_iter()
+        .filter(|shader_id| root.join(shader_path(shader_id)).is_file())
+        .collect())
+}
+
+/// Loads the JSON of a downloaded shadertoy.
+pub fn load_shader(root: &Path, shader_id: &str) -> Result<shadertoy::Shader> {
+    let path = root.join(shader_path(shader_id));
+    let mut json_str = String::new();
+    File::open(&path)
+        .chain_err(|| format!("failed opening {}", path.display()))?
+        .read_to_string(&mut json_str)?;
+    serde_json::from_str(&json_str).chain_err(|| format!("failed parsing {}", path.display()))
+}
+
+/// Paths of all files below a directory, recursively.
+fn files_in_dir(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files_in_dir(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn dir_usage(path: &Path, usage: &mut DiskUsage) -> Result<()> {
+    let mut files = vec![];
+    files_in_dir(path, &mut files)?;
+    for file in files {
+        usage.add_file(&file)?;
+    }
+    Ok(())
+}
+
+/// Disk usage of a section of the cache.
+pub fn usage(root: &Path, section: CacheSection) -> Result<DiskUsage> {
+    let mut usage = DiskUsage::default();
+    let path = section.path(root);
+    if path.is_dir() {
+        dir_usage(&path, &mut usage)?;
+    }
+    Ok(usage)
+}
+
+/// Removes all files in a section of the cache, returning how much was removed.
+pub fn clear(root: &Path, section: CacheSection) -> Result<DiskUsage> {
+    let usage = usage(root, section)?;
+    let path = section.path(root);
+    if path.is_dir() {
+        std::fs::remove_dir_all(&path)
+            .chain_err(|| format!("failed removing {}", path.display()))?;
+    }
+    Ok(usage)
+}
+
+/// What was removed by `gc`.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Assets not used by any downloaded shader.
+    pub assets: DiskUsage,
+    /// Queries older than the maximum age.
+    pub queries: DiskUsage,
+    /// Failures recorded by other versions of the pipeline code.
+    pub pipeline_failures: DiskUsage,
+}
+
+/// Removes orphaned assets, stale queries and pipeline failures of obsolete code versions
+/// from the `root` output directory.
+pub fn gc(root: &Path, policy: &CachePolicy) -> Result<GcReport> {
+    profile_scope!("cache_gc");
+
+    let mut report = GcReport::default();
+
+    // assets referenced by the downloaded shaders, relative to the output directory
+    let mut used_assets = HashSet::new();
+    for shader_id in downloaded_shadertoys(root)? {
+        let shader = load_shader(root, &shader_id)?;
+        for pass in &shader.renderpass {
+            for input in &pass.inputs {
+                for src in input.asset_paths() {
+                    used_assets.insert(root.join(src.trim_start_matches('/')));
+                }
+            }
+        }
+    }
+
+    let media_path = CacheSection::Media.path(root);
+    if media_path.is_dir() {
+        let mut assets = vec![];
+        files_in_dir(&media_path, &mut assets)?;
+        for asset in assets.iter().filter(|asset| !used_assets.contains(*asset)) {
+            report.assets.add_file(asset)?;
+            std::fs::remove_file(asset)?;
+        }
+        remove_empty_dirs(&media_path)?;
+    }
+
+    if policy.max_age.is_some() {
+        let query_path = CacheSection::Queries.path(root);
+        for query in list_dir(&query_path)? {
+            let path = query_path.join(query);
+            if !policy.is_fresh(&path) {
+                report.queries.add_file(&path)?;
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    let failures_path = CacheSection::PipelineFailures.path(root);
+    for code_version in list_dir(&failures_path)? {
+        if code_version != PIPELINE_CODE_VERSION.to_string() {
+            let path = failures_path.join(code_version);
+            dir_usage(&path, &mut report.pipeline_failures)?;
+            std::fs::remove_dir_all(&path)?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Removes all empty directories below `path`, returns true if `path` itself is left empty.
+fn remove_empty_dirs(path: &Path) -> Result<bool> {
+    let mut empty = true;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && remove_empty_dirs(&entry.path())? {
+            std::fs::remove_dir(entry.path())?;
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn durations() {
+        let secs = |duration| parse_duration(duration).unwrap().as_secs();
+        assert_eq!(secs("90"), 90);
+        assert_eq!(secs("90s"), 90);
+        assert_eq!(secs("30m"), 30 * 60);
+        assert_eq!(secs(" 12h "), 12 * 60 * 60);
+        assert_eq!(secs("7d"), 7 * 24 * 60 * 60);
+        assert_eq!(secs("2w"), 2 * 7 * 24 * 60 * 60);
+        assert_eq!(secs("0s"), 0);
+        assert_eq!(secs(&u64::MAX.to_string()), u64::MAX);
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for duration in &["", "s", "h2", "1.5h", "-1d", "12y", "99999999999999999w"] {
+            assert!(parse_duration(duration).is_err(), "{}", duration);
+        }
+    }
+
+    #[test]
+    fn gc_removes_unused_files() {
+        let root = test_dir("cache-gc");
+        write(
+            &root.join(shader_path("abc")),
+            r#"{
+                "info": { "id": "abc" },
+                "renderpass": [{
+                    "inputs": [{ "src": "/media/a/texture.png", "ctype": "texture" }],
+                    "type": "image"
+                }]
+            }"#,
+        );
+        write(&root.join("media/a/texture.png"), "png");
+        write(&root.join("media/a/orphan.png"), "orphan");
+        write(&root.join("media/previz/orphan.png"), "orphan");
+
+        let paths = CachePaths::new(&root);
+        write(&paths.query("fresh"), r#"["abc"]"#);
+        write(&paths.query("stale"), r#"["abc"]"#);
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(paths.query("stale"))
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+
+        write(&paths.pipeline_failure("current"), "error");
+        let failures_path = CacheSection::PipelineFailures.path(&root);
+        let obsolete = failures_path.join((PIPELINE_CODE_VERSION - 1).to_string());
+        write(&obsolete.join("old"), "old error");
+        write(&obsolete.join("older"), "older error");
+
+        // queries are kept forever without a maximum age
+        let report = gc(&root, &CachePolicy::default()).unwrap();
+        assert_eq!(report.assets.files, 2);
+        assert_eq!(report.assets.bytes, 12);
+        assert_eq!(report.queries.files, 0);
+        assert_eq!(report.pipeline_failures.files, 2);
+        assert_eq!(report.pipeline_failures.bytes, 20);
+
+        assert!(root.join("media/a/texture.png").is_file());
+        assert!(!root.join("media/a/orphan.png").exists());
+        assert!(!root.join("media/previz").exists());
+        assert!(paths.query("stale").is_file());
+        assert!(paths.pipeline_failure("current").is_file());
+        assert!(!obsolete.exists());
+
+        let policy = CachePolicy {
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+        };
+        let report = gc(&root, &policy).unwrap();
+        assert_eq!(report.assets.files, 0);
+        assert_eq!(report.queries.files, 1);
+        assert_eq!(report.pipeline_failures.files, 0);
+        assert!(paths.query("fresh").is_file());
+        assert!(!paths.query("stale").exists());
+        assert!(root.join(shader_path("abc")).is_file());
+    }
+}
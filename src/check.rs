@@ -0,0 +1,339 @@
+//! Validation of the downloaded shadertoys without a GPU, for the `check` subcommand.
+//!
+//! Every pass is compiled to SPIR-V, and optionally cross-compiled to a number of targets, in
+//! a sandboxed worker process. This works on any OS and doesn't need a render backend, so CI
+//! can track how much of a corpus of shadertoys is supported.
+
+use crate::cache;
+use crate::errors::*;
+use crate::sandbox::Sandbox;
+use crate::translate::{self, StageReport, Target};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Check result of a single pass.
+#[derive(Serialize, Debug)]
+pub struct PassCheck {
+    pub name: String,
+    pub pass_type: String,
+    pub success: bool,
+    pub stages: Vec<StageReport>,
+}
+
+/// Check result of a shadertoy, it passes if all its passes do.
+#[derive(Serialize, Debug)]
+pub struct ShaderCheck {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    pub success: bool,
+    pub passes: Vec<PassCheck>,
+}
+
+impl ShaderCheck {
+    /// Error of the first pass that failed, prefixed with the name of the pass.
+    pub fn error(&self) -> Option<String> {
+        self.passes.iter().find(|pass| !pass.success).map(|pass| {
+            let error = translate::first_error(&pass.stages).unwrap_or_default();
+            format!("{}: {}", pass.name, error)
+        })
+    }
+}
+
+/// Totals over all checked shadertoys.
+#[derive(Serialize, Debug, Default)]
+pub struct CheckSummary {
+    pub shaders: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub passes: usize,
+    pub passes_passed: usize,
+    /// Number of failed passes by the stage that failed, e.g. `spirv` or `wgsl`.
+    pub failed_stages: BTreeMap<String, usize>,
+}
+
+impl CheckSummary {
+    fn add(&mut self, check: &ShaderCheck) {
+        self.shaders += 1;
+        if check.success {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+
+        for pass in &check.passes {
+            self.passes += 1;
+            if pass.success {
+                self.passes_passed += 1;
+            }
+            if let Some(stage) = pass.stages.iter().find(|stage| !stage.success) {
+                *self.failed_stages.entry(stage.stage.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Percentage of the shadertoys that passed, 100 if there are none.
+    pub fn percent_passed(&self) -> f64 {
+        if self.shaders == 0 {
+            return 100.0;
+        }
+        self.passed as f64 * 100.0 / self.shaders as f64
+    }
+
+    /// Fails if less than `percent` of the shadertoys passed, see the `--fail-under` option.
+    pub fn fail_under(&self, percent: f64) -> Result<()> {
+        if self.percent_passed() < percent {
+            bail!(
+                "{:.1}% of the shadertoys passed the check, less than {}%",
+                self.percent_passed(),
+                percent
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CheckSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} / {} shadertoys passed ({:.1}%), {} / {} passes",
+            self.passed,
+            self.shaders,
+            self.percent_passed(),
+            self.passes_passed,
+            self.passes
+        )?;
+        if !self.failed_stages.is_empty() {
+            writeln!(f, "Failed passes by stage:")?;
+            for (stage, count) in &self.failed_stages {
+                writeln!(f, "  {:<20} {}", stage, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Translates the source of a pass to the targets in a worker process, and returns the reports
+/// of the stages.
+pub fn check_pass(
+    sandbox: &Sandbox,
+    targets: &[Target],
+    name: &str,
+    source: &str,
+) -> Vec<StageReport> {
+    match sandbox.check(targets, name, source) {
+        Ok(stages) => stages,
+        Err(err) => vec![worker_failure(&err)],
+    }
+}
+
+/// Report of a worker that failed, timeouts and crashes of the worker fail the pass like any
+/// other error.
+pub fn worker_failure(err: &Error) -> StageReport {
+    StageReport {
+        stage: "worker".to_string(),
+        success: false,
+        time_ms: 0.0,
+        diagnostics: Some(err.to_string()),
+    }
+}
+
+/// Checks all passes of a downloaded shadertoy.
+pub fn check_shader(
+    root: &Path,
+    shader_id: &str,
+    sandbox: &Sandbox,
+    targets: &[Target],
+) -> Result<ShaderCheck> {
+    profile_scope!("check_shader");
+
+    let shader = cache::load_shader(root, shader_id)?;
+
+    let mut passes = vec![];
+    for pass in &shader.renderpass {
+        if !translate::is_translated(pass) {
+            continue;
+        }
+
+        let source = translate::assemble_pass(&shader, pass);
+        let stages = check_pass(sandbox, targets, &pass.name, &source);
+
+        passes.push(PassCheck {
+            name: pass.name.clone(),
+            pass_type: pass.pass_type.as_str().to_string(),
+            success: stages.iter().all(|stage| stage.success),
+            stages,
+        });
+    }
+
+    Ok(ShaderCheck {
+        id: shader.info.id.clone(),
+        name: shader.info.name.clone(),
+        username: shader.info.username.clone(),
+        success: passes.iter().all(|pass| pass.success),
+        passes,
+    })
+}
+
+/// Checks the shadertoys on `threads` threads, see the `--threads` option, and calls
+/// `on_check` with the result of each as soon as it is available.
+pub fn check_all<F>(
+    root: &Path,
+    shadertoys: &[String],
+    sandbox: &Sandbox,
+    targets: &[Target],
+    threads: i64,
+    on_check: F,
+) -> Result<CheckSummary>
+where
+    F: Fn(&ShaderCheck) -> Result<()> + Sync,
+{
+    profile_scope!("check_all");
+
+    let summary = std::sync::Mutex::new(CheckSummary::default());
+    let check = |shader_id: &String| -> Result<()> {
+        let check = check_shader(root, shader_id, sandbox, targets)
+            .chain_err(|| format!("failed checking shadertoy {}", shader_id))?;
+        summary.lock().unwrap().add(&check);
+        on_check(&check)
+    };
+
+    if threads == 0 {
+        shadertoys.iter().try_for_each(check)?;
+    } else {
+        let mut pool = rayon::ThreadPoolBuilder::new();
+        if threads > 0 {
+            pool = pool.num_threads(threads as usize);
+        }
+        pool.build()
+            .chain_err(|| "failed creating thread pool")?
+            .install(|| shadertoys.par_iter().try_for_each(check))?;
+    }
+
+    Ok(summary.into_inner().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(stage: &str, success: bool) -> StageReport {
+        StageReport {
+            stage: stage.to_string(),
+            success,
+            time_ms: 1.0,
+            diagnostics: if success {
+                None
+            } else {
+                Some(format!("{} error", stage))
+            },
+        }
+    }
+
+    fn pass(name: &str, stages: Vec<StageReport>) -> PassCheck {
+        PassCheck {
+            name: name.to_string(),
+            pass_type: "image".to_string(),
+            success: stages.iter().all(|stage| stage.success),
+            stages,
+        }
+    }
+
+    fn shader(id: &str, passes: Vec<PassCheck>) -> ShaderCheck {
+        ShaderCheck {
+            id: id.to_string(),
+            name: format!("shader {}", id),
+            username: "user".to_string(),
+            success: passes.iter().all(|pass| pass.success),
+            passes,
+        }
+    }
+
+    #[test]
+    fn error() {
+        let check = shader(
+            "ok",
+            vec![pass(
+                "Image",
+                vec![stage("spirv", true), stage("msl", true)],
+            )],
+        );
+        assert_eq!(check.error(), None);
+
+        let check = shader(
+            "failed",
+            vec![
+                pass("Buffer A", vec![stage("spirv", true)]),
+                pass("Buffer B", vec![stage("spirv", true), stage("wgsl", false)]),
+                pass("Image", vec![stage("spirv", false)]),
+            ],
+        );
+        assert_eq!(
+            check.error().as_deref(),
+            Some("Buffer B: wgsl failed: wgsl error")
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let mut summary = CheckSummary::default();
+        summary.add(&shader(
+            "ok",
+            vec![pass(
+                "Image",
+                vec![stage("spirv", true), stage("msl", true)],
+            )],
+        ));
+        summary.add(&shader(
+            "failed",
+            vec![
+                pass("Buffer A", vec![stage("spirv", false)]),
+                pass("Buffer B", vec![stage("spirv", true), stage("msl", false)]),
+                pass("Image", vec![stage("spirv", false)]),
+            ],
+        ));
+        summary.add(&shader("empty", vec![]));
+
+        assert_eq!(summary.shaders, 3);
+        assert_eq!(summary.passed, 2);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.passes, 4);
+        assert_eq!(summary.passes_passed, 1);
+        assert_eq!(summary.failed_stages.len(), 2);
+        assert_eq!(summary.failed_stages["spirv"], 2);
+        assert_eq!(summary.failed_stages["msl"], 1);
+        assert!((summary.percent_passed() - 200.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn percent_passed_without_shaders() {
+        let summary = CheckSummary::default();
+        assert_eq!(summary.percent_passed(), 100.0);
+        assert!(summary.fail_under(100.0).is_ok());
+    }
+
+    #[test]
+    fn fail_under() {
+        let mut summary = CheckSummary::default();
+        summary.add(&shader(
+            "ok",
+            vec![pass("Image", vec![stage("spirv", true)])],
+        ));
+        summary.add(&shader(
+            "failed",
+            vec![pass("Image", vec![stage("spirv", false)])],
+        ));
+
+        assert!(summary.fail_under(0.0).is_ok());
+        assert!(summary.fail_under(50.0).is_ok());
+        let err = summary.fail_under(50.1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "50.0% of the shadertoys passed the check, less than 50.1%"
+        );
+    }
+}
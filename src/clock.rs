@@ -0,0 +1,310 @@
+//! The time source of rendering, which all shadertoy constants that depend on time come from.
+//!
+//! Viewing, recording and tests all render with a `Clock`, which either follows the wall-clock or
+//! advances a fixed step every frame, so that frames can be reproduced exactly.
+
+use crate::render::FrameInputs;
+use chrono::prelude::*;
+use std::time::Instant;
+
+/// How a clock advances from one frame to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockMode {
+    /// By the wall-clock time between frames.
+    RealTime,
+    /// By a fixed number of seconds every frame, however long rendering takes.
+    FixedStep(f64),
+}
+
+/// Time, frame number and date of the frames being rendered.
+///
+/// The render loop reads the inputs of a frame with `frame_inputs` and calls `advance` after
+/// rendering it. Changing the speed, pausing and seeking take effect from the next frame.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    mode: ClockMode,
+    /// Shadertoy seconds per wall-clock or fixed step second.
+    speed: f64,
+    paused: bool,
+
+    time: f64,
+    time_delta: f64,
+    frame: u64,
+
+    /// Time and frames since the last seek or change of speed, fixed steps are counted instead of
+    /// summed up so the time of a frame doesn't depend on rounding errors of earlier frames.
+    anchor_time: f64,
+    steps: u64,
+    last_advance: Instant,
+
+    /// Date at time 0, `iDate` is this plus the time.
+    start_date: DateTime<Local>,
+}
+
+impl Clock {
+    pub fn new(mode: ClockMode) -> Clock {
+        let mut clock = Clock {
+            mode,
+            speed: 1.0,
+            paused: false,
+            time: 0.0,
+            time_delta: 0.0,
+            frame: 0,
+            anchor_time: 0.0,
+            steps: 0,
+            last_advance: Instant::now(),
+            start_date: Local::now(),
+        };
+        clock.time_delta = clock.step_delta();
+        clock
+    }
+
+    /// A clock following the wall-clock, starting now.
+    pub fn real_time() -> Clock {
+        Clock::new(ClockMode::RealTime)
+    }
+
+    /// A clock advancing `1 / fps` seconds every frame.
+    pub fn fixed_fps(fps: f64) -> Clock {
+        Clock::new(ClockMode::FixedStep(1.0 / fps))
+    }
+
+    /// Sets the date at time 0, e.g. to render frames with the same `iDate` every time.
+    pub fn with_start_date(mut self, start_date: DateTime<Local>) -> Clock {
+        self.start_date = start_date;
+        self
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.mode
+    }
+
+    /// Current time in seconds.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Date of the current time.
+    pub fn date(&self) -> DateTime<Local> {
+        self.start_date + chrono::Duration::microseconds((self.time * 1e6).round() as i64)
+    }
+
+    /// Sets how many times faster than the wall-clock or fixed step time passes, can be negative
+    /// to play backwards.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.reanchor();
+        self.speed = speed;
+        self.time_delta = self.step_delta();
+    }
+
+    /// Stops time, frames are rendered at the current time until resumed.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.reanchor();
+        if self.paused && !paused {
+            // the time spent paused doesn't count towards the next frame
+            self.last_advance = Instant::now();
+        }
+        self.paused = paused;
+        self.time_delta = self.step_delta();
+    }
+
+    /// Jumps to a time in seconds, with the frame number it has when played from time 0 at a
+    /// fixed step, or at `FrameInputs::FRAME_RATE` in real-time.
+    pub fn seek(&mut self, time: f64) {
+        let frame_duration = match self.mode {
+            ClockMode::RealTime => 1.0 / f64::from(FrameInputs::FRAME_RATE),
+            ClockMode::FixedStep(step) => step,
+        };
+
+        self.time = time;
+        self.time_delta = self.step_delta();
+        self.frame = (time / frame_duration).round().max(0.0) as u64;
+        self.reanchor();
+    }
+
+    /// Moves on to the next frame.
+    pub fn advance(&mut self) {
+        let now = Instant::now();
+        let wall_delta = now.duration_since(self.last_advance).as_secs_f64();
+        self.last_advance = now;
+
+        if self.paused {
+            self.time_delta = 0.0;
+            return;
+        }
+
+        let time = match self.mode {
+            ClockMode::RealTime => self.time + wall_delta * self.speed,
+            ClockMode::FixedStep(step) => {
+                self.steps += 1;
+                self.anchor_time + self.steps as f64 * step * self.speed
+            }
+        };
+
+        self.time_delta = time - self.time;
+        self.time = time;
+        self.frame += 1;
+    }
+
+    /// Inputs of the current frame, with the mouse state in shadertoy convention, see `iMouse`.
+    pub fn frame_inputs(&self, mouse: (f32, f32, f32, f32)) -> FrameInputs {
+        FrameInputs {
+            time: self.time as f32,
+            time_delta: self.time_delta as f32,
+            frame: self.frame as i32,
+            mouse,
+            date: self.date(),
+        }
+    }
+
+    /// Time delta of frames that aren't preceded by an advance, such as the first one: the fixed
+    /// step, and 0 in real-time as there is no previous frame to measure.
+    fn step_delta(&self) -> f64 {
+        match self.mode {
+            ClockMode::FixedStep(step) if !self.paused => step * self.speed,
+            _ => 0.0,
+        }
+    }
+
+    fn reanchor(&mut self) {
+        self.anchor_time = self.time;
+        self.steps = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_time(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} isn't {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn fixed_step() {
+        let mut clock = Clock::fixed_fps(60.0);
+        assert_eq!(clock.time(), 0.0);
+        assert_eq!(clock.frame(), 0);
+        assert_time(clock.time_delta, 1.0 / 60.0);
+
+        // the steps are counted, so the time doesn't drift from summing them up
+        for _ in 0..6000 {
+            clock.advance();
+        }
+        assert_eq!(clock.time(), 100.0);
+        assert_eq!(clock.frame(), 6000);
+        assert_time(clock.time_delta, 1.0 / 60.0);
+    }
+
+    #[test]
+    fn seek() {
+        let mut clock = Clock::fixed_fps(60.0);
+        clock.seek(2.0);
+        assert_eq!(clock.time(), 2.0);
+        assert_eq!(clock.frame(), 120);
+        clock.advance();
+        assert_time(clock.time(), 2.0 + 1.0 / 60.0);
+        assert_eq!(clock.frame(), 121);
+
+        clock.seek(-1.0);
+        assert_eq!(clock.time(), -1.0);
+        assert_eq!(clock.frame(), 0);
+
+        // real-time frame numbers are at the assumed frame rate, there is no delta to the seek
+        let mut clock = Clock::real_time();
+        clock.seek(1.0);
+        assert_eq!(clock.frame(), 60);
+        assert_eq!(clock.time_delta, 0.0);
+    }
+
+    #[test]
+    fn set_speed() {
+        let mut clock = Clock::fixed_fps(10.0);
+        clock.advance();
+        clock.advance();
+        assert_time(clock.time(), 0.2);
+
+        clock.set_speed(2.0);
+        assert_eq!(clock.speed(), 2.0);
+        assert_time(clock.time_delta, 0.2);
+        clock.advance();
+        assert_time(clock.time(), 0.4);
+
+        // backwards
+        clock.set_speed(-1.0);
+        clock.advance();
+        clock.advance();
+        assert_time(clock.time(), 0.2);
+        assert_time(clock.time_delta, -0.1);
+        assert_eq!(clock.frame(), 5);
+    }
+
+    #[test]
+    fn set_paused() {
+        let mut clock = Clock::fixed_fps(10.0);
+        clock.advance();
+
+        clock.set_paused(true);
+        assert!(clock.is_paused());
+        assert_eq!(clock.time_delta, 0.0);
+        clock.advance();
+        clock.advance();
+        assert_time(clock.time(), 0.1);
+        assert_eq!(clock.frame(), 1);
+        assert_eq!(clock.time_delta, 0.0);
+
+        clock.set_paused(false);
+        assert_time(clock.time_delta, 0.1);
+        clock.advance();
+        assert_time(clock.time(), 0.2);
+        assert_eq!(clock.frame(), 2);
+    }
+
+    #[test]
+    fn real_time_delta_after_pause() {
+        let mut clock = Clock::real_time();
+        clock.set_paused(true);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        clock.set_paused(false);
+        assert_eq!(clock.time_delta, 0.0);
+
+        // only the time since resuming passes
+        clock.advance();
+        assert!(clock.time_delta >= 0.0 && clock.time_delta < 0.1);
+        assert_eq!(clock.time(), clock.time_delta);
+        assert_eq!(clock.frame(), 1);
+    }
+
+    #[test]
+    fn date() {
+        let start_date = DateTime::parse_from_rfc3339("2021-04-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Local);
+        let mut clock = Clock::fixed_fps(60.0).with_start_date(start_date);
+        assert_eq!(clock.date(), start_date);
+
+        clock.seek(90.5);
+        assert_eq!(
+            clock.date(),
+            start_date + chrono::Duration::milliseconds(90_500)
+        );
+        assert_eq!(clock.frame_inputs((0.0, 0.0, 0.0, 0.0)).date, clock.date());
+    }
+}
@@ -0,0 +1,399 @@
+//! Settings from TOML configuration files, layered below the command-line.
+//!
+//! There is a user configuration file, `shadertoy-browser/config.toml` in the user's config directory,
+//! and a project configuration file, `shadertoy-browser.toml` in the current directory. Both set
+//! defaults at the top level and can have named profiles that are selected with `--profile`:
+//!
+//! ```toml
+//! apikey = "your-api-key"
+//! threads = 8
+//!
+//! [profiles.raymarching-demo-wall]
+//! search = "raymarching"
+//! order = "Love"
+//! limit = 200
+//! grid_width = 6
+//! grid_height = 4
+//! skip = ["XllSWf", "ll2BWz"]
+//! ```
+//!
+//! Keys are the names of the command-line arguments, see `SETTINGS`. A setting is taken from the
+//! first of these that has it:
+//!
+//! 1. the command-line
+//! 2. the environment, for the settings that have an environment variable
+//! 3. the selected profile, in the project and then the user configuration file
+//! 4. the top level of the project and then the user configuration file
+//! 5. the default of the command-line argument
+
+use crate::errors::*;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+pub const PROJECT_CONFIG_FILE: &str = "shadertoy-browser.toml";
+
+/// Names of the settings that can be set in configuration files.
+pub const SETTINGS: &[&str] = &[
+    "apikey",
+    "baseurl",
+    "search",
+    "filter",
+    "order",
+    "limit",
+    "skip",
+    "threads",
+    "ratelimit",
+    "retries",
+    "cache_dir",
+    "max_age",
+    "refresh",
+    "denylist",
+    "compile_timeout",
+    "deny_timeout",
+    "compile_memory",
+    "backend",
+    "format",
+    "headless",
+    "verbose",
+    "res_width",
+    "res_height",
+    "grid_width",
+    "grid_height",
+];
+
+/// Settings that can also be set with an environment variable.
+const ENV_VARS: &[(&str, &str)] = &[("cache_dir", crate::cache::CACHE_DIR_ENV)];
+
+/// Path of the user configuration file, if the platform has a config directory.
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|config_dir| config_dir.join("shadertoy-browser").join("config.toml"))
+}
+
+/// A parsed configuration file.
+#[derive(Debug, Default)]
+pub struct Config {
+    settings: Table,
+    profiles: Vec<(String, Table)>,
+}
+
+impl Config {
+    /// Loads a configuration file, `None` if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Option<Config>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let toml_str = std::fs::read_to_string(path)
+            .chain_err(|| format!("failed reading config file {}", path.display()))?;
+        Config::parse(&toml_str)
+            .chain_err(|| format!("invalid config file {}", path.display()))
+            .map(Some)
+    }
+
+    pub fn parse(toml_str: &str) -> Result<Config> {
+        let mut settings: Table = toml::from_str(toml_str).chain_err(|| "failed parsing TOML")?;
+
+        let profiles = match settings.remove("profiles") {
+            Some(Value::Table(profiles)) => profiles
+                .into_iter()
+                .map(|(name, profile)| match profile {
+                    Value::Table(profile) => {
+                        check_keys(&profile).chain_err(|| format!("in profile {}", name))?;
+                        Ok((name, profile))
+                    }
+                    _ => bail!("profile {} is not a table", name),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(_) => bail!("profiles is not a table"),
+            None => vec![],
+        };
+
+        check_keys(&settings)?;
+
+        Ok(Config { settings, profiles })
+    }
+
+    fn profile(&self, name: &str) -> Option<&Table> {
+        self.profiles
+            .iter()
+            .find(|(profile_name, _)| profile_name == name)
+            .map(|(_, profile)| profile)
+    }
+
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+fn check_keys(table: &Table) -> Result<()> {
+    for key in table.keys() {
+        if !SETTINGS.contains(&key.as_str()) {
+            bail!(
+                "unknown setting {}, expected one of: {}",
+                key,
+                SETTINGS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The settings of a run, from the command-line and configuration files.
+///
+/// Lookups mirror `clap::ArgMatches`, but return owned values.
+pub struct Settings<'a> {
+    matches: &'a clap::ArgMatches<'a>,
+    /// Tables from the configuration files, highest priority first.
+    layers: Vec<Table>,
+}
+
+impl<'a> Settings<'a> {
+    /// Loads the user and project configuration files and selects the profile given with `--profile`.
+    pub fn load(matches: &'a clap::ArgMatches<'a>) -> Result<Settings<'a>> {
+        let mut configs = vec![];
+        if let Some(config) = Config::load(Path::new(PROJECT_CONFIG_FILE))? {
+            configs.push(config);
+        }
+        if let Some(path) = user_config_path() {
+            if let Some(config) = Config::load(&path)? {
+                configs.push(config);
+            }
+        }
+
+        Settings::new(matches, configs, matches.value_of("profile"))
+    }
+
+    /// Creates settings from configuration files, highest priority first.
+    pub fn new(
+        matches: &'a clap::ArgMatches<'a>,
+        configs: Vec<Config>,
+        profile: Option<&str>,
+    ) -> Result<Settings<'a>> {
+        let mut layers = vec![];
+
+        if let Some(profile) = profile {
+            for config in &configs {
+                if let Some(table) = config.profile(profile) {
+                    layers.push(table.clone());
+                }
+            }
+
+            if layers.is_empty() {
+                let mut names: Vec<&str> = configs
+                    .iter()
+                    .flat_map(|config| config.profile_names())
+                    .collect();
+                names.sort_unstable();
+                names.dedup();
+                if names.is_empty() {
+                    bail!(
+                        "unknown profile {}, no profiles are defined in the configuration files",
+                        profile
+                    );
+                }
+                bail!(
+                    "unknown profile {}, available profiles: {}",
+                    profile,
+                    names.join(", ")
+                );
+            }
+        }
+
+        layers.extend(configs.into_iter().map(|config| config.settings));
+
+        Ok(Settings { matches, layers })
+    }
+
+    /// The command-line arguments, e.g. to check which subcommand to run.
+    pub fn matches(&self) -> &'a clap::ArgMatches<'a> {
+        self.matches
+    }
+
+    fn env_var(name: &str) -> Option<String> {
+        ENV_VARS
+            .iter()
+            .find(|(setting, _)| *setting == name)
+            .and_then(|(_, env_var)| std::env::var(env_var).ok())
+    }
+
+    fn config_value(&self, name: &str) -> Option<&Value> {
+        self.layers.iter().find_map(|layer| layer.get(name))
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<String> {
+        if self.matches.occurrences_of(name) > 0 {
+            return self.matches.value_of(name).map(str::to_string);
+        }
+        if let Some(value) = Settings::env_var(name) {
+            return Some(value);
+        }
+        match self.config_value(name) {
+            Some(value) => Some(value_to_string(value)),
+            None => self.matches.value_of(name).map(str::to_string),
+        }
+    }
+
+    /// Values of a setting that can be given multiple times, a single value in a
+    /// configuration file doesn't have to be in an array.
+    pub fn values_of(&self, name: &str) -> Option<Vec<String>> {
+        if self.matches.occurrences_of(name) > 0 {
+            return self
+                .matches
+                .values_of(name)
+                .map(|values| values.map(str::to_string).collect());
+        }
+        match self.config_value(name) {
+            Some(Value::Array(values)) => Some(values.iter().map(value_to_string).collect()),
+            Some(value) => Some(vec![value_to_string(value)]),
+            None => self
+                .matches
+                .values_of(name)
+                .map(|values| values.map(str::to_string).collect()),
+        }
+    }
+
+    /// Whether a flag is set, in a configuration file flags are booleans.
+    pub fn is_present(&self, name: &str) -> bool {
+        if self.matches.occurrences_of(name) > 0 {
+            return true;
+        }
+        match self.config_value(name) {
+            Some(Value::Boolean(value)) => *value,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{App, Arg};
+
+    fn app() -> App<'static, 'static> {
+        App::new("test")
+            .arg(Arg::with_name("search").long("search").takes_value(true))
+            .arg(
+                Arg::with_name("limit")
+                    .long("limit")
+                    .takes_value(true)
+                    .default_value("-1"),
+            )
+            .arg(
+                Arg::with_name("skip")
+                    .long("skip")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(Arg::with_name("refresh").long("refresh"))
+            .arg(
+                Arg::with_name("cache_dir")
+                    .long("cache-dir")
+                    .takes_value(true),
+            )
+    }
+
+    const PROJECT: &str = r#"
+        search = "project"
+
+        [profiles.demo]
+        search = "project demo"
+        skip = "abc"
+    "#;
+
+    const USER: &str = r#"
+        search = "user"
+        limit = 10
+        refresh = true
+
+        [profiles.demo]
+        limit = 20
+
+        [profiles.other]
+        limit = 30
+    "#;
+
+    fn configs() -> Vec<Config> {
+        vec![
+            Config::parse(PROJECT).unwrap(),
+            Config::parse(USER).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(USER).unwrap();
+        assert_eq!(
+            config.profile_names().collect::<Vec<_>>(),
+            vec!["demo", "other"]
+        );
+
+        assert!(Config::parse("unknown = 1").is_err());
+        assert!(Config::parse("[profiles.demo]\nunknown = 1").is_err());
+        assert!(Config::parse("profiles = 1").is_err());
+        assert!(Config::parse("search = ").is_err());
+    }
+
+    #[test]
+    fn layers() {
+        let matches = app().get_matches_from(vec!["test"]);
+
+        // top level of the project config before the user config, before the default
+        let settings = Settings::new(&matches, configs(), None).unwrap();
+        assert_eq!(settings.value_of("search").unwrap(), "project");
+        assert_eq!(settings.value_of("limit").unwrap(), "10");
+        assert!(settings.is_present("refresh"));
+        assert_eq!(settings.values_of("skip"), None);
+
+        // profiles before the top level, in the project config before the user config
+        let settings = Settings::new(&matches, configs(), Some("demo")).unwrap();
+        assert_eq!(settings.value_of("search").unwrap(), "project demo");
+        assert_eq!(settings.value_of("limit").unwrap(), "20");
+        assert_eq!(settings.values_of("skip").unwrap(), vec!["abc"]);
+
+        let settings = Settings::new(&matches, configs(), Some("other")).unwrap();
+        assert_eq!(settings.value_of("search").unwrap(), "project");
+        assert_eq!(settings.value_of("limit").unwrap(), "30");
+
+        let settings = Settings::new(&matches, vec![], None).unwrap();
+        assert_eq!(settings.value_of("limit").unwrap(), "-1");
+        assert!(!settings.is_present("refresh"));
+
+        assert!(Settings::new(&matches, configs(), Some("unknown")).is_err());
+        assert!(Settings::new(&matches, vec![], Some("demo")).is_err());
+    }
+
+    #[test]
+    fn command_line_before_configs() {
+        let matches = app().get_matches_from(vec![
+            "test", "--search", "cli", "--limit", "5", "--skip", "x", "--skip", "y",
+        ]);
+
+        let settings = Settings::new(&matches, configs(), Some("demo")).unwrap();
+        assert_eq!(settings.value_of("search").unwrap(), "cli");
+        assert_eq!(settings.value_of("limit").unwrap(), "5");
+        assert_eq!(settings.values_of("skip").unwrap(), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn environment_before_configs() {
+        let matches = app().get_matches_from(vec!["test"]);
+        let config = Config::parse(r#"cache_dir = "config""#).unwrap();
+        let settings = Settings::new(&matches, vec![config], None).unwrap();
+
+        std::env::set_var(crate::cache::CACHE_DIR_ENV, "environment");
+        assert_eq!(settings.value_of("cache_dir").unwrap(), "environment");
+
+        let matches = app().get_matches_from(vec!["test", "--cache-dir", "cli"]);
+        let settings = Settings::new(&matches, vec![], None).unwrap();
+        assert_eq!(settings.value_of("cache_dir").unwrap(), "cli");
+        std::env::remove_var(crate::cache::CACHE_DIR_ENV);
+    }
+}
@@ -0,0 +1,339 @@
+//! Persisted lists of shadertoys to never build, or to always build, with the reason why.
+//!
+//! Some shaders make the shader compilers hang, these are added to the denylist automatically when
+//! their translation worker is stopped after the compile timeout, or when building them takes
+//! longer than the deny timeout. The allowlist overrides the denylist, and
+//! shaders on it are never added automatically. The lists are stored in a TOML file:
+//!
+//! ```toml
+//! [deny.XllSWf]
+//! reason = "gets stuck in shader compilation"
+//! added = "2021-05-01T12:00:00+02:00"
+//! automatic = false
+//! ```
+
+use crate::errors::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Shaders that were known to get stuck in compilation, these seed a new denylist.
+const DEFAULT_DENIED: [&str; 5] = ["XllSWf", "ll2BWz", "4sG3Wy", "XdsBzj", "4td3z4"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum List {
+    Deny,
+    Allow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub reason: String,
+    /// When the entry was added, in RFC 3339 format.
+    #[serde(default)]
+    pub added: String,
+    /// True if added because of a compile or deny timeout instead of by hand.
+    #[serde(default)]
+    pub automatic: bool,
+}
+
+impl Entry {
+    pub fn new(reason: &str, automatic: bool) -> Entry {
+        Entry {
+            reason: reason.to_string(),
+            added: chrono::Local::now().to_rfc3339(),
+            automatic,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct Lists {
+    deny: BTreeMap<String, Entry>,
+    allow: BTreeMap<String, Entry>,
+}
+
+impl Lists {
+    fn list_mut(&mut self, list: List) -> &mut BTreeMap<String, Entry> {
+        match list {
+            List::Deny => &mut self.deny,
+            List::Allow => &mut self.allow,
+        }
+    }
+}
+
+/// The deny and allow lists, shared between the threads building shaders.
+pub struct Denylist {
+    path: PathBuf,
+    lists: Mutex<Lists>,
+}
+
+impl Denylist {
+    /// `shadertoy-browser/denylist.toml` in the user's config directory, so it survives clearing the cache.
+    pub fn default_path() -> PathBuf {
+        match dirs::config_dir() {
+            Some(config_dir) => config_dir.join("shadertoy-browser").join("denylist.toml"),
+            None => PathBuf::from("denylist.toml"),
+        }
+    }
+
+    /// Loads the lists from a file, or starts with the default denylist if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Denylist> {
+        let lists = if path.is_file() {
+            let toml_str = std::fs::read_to_string(path)
+                .chain_err(|| format!("failed reading denylist {}", path.display()))?;
+            toml::from_str(&toml_str)
+                .chain_err(|| format!("failed parsing denylist {}", path.display()))?
+        } else {
+            let mut lists = Lists::default();
+            for shader_id in DEFAULT_DENIED.iter() {
+                lists.deny.insert(
+                    shader_id.to_string(),
+                    Entry::new("gets stuck in shader compilation", false),
+                );
+            }
+            lists
+        };
+
+        Ok(Denylist {
+            path: path.to_path_buf(),
+            lists: Mutex::new(lists),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The denylist entry of a shader, `None` if it isn't denied or is allowed.
+    pub fn denied(&self, shader_id: &str) -> Option<Entry> {
+        let lists = self.lists.lock().unwrap();
+        if lists.allow.contains_key(shader_id) {
+            return None;
+        }
+        lists.deny.get(shader_id).cloned()
+    }
+
+    /// Adds a shader to a list and saves the file. A shader is only on one of the lists,
+    /// so it is removed from the other list.
+    pub fn add(&self, list: List, shader_id: &str, entry: Entry) -> Result<()> {
+        let mut lists = self.lists.lock().unwrap();
+        let other = match list {
+            List::Deny => List::Allow,
+            List::Allow => List::Deny,
+        };
+        lists.list_mut(other).remove(shader_id);
+        lists.list_mut(list).insert(shader_id.to_string(), entry);
+        self.save(&lists)
+    }
+
+    /// Removes a shader from a list and saves the file, returns false if it wasn't on the list.
+    pub fn remove(&self, list: List, shader_id: &str) -> Result<bool> {
+        let mut lists = self.lists.lock().unwrap();
+        let removed = lists.list_mut(list).remove(shader_id).is_some();
+        if removed {
+            self.save(&lists)?;
+        }
+        Ok(removed)
+    }
+
+    /// All entries of a list, sorted by shader id.
+    pub fn entries(&self, list: List) -> Vec<(String, Entry)> {
+        self.lists
+            .lock()
+            .unwrap()
+            .list_mut(list)
+            .iter()
+            .map(|(shader_id, entry)| (shader_id.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Writes the lists to the file, called with the lists locked so saves don't interleave.
+    fn save(&self, lists: &Lists) -> Result<()> {
+        let toml_str = toml::to_string_pretty(lists).chain_err(|| "failed serializing denylist")?;
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // write to a temporary file first, so concurrent readers never see a partial file
+        let tmp_path = self.path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml_str)?;
+        std::fs::rename(&tmp_path, &self.path)
+            .chain_err(|| format!("failed writing denylist {}", self.path.display()))
+    }
+
+    /// Adds a shader to the denylist because it hangs the build, unless it is on the allowlist.
+    /// Failing to save the list is only logged, as the build goes on either way.
+    pub fn deny_automatically(&self, shader_id: &str, reason: &str) {
+        if self.lists.lock().unwrap().allow.contains_key(shader_id) {
+            return;
+        }
+
+        warn!("Adding shadertoy {} to the denylist: {}", shader_id, reason);
+        if let Err(err) = self.add(List::Deny, shader_id, Entry::new(reason, true)) {
+            error!(
+                "Failed adding shadertoy {} to the denylist: {}",
+                shader_id, err
+            );
+        }
+    }
+
+    /// Watches the build of a shader, if it isn't done within `timeout` the shader is added to the
+    /// denylist right away. That way it is skipped the next time, even if the build never finishes.
+    /// Translation in the worker processes is stopped after the shorter compile timeout, so this
+    /// catches the steps that aren't sandboxed, such as the Metal compiler.
+    pub fn watch(self: &Arc<Self>, shader_id: &str, timeout: Duration) -> CompileWatchdog {
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+        let denylist = Arc::clone(self);
+        let shader_id = shader_id.to_string();
+
+        let thread = std::thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = done_receiver.recv_timeout(timeout) {
+                let reason = format!("building took longer than {} s", timeout.as_secs_f32());
+                denylist.deny_automatically(&shader_id, &reason);
+            }
+        });
+
+        CompileWatchdog {
+            done_sender: Some(done_sender),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Returned by `Denylist::watch`, dropping it marks the build as done.
+pub struct CompileWatchdog {
+    done_sender: Option<mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for CompileWatchdog {
+    fn drop(&mut self) {
+        // disconnecting wakes up the watchdog thread
+        self.done_sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _r_ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn default_denylist() {
+        let path = test_dir("denylist-default").join("denylist.toml");
+        let denylist = Denylist::load(&path).unwrap();
+
+        for shader_id in DEFAULT_DENIED.iter() {
+            assert!(!denylist.denied(shader_id).unwrap().automatic);
+        }
+        assert!(denylist.denied("4d2BDy").is_none());
+        assert!(denylist.entries(List::Allow).is_empty());
+        // the file is only written once something changes
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = test_dir("denylist-save")
+            .join("config")
+            .join("denylist.toml");
+        let denylist = Denylist::load(&path).unwrap();
+        denylist
+            .add(List::Deny, "4d2BDy", Entry::new("too slow", true))
+            .unwrap();
+        assert!(denylist.remove(List::Deny, "XllSWf").unwrap());
+        assert!(!denylist.remove(List::Deny, "XllSWf").unwrap());
+
+        let denylist = Denylist::load(&path).unwrap();
+        let entry = denylist.denied("4d2BDy").unwrap();
+        assert_eq!(entry.reason, "too slow");
+        assert!(entry.automatic);
+        assert!(denylist.denied("XllSWf").is_none());
+        assert!(denylist.denied("ll2BWz").is_some());
+
+        std::fs::write(&path, "[deny.abc]\nautomatic = true\n").unwrap();
+        assert!(Denylist::load(&path).is_err());
+    }
+
+    #[test]
+    fn allow_overrides_deny() {
+        let path = test_dir("denylist-allow").join("denylist.toml");
+        let denylist = Denylist::load(&path).unwrap();
+
+        // a shader is only on one of the lists
+        denylist
+            .add(List::Allow, "XllSWf", Entry::new("fixed", false))
+            .unwrap();
+        assert!(denylist.denied("XllSWf").is_none());
+        assert!(!denylist
+            .entries(List::Deny)
+            .iter()
+            .any(|(shader_id, _)| shader_id == "XllSWf"));
+
+        denylist
+            .add(List::Deny, "XllSWf", Entry::new("broken again", false))
+            .unwrap();
+        assert_eq!(denylist.denied("XllSWf").unwrap().reason, "broken again");
+        assert!(denylist.entries(List::Allow).is_empty());
+
+        // allowing in the file by hand overrides the denylist too
+        std::fs::write(
+            &path,
+            "[deny.abc]\nreason = \"slow\"\n\n[allow.abc]\nreason = \"fine\"\n",
+        )
+        .unwrap();
+        let denylist = Denylist::load(&path).unwrap();
+        assert!(denylist.denied("abc").is_none());
+    }
+
+    #[test]
+    fn deny_automatically() {
+        let path = test_dir("denylist-automatic").join("denylist.toml");
+        let denylist = Denylist::load(&path).unwrap();
+        denylist
+            .add(List::Allow, "MdX3Rr", Entry::new("fine", false))
+            .unwrap();
+
+        denylist.deny_automatically("4d2BDy", "timed out");
+        denylist.deny_automatically("MdX3Rr", "timed out");
+
+        let denylist = Denylist::load(&path).unwrap();
+        let entry = denylist.denied("4d2BDy").unwrap();
+        assert_eq!(entry.reason, "timed out");
+        assert!(entry.automatic);
+        assert!(denylist.denied("MdX3Rr").is_none());
+    }
+
+    #[test]
+    fn watch() {
+        let path = test_dir("denylist-watch").join("denylist.toml");
+        let denylist = Arc::new(Denylist::load(&path).unwrap());
+
+        // done in time
+        let watchdog = denylist.watch("4d2BDy", Duration::from_secs(60));
+        drop(watchdog);
+        assert!(denylist.denied("4d2BDy").is_none());
+
+        // denied while the build is still going, dropping waits for the watchdog
+        let watchdog = denylist.watch("MdX3Rr", Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(denylist.denied("MdX3Rr").unwrap().automatic);
+        drop(watchdog);
+
+        let denylist = Denylist::load(&path).unwrap();
+        assert!(denylist.denied("4d2BDy").is_none());
+        assert_eq!(
+            denylist.denied("MdX3Rr").unwrap().reason,
+            "building took longer than 0.01 s"
+        );
+    }
+}
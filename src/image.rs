@@ -0,0 +1,174 @@
+//! Images rendered offscreen, see `RenderBackend::render_to_image`, and saving them as PNG or EXR.
+
+use crate::errors::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// An RGBA image with float components, rows from the top.
+///
+/// The values are the colors written by the shader, which shadertoys treat as sRGB and not as
+/// linear. The cpu backend doesn't clamp them, so saving as EXR keeps values outside of [0,1],
+/// while the Metal backend renders to 8 bits per component like the window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Image {
+    /// A black, transparent image.
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 4]] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [[f32; 4]] {
+        &mut self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [f32; 4] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [f32; 4]) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Sets all pixels to a color.
+    pub fn fill(&mut self, color: [f32; 4]) {
+        for pixel in &mut self.pixels {
+            *pixel = color;
+        }
+    }
+
+    /// Copies an image into this one with its top left corner at `x`, `y`, clipped to this image.
+    pub fn copy_from(&mut self, image: &Image, x: usize, y: usize) {
+        let width = image.width.min(self.width.saturating_sub(x));
+        let height = image.height.min(self.height.saturating_sub(y));
+
+        for row in 0..height {
+            let src = row * image.width;
+            let dst = (y + row) * self.width + x;
+            self.pixels[dst..dst + width].copy_from_slice(&image.pixels[src..src + width]);
+        }
+    }
+
+    /// The pixels as RGBA8, clamped to [0,1].
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| {
+                pixel
+                    .iter()
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    }
+
+    /// Saves the image as EXR if the path has the `exr` extension, otherwise as PNG.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        if extension.as_deref() == Some("exr") {
+            self.save_exr(path)
+        } else {
+            self.save_png(path)
+        }
+    }
+
+    /// Saves the image as an 8-bit RGBA PNG.
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .chain_err(|| format!("failed creating image file {}", path.display()))?;
+
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.to_rgba8()))
+            .chain_err(|| format!("failed writing PNG {}", path.display()))
+    }
+
+    /// Saves the image as a 32-bit float RGBA EXR, without clamping.
+    pub fn save_exr(&self, path: &Path) -> Result<()> {
+        exr::prelude::write_rgba_file(path, self.width, self.height, |x, y| {
+            let pixel = self.pixel(x, y);
+            (pixel[0], pixel[1], pixel[2], pixel[3])
+        })
+        .map_err(|err| format!("failed writing EXR {}: {}", path.display(), err).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image whose pixels are numbered from the top left, row by row.
+    fn numbered(width: usize, height: usize) -> Image {
+        let mut image = Image::new(width, height);
+        for (index, pixel) in image.pixels_mut().iter_mut().enumerate() {
+            *pixel = [index as f32, 0.0, 0.0, 1.0];
+        }
+        image
+    }
+
+    #[test]
+    fn copy_from() {
+        let mut image = Image::new(4, 4);
+        image.copy_from(&numbered(3, 2), 1, 1);
+
+        assert_eq!(image.pixel(0, 0), [0.0; 4]);
+        assert_eq!(image.pixel(1, 1), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.pixel(3, 1), [2.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.pixel(1, 2), [3.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.pixel(3, 2), [5.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.pixel(1, 3), [0.0; 4]);
+    }
+
+    #[test]
+    fn copy_from_clipped() {
+        let mut image = Image::new(3, 3);
+        image.copy_from(&numbered(2, 2), 2, 2);
+        assert_eq!(image.pixel(2, 2), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            image
+                .pixels()
+                .iter()
+                .filter(|pixel| **pixel != [0.0; 4])
+                .count(),
+            1
+        );
+
+        // entirely outside of the image
+        let mut image = Image::new(3, 3);
+        image.copy_from(&numbered(2, 2), 3, 0);
+        image.copy_from(&numbered(2, 2), 0, 5);
+        assert!(image.pixels().iter().all(|pixel| *pixel == [0.0; 4]));
+
+        // larger than the image
+        let mut image = Image::new(2, 1);
+        image.copy_from(&numbered(3, 3), 0, 0);
+        assert_eq!(image.pixel(1, 0), [1.0, 0.0, 0.0, 1.0]);
+    }
+}
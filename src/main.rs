@@ -18,22 +18,44 @@ extern crate thread_profiler;
 #[macro_use]
 extern crate log;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use colored::*;
 use floating_duration::TimeAsFloat;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use rayon::prelude::*;
 use rust_base58::ToBase58;
-use sha3::{Digest as Sha3Digest, Sha3_256};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+mod archive;
+mod cache;
+mod check;
+mod clock;
+mod config;
+mod denylist;
+mod image;
+mod output;
+mod record;
 mod render;
+mod render_cpu;
+mod report;
+mod sandbox;
+mod stats;
+#[cfg(test)]
+mod test_util;
+mod thumbnails;
+mod translate;
+use clock::Clock;
+use config::Settings;
+use output::*;
 use render::*;
+use render_cpu::CpuRenderBackend;
+use serde::{Deserialize, Serialize};
 
 // TODO try and get rid of most of this and only depend on render_metal
 #[cfg(target_os = "macos")]
@@ -50,11 +72,8 @@ use cocoa::foundation::NSAutoreleasePool;
 
 mod errors {
     error_chain! {
-        links {
-            Shadertoy(::shadertoy::Error, ::shadertoy::ErrorKind);
-        }
-
         foreign_links {
+            Shadertoy(::shadertoy::Error);
             Fmt(::std::fmt::Error);
             Io(::std::io::Error);
             Json(::serde_json::error::Error);
@@ -62,6 +81,14 @@ mod errors {
             Reqwest(::reqwest::Error);
             Log(::log::SetLoggerError);
         }
+
+        errors {
+            /// A translation worker was stopped after the compile timeout, see `sandbox::Sandbox`.
+            WorkerTimeout(timeout: std::time::Duration) {
+                description("shader translation timed out")
+                display("shader translation timed out after {} s", timeout.as_secs_f32())
+            }
+        }
     }
 }
 use errors::*;
@@ -84,71 +111,179 @@ fn write_file<P: AsRef<Path>>(path: P, buf: &[u8]) -> Result<()> {
     Ok(())
 }
 
-fn search(client: &shadertoy::Client, matches: &clap::ArgMatches<'_>) -> Result<Vec<String>> {
+/// Shader ids found by a search, up to the limit.
+#[derive(Serialize, Deserialize, Debug)]
+struct SearchResult {
+    /// Number of shadertoys matching the search, including the ones beyond the limit.
+    total: u64,
+    ids: Vec<String>,
+}
+
+/// Searches with the search settings, or only looks up the search in the cache without a client.
+fn search(
+    client: Option<&shadertoy::Client>,
+    settings: &Settings<'_>,
+    output: &Output,
+) -> Result<SearchResult> {
     profile_scope!("search");
 
     use std::str::FromStr;
 
-    // create search parameters
+    // create search parameters, with a limit set only that many ids are requested
+
+    let limit = value_t!(settings, "limit", i64)?;
+    let limit = if limit < 0 {
+        None
+    } else {
+        Some(limit as usize)
+    };
+
+    let search_string = settings.value_of("search").unwrap_or_default();
 
     let search_params = shadertoy::SearchParams {
-        string: matches.value_of("search").unwrap_or(""),
+        string: &search_string,
 
-        sort_order: value_t!(matches, "order", shadertoy::SearchSortOrder)?,
+        sort_order: value_t!(settings, "order", shadertoy::SearchSortOrder)?,
 
-        filters: match matches.values_of("filter") {
+        filters: match settings.values_of("filter") {
             Some(args) => args
-                .map(|f| shadertoy::SearchFilter::from_str(f).unwrap())
-                .collect(),
+                .iter()
+                .map(|f| {
+                    shadertoy::SearchFilter::from_str(f)
+                        .map_err(|_| Error::from(format!("invalid search filter {}", f)))
+                })
+                .collect::<Result<_>>()?,
             None => vec![],
         },
+
+        page_size: limit.map(|limit| limit.min(shadertoy::DEFAULT_SEARCH_PAGE_SIZE)),
+
+        ..Default::default()
     };
 
-    println!("{:?}", search_params);
+    info!("Searching {:?}", search_params);
 
     // check if we can find a cached search on disk
 
-    let path = format!(
-        "output/query/{}",
-        serde_json::to_string(&search_params)?
+    let path = cache_paths(settings).query(
+        &serde_json::to_string(&(&search_params, limit))?
             .as_bytes()
-            .to_base58()
+            .to_base58(),
     );
 
-    if let Ok(mut file) = File::open(&path) {
+    // searches cached before the total was recorded as well are requested again,
+    // stale searches only when there is a client to do so
+    let use_cache = match client {
+        Some(_) => cache_policy(settings)?.is_fresh(&path),
+        None => path.exists(),
+    };
+    let cached_result = if use_cache {
         let mut json_str = String::new();
-        file.read_to_string(&mut json_str)
+        File::open(&path)?
+            .read_to_string(&mut json_str)
             .chain_err(|| "failed reading json shader file")?;
-        let search_result: serde_json::Result<Vec<String>> = serde_json::from_str(&json_str);
-        search_result.chain_err(|| "shader query json deserialization failed")
+        serde_json::from_str::<SearchResult>(&json_str).ok()
+    } else {
+        None
+    };
+    let cached = cached_result.is_some();
+
+    let result = if let Some(result) = cached_result {
+        result
     } else {
         // issue the actual request
-        match client
-            .search(&search_params)
-            .chain_err(|| "shadertoy search failed")
-        {
-            Ok(result) => {
-                // cache search results to a file on disk
-                write_file(&path, serde_json::to_string(&result)?.as_bytes())?;
-                Ok(result)
-            }
-            Err(err) => Err(err),
-        }
+        let client = client.chain_err(|| {
+            format!(
+                "search {:?} has not been downloaded, fetch it first",
+                search_params
+            )
+        })?;
+        let mut pages = client.search_pages(&search_params);
+        let ids = pages
+            .by_ref()
+            .take(limit.unwrap_or(usize::MAX))
+            .collect::<shadertoy::Result<Vec<String>>>()
+            .chain_err(|| "shadertoy search failed")?;
+        let result = SearchResult {
+            total: pages.total().unwrap_or(0),
+            ids,
+        };
+
+        // cache search results to a file on disk
+        write_file(&path, serde_json::to_string(&result)?.as_bytes())?;
+        result
+    };
+
+    output.record(Record::Search {
+        params: &search_params,
+        limit,
+        cached,
+        total: result.total,
+        ids: &result.ids,
+    })?;
+
+    Ok(result)
+}
+
+fn cache_paths(settings: &Settings<'_>) -> cache::CachePaths {
+    match settings.value_of("cache_dir") {
+        Some(cache_dir) => cache::CachePaths::new(cache_dir),
+        None => cache::CachePaths::new(cache::CachePaths::default_root()),
     }
 }
 
-fn download(
-    matches: &clap::ArgMatches<'_>,
-    render_backend: &Option<Box<dyn RenderBackend>>,
-) -> Result<Vec<BuiltShadertoy>> {
-    profile_scope!("download");
+fn denylist_path(settings: &Settings<'_>) -> PathBuf {
+    match settings.value_of("denylist") {
+        Some(path) => PathBuf::from(path),
+        None => denylist::Denylist::default_path(),
+    }
+}
 
-    let time = Instant::now();
+fn cache_policy(settings: &Settings<'_>) -> Result<cache::CachePolicy> {
+    if settings.is_present("refresh") {
+        return Ok(cache::CachePolicy::refresh());
+    }
+
+    Ok(cache::CachePolicy {
+        max_age: match settings.value_of("max_age") {
+            Some(max_age) => Some(cache::parse_duration(&max_age)?),
+            None => None,
+        },
+    })
+}
 
-    let api_key = matches.value_of("apikey").unwrap();
-    let client = shadertoy::Client::new(api_key);
+fn new_client(settings: &Settings<'_>) -> Result<shadertoy::Client> {
+    let ratelimit = value_t!(settings, "ratelimit", f64)?;
+    if !ratelimit.is_finite() || ratelimit < 0.0 {
+        bail!(
+            "invalid --ratelimit {}, expected a number of requests per second or 0 for no limit",
+            ratelimit
+        );
+    }
+
+    let api_key = settings.value_of("apikey").unwrap();
+    Ok(shadertoy::ClientBuilder::new(&api_key)
+        .base_url(&settings.value_of("baseurl").unwrap())
+        .requests_per_second(ratelimit)
+        .retry_policy(shadertoy::RetryPolicy {
+            max_retries: value_t!(settings, "retries", u32)?,
+            ..Default::default()
+        })
+        .build()?)
+}
+
+/// Searches for shadertoys while showing a progress spinner.
+fn find_shadertoys(
+    client: &shadertoy::Client,
+    settings: &Settings<'_>,
+    output: &Output,
+) -> Result<Vec<String>> {
+    let time = Instant::now();
 
     let pb = ProgressBar::new_spinner();
+    if !output.is_text() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
     pb.set_style(ProgressStyle::default_spinner().template("")); // workaround
     pb.enable_steady_tick(200);
     pb.tick(); // workaround for https://github.com/mitsuhiko/indicatif/issues/36
@@ -156,26 +291,74 @@ fn download(
         ProgressStyle::default_spinner().template("{spinner:.green}  Searching{wide_msg}"),
     );
 
-    let shadertoys_found = search(&client, matches)?;
-    let shadertoys_found_len = shadertoys_found.len();
-    let shadertoys_dl_len: i64 = matches.value_of("limit").unwrap().parse().unwrap();
-    let shadertoys_len = if shadertoys_dl_len == -1 {
-        shadertoys_found_len
-    } else {
-        shadertoys_dl_len as usize
-    };
-    let shadertoys = &shadertoys_found[0..shadertoys_len];
+    // search only returns up to `limit` ids
+    let result = search(Some(client), settings, output)?;
 
     pb.finish_with_message(&format!(
         ": {} found, {} will download [{:.2} s]",
-        shadertoys_found_len,
-        shadertoys_len,
+        result.total,
+        result.ids.len(),
         time.elapsed().as_fractional_secs()
     ));
 
+    Ok(result.ids)
+}
+
+/// The downloaded shadertoys, or only the ones found by the search if the settings have a search
+/// string, filters or a limit. Nothing is downloaded, so the search has to be fetched before.
+fn select_downloaded(settings: &Settings<'_>, output: &Output) -> Result<Vec<String>> {
+    let cache_paths = cache_paths(settings);
+
+    let has_search = settings.value_of("search").is_some()
+        || settings.values_of("filter").is_some()
+        || value_t!(settings, "limit", i64)? >= 0;
+    if !has_search {
+        return cache::downloaded_shadertoys(cache_paths.root());
+    }
+
+    let result = search(None, settings, output).chain_err(|| "search failed")?;
+    Ok(result
+        .ids
+        .into_iter()
+        .filter(|shadertoy| cache_paths.shader(shadertoy).is_file())
+        .collect())
+}
+
+/// Downloads the shadertoys and their assets and builds pipelines for them if there is a render backend.
+///
+/// Without a client nothing is downloaded, and only shadertoys already in the output directory are processed.
+fn download(
+    client: Option<&shadertoy::Client>,
+    shadertoys: &[String],
+    settings: &Settings<'_>,
+    output: &Output,
+    render_backend: &Option<Box<dyn RenderBackend>>,
+) -> Result<Vec<BuiltShadertoy>> {
+    profile_scope!("download");
+
+    let time = Instant::now();
+    let cache_policy = cache_policy(settings)?;
+    let cache_paths = cache_paths(settings);
+    let denylist = Arc::new(denylist::Denylist::load(&denylist_path(settings))?);
+    let deny_timeout = cache::parse_duration(&settings.value_of("deny_timeout").unwrap())?;
+
+    // shadertoys skipped through the settings, e.g. to leave them out of a profile
+    let skip = settings.values_of("skip").unwrap_or_default();
+    let shadertoys: Vec<String> = shadertoys
+        .iter()
+        .filter(|shadertoy| !skip.contains(shadertoy))
+        .cloned()
+        .collect();
+    let shadertoys = &shadertoys[..];
+    let shadertoys_len = shadertoys.len();
+
     let built_shadertoys = Mutex::new(Vec::<BuiltShadertoy>::new());
+    let failed_shadertoys = AtomicUsize::new(0);
 
     let pb = ProgressBar::new(shadertoys_len as u64);
+    if !output.is_text() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
     pb.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} Processing [{bar:40.cyan/blue}] {pos}/{len} {eta}")
@@ -184,13 +367,16 @@ fn download(
 
     {
         // closure for processing a shadertoy
-        let process_shadertoy = |shadertoy| -> Result<()> {
-            let path = PathBuf::from(format!("output/shader/{}/{}.json", shadertoy, shadertoy));
+        let process_shadertoy = |shadertoy| -> Result<ShadertoyStatus> {
+            let path = cache_paths.shader(shadertoy);
 
             let shader;
 
-            if !path.exists() {
+            // stale shaders are only requested again when there is a client to do so
+            if !path.exists() || (client.is_some() && !cache_policy.is_fresh(&path)) {
                 profile_scope!("shader_json_query");
+                let client = client
+                    .chain_err(|| format!("shadertoy {} has not been downloaded", shadertoy))?;
                 shader = client.get_shader(shadertoy)?;
                 write_file(&path, serde_json::to_string_pretty(&shader)?.as_bytes())?;
             } else {
@@ -209,70 +395,45 @@ fn download(
                 shader.info.likes
             );
 
+            let mut status = ShadertoyStatus {
+                id: shader.info.id.clone(),
+                name: shader.info.name.clone(),
+                username: shader.info.username.clone(),
+                pass_types: shader
+                    .renderpass
+                    .iter()
+                    .map(|pass| pass.pass_type.as_str().to_string())
+                    .collect(),
+                success: true,
+                ..Default::default()
+            };
+
             //pb.set_message(&format!("\"{}\"", shader.info.name));
 
+            let supported = report::blockers(&shader).is_empty();
+
             for pass in &shader.renderpass {
-                // generate a GLSL snippet containing the sampler declarations
-                // as they are dependent on the renderpass inputs in the JSON
-                // for exaxmple:
-                //     uniform sampler2D iChannel0;
-                //     uniform sampler2D iChannel1;
-                //     uniform sampler2D iChannel2;
-                //     uniform sampler2D iChannel3;
-
-                let mut sampler_source = String::new();
-                for input in &pass.inputs {
-                    let glsl_type = match input.ctype.as_str() {
-                        "texture" => "sampler2D",
-                        "volume" => "sampler3D",
-                        "cubemap" => "samplerCube",
-                        "buffer" => "sampler2D",
-                        "video" => "sampler2D",
-                        "webcam" => "sampler2D",
-                        "keyboard" => "sampler2D",
-                        "music" => "sampler2D",
-                        "musicstream" => "sampler2D",
-                        "mic" => "sampler2D",
-                        _ => {
-                            panic!("Unknown ctype: {}", input.ctype);
-                        }
-                    };
-                    sampler_source.push_str(&format!(
-                        "uniform {} iChannel{};\n",
-                        glsl_type, input.channel
-                    ));
+                // the common pass has no inputs, its code is included in the other passes
+                if !translate::is_translated(pass) {
+                    continue;
                 }
 
-                let header_source = include_str!("shadertoy_header.glsl");
-                let image_footer_source = include_str!("shadertoy_image_footer.glsl");
-                let sound_footer_source = include_str!("shadertoy_sound_footer.glsl");
-
-                let footer_source = match pass.pass_type.as_str() {
-                    "sound" => sound_footer_source,
-                    _ => image_footer_source,
-                };
-
-                // add our header source first which includes shadertoy constant & resource definitions
-                let full_source = format!(
-                    "{}\n{}\n{}\n{}",
-                    header_source, sampler_source, pass.code, footer_source
-                );
+                let full_source = translate::assemble_pass(&shader, pass);
 
                 // save out the source GLSL file, for debugging
-                let shader_path = format!("output/shader/{}/{}{}", shadertoy, shadertoy, pass.name);
+                let shader_path = cache_paths
+                    .shader_pass(shadertoy, &pass.name)
+                    .to_string_lossy()
+                    .into_owned();
                 let glsl_path = format!("{}.glsl", shader_path);
                 write_file(&glsl_path, full_source.as_bytes())?;
 
                 // we currently only support single-pass image shaders, with no inputs
-                if pass.pass_type == "image"
-                    && pass.inputs.is_empty()
-                    && shader.renderpass.len() == 1
-                {
-                    // these shaders get stuck in forever compilation, so let's skip them for now
-                    // TODO should make compilation more robust and be able to timeout and then remove this
-                    let skip_shaders = ["XllSWf", "ll2BWz", "4sG3Wy", "XdsBzj", "4td3z4"];
-
-                    if skip_shaders.contains(&shader.info.id.as_str()) {
+                if supported {
+                    // some shaders get stuck in compilation forever, these are on the denylist
+                    if let Some(entry) = denylist.denied(&shader.info.id) {
+                        status.success = false;
+                        status.error = Some(format!("denied: {}", entry.reason));
                         continue;
                     }
 
@@ -286,21 +447,24 @@ fn download(
                         // do not try and build it again. this is a major speed up as not all
                         // shadertoys are successfully built, and it is redundant to try and build
                         // them without nay changes
-                        let source_hash = Sha3_256::digest(full_source.as_bytes());
-                        let code_version = 1; // bump this if any of the code in new_pipeline is changed that could affect the success
-                        let error_path = PathBuf::from(&format!(
-                            "output/pipeline_fail/{}/{}",
-                            code_version,
-                            source_hash.to_base58()
-                        ));
+                        let error_path =
+                            cache_paths.pipeline_failure(&cache::source_hash(&full_source));
 
                         if error_path.exists() {
                             error!(
                                 "Skipped building failing shader for shadertoy {} ({} by {})",
                                 shader.info.id, shader.info.name, shader.info.username
                             );
+
+                            status.success = false;
+                            status.error = Some(std::fs::read_to_string(&error_path)?);
                         } else {
-                            match rb.new_pipeline(&shader_path, full_source.as_str()) {
+                            // adds the shader to the denylist if the build hangs
+                            let watchdog = denylist.watch(&shader.info.id, deny_timeout);
+                            let result = rb.new_pipeline(&shader_path, full_source.as_str());
+                            drop(watchdog);
+
+                            match result {
                                 Ok(pipeline_handle) => {
                                     info!(
                                         "Built shadertoy pipeline for {} ({} by {}) in {:.1} ms",
@@ -310,6 +474,9 @@ fn download(
                                         time.elapsed().as_fractional_millis()
                                     );
 
+                                    status.build_time_ms =
+                                        Some(time.elapsed().as_fractional_millis());
+
                                     let mut bs = built_shadertoys.lock().unwrap();
                                     bs.push(BuiltShadertoy {
                                         info: shader.info.clone(),
@@ -323,48 +490,77 @@ fn download(
                                     );
 
                                     write_file(error_path, format!("{}", err).as_bytes())?;
+
+                                    // the shaders that hang the worker would hang it every time
+                                    if let ErrorKind::WorkerTimeout(_) = *err.kind() {
+                                        denylist
+                                            .deny_automatically(&shader.info.id, &err.to_string());
+                                    }
+
+                                    status.success = false;
+                                    status.error = Some(err.to_string());
                                 }
                             }
                         }
                     }
                 }
 
-                // download texture inputs
+                // download texture inputs, cubemaps consist of multiple files
 
-                for input in &pass.inputs {
-                    match input.ctype.as_str() {
-                        "texture" | "volume" | "cubemap" | "buffer" => (),
-                        _ => continue,
-                    };
-
-                    let path = PathBuf::from(format!("output{}", input.src));
-
-                    if !path.exists() {
-                        let mut data_response = client
-                            .rest_client
-                            .get(&format!("https://www.shadertoy.com/{}", input.src))
-                            .send()?;
+                if let Some(client) = client {
+                    for input in &pass.inputs {
+                        for src in input.asset_paths() {
+                            let path = cache_paths.asset(&src);
 
-                        let mut data = vec![];
-                        data_response.read_to_end(&mut data)?;
+                            if !path.exists() {
+                                let asset = client.get_asset_file(&src)?;
 
-                        info!("Asset downloaded: {}, {} bytes", input.src, data.len());
+                                info!("Asset downloaded: {}, {} bytes", src, asset.data.len());
 
-                        write_file(&path, &data)?;
+                                write_file(&path, &asset.data)?;
+                            }
+                        }
                     }
                 }
             }
 
             pb.inc(1);
 
-            Ok(())
+            Ok(status)
         };
 
-        let threads: i64 = matches.value_of("threads").unwrap().parse().unwrap();
+        // an invalid API key will fail for every shader so stop processing instead,
+        // shaders that can't be found are skipped and other errors are only logged
+        let handle_result =
+            |shadertoy_id: &String, result: Result<ShadertoyStatus>| -> Result<()> {
+                let status = match result {
+                    Ok(status) => status,
+                    Err(err) => {
+                        match err.kind() {
+                            ErrorKind::Shadertoy(shadertoy::Error::Auth(_)) => return Err(err),
+                            ErrorKind::Shadertoy(shadertoy::Error::NotFound(_)) => {
+                                warn!("Skipped shadertoy {}: {}", shadertoy_id, err);
+                            }
+                            _ => {
+                                error!("Failed processing shadertoy {}: {}", shadertoy_id, err);
+                                failed_shadertoys.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                        ShadertoyStatus {
+                            id: shadertoy_id.clone(),
+                            error: Some(err.to_string()),
+                            ..Default::default()
+                        }
+                    }
+                };
+                output.record(Record::Shadertoy(&status))
+            };
+
+        let threads = value_t!(settings, "threads", i64)?;
 
         if threads == 0 {
             for shadertoy in shadertoys {
-                let _r_ = process_shadertoy(shadertoy);
+                handle_result(shadertoy, process_shadertoy(shadertoy))?;
             }
         } else {
             if threads > 1 {
@@ -376,7 +572,7 @@ fn download(
 
             let init_threads: Mutex<Vec<std::thread::ThreadId>> = Mutex::new(vec![]);
 
-            shadertoys.par_iter().for_each(|shadertoy| {
+            shadertoys.par_iter().try_for_each(|shadertoy| {
                 // TODO clean up & simplify this hacky way of naming the job worker threads
                 {
                     let mut it = init_threads.lock().unwrap();
@@ -386,22 +582,44 @@ fn download(
                     }
                 }
 
-                let _r_ = process_shadertoy(shadertoy);
-            });
+                handle_result(shadertoy, process_shadertoy(shadertoy))
+            })?;
         }
     }
 
     pb.finish_and_clear();
 
     let built_shadertoys = built_shadertoys.into_inner().unwrap();
+    let failed_shadertoys = failed_shadertoys.into_inner();
 
-    println!(
-        "  Processing: {} built successfully [{:.2} s]",
-        built_shadertoys.len(),
-        time.elapsed().as_fractional_secs()
-    );
+    output.record(Record::Summary(&Summary {
+        shadertoys: shadertoys_len,
+        built: built_shadertoys.len(),
+        failed: failed_shadertoys,
+        elapsed_secs: time.elapsed().as_fractional_secs(),
+    }))?;
+
+    if !output.is_text() {
+        return Ok(built_shadertoys);
+    }
+
+    if render_backend.is_some() {
+        println!(
+            "  Processing: {} built successfully, {} failed [{:.2} s]",
+            built_shadertoys.len(),
+            failed_shadertoys,
+            time.elapsed().as_fractional_secs()
+        );
+    } else {
+        println!(
+            "  Processing: {} succeeded, {} failed [{:.2} s]",
+            shadertoys_len - failed_shadertoys,
+            failed_shadertoys,
+            time.elapsed().as_fractional_secs()
+        );
+    }
 
-    if matches.is_present("verbose") {
+    if settings.is_present("verbose") {
         for shadertoy in &built_shadertoys {
             println!(
                 "{}: {} by {} ({} views, {} likes)",
@@ -423,11 +641,206 @@ fn download(
     Ok(built_shadertoys)
 }
 
+/// Translates the passes of downloaded shadertoys to the targets and saves the results next to
+/// their GLSL source, without a render backend.
+fn translate_shadertoys(
+    shadertoys: &[String],
+    targets: &[translate::Target],
+    settings: &Settings<'_>,
+    output: &Output,
+) -> Result<()> {
+    profile_scope!("translate_shadertoys");
+
+    let cache_paths = cache_paths(settings);
+    let denylist = denylist::Denylist::load(&denylist_path(settings))?;
+    let sandbox = new_sandbox(settings)?;
+    let mut passes = 0;
+    let mut failed = 0;
+    let mut denied = 0;
+
+    for shadertoy in shadertoys {
+        // the shaders that hang the compilers would hang the translation as well
+        if let Some(entry) = denylist.denied(shadertoy) {
+            warn!(
+                "Skipped translating denied shadertoy {}: {}",
+                shadertoy, entry.reason
+            );
+            if output.is_text() {
+                println!("{} skipped, denied: {}", shadertoy, entry.reason);
+            }
+            denied += 1;
+            continue;
+        }
+
+        let shader = cache::load_shader(cache_paths.root(), shadertoy)?;
+
+        for pass in &shader.renderpass {
+            if !translate::is_translated(pass) {
+                continue;
+            }
+
+            let full_source = translate::assemble_pass(&shader, pass);
+            let translation = translate_pass(&sandbox, targets, &pass.name, &full_source);
+
+            let shader_path = cache_paths
+                .shader_pass(shadertoy, &pass.name)
+                .to_string_lossy()
+                .into_owned();
+            write_file(format!("{}.glsl", shader_path), full_source.as_bytes())?;
+            for (target, source) in &translation.outputs {
+                write_file(
+                    format!("{}.{}", shader_path, target.extension()),
+                    source.as_bytes(),
+                )?;
+            }
+
+            passes += 1;
+            if !translation.success() {
+                failed += 1;
+            }
+
+            if output.is_text() {
+                println!(
+                    "{} {} ({} by {})",
+                    shadertoy, pass.name, shader.info.name, shader.info.username
+                );
+                for stage in &translation.stages {
+                    let result = if stage.success {
+                        format!("{:<6}", "ok").green()
+                    } else {
+                        format!("{:<6}", "failed").red()
+                    };
+                    println!("  {:<8} {} {:>8.1} ms", stage.stage, result, stage.time_ms);
+                    if let Some(ref diagnostics) = stage.diagnostics {
+                        for line in diagnostics.lines() {
+                            println!("    {}", line);
+                        }
+                    }
+                }
+            }
+
+            output.record(Record::Translation {
+                id: shadertoy,
+                pass: &pass.name,
+                success: translation.success(),
+                stages: &translation.stages,
+            })?;
+        }
+    }
+
+    if output.is_text() {
+        println!(
+            "Translated {} passes, {} failed, {} denied shadertoys skipped",
+            passes, failed, denied
+        );
+    }
+    Ok(())
+}
+
+/// Translates a pass in a worker process like `check` does.
+fn translate_pass(
+    sandbox: &sandbox::Sandbox,
+    targets: &[translate::Target],
+    name: &str,
+    source: &str,
+) -> translate::Translation {
+    match sandbox.translate_all(targets, name, source) {
+        Ok(translation) => translation,
+        Err(err) => translate::Translation {
+            stages: vec![check::worker_failure(&err)],
+            ..Default::default()
+        },
+    }
+}
+
+/// Subcommand to add, remove and list the entries of the deny or allow list.
+fn list_subcommand<'a, 'b>(name: &'a str, about: &'a str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Adds shadertoys to the list")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy id")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("reason")
+                        .long("reason")
+                        .value_name("text")
+                        .help("Why the shadertoys are on the list")
+                        .takes_value(true)
+                        .default_value("added by hand"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Removes shadertoys from the list")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy id")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(SubCommand::with_name("list").about("Lists the shadertoys on the list"))
+}
+
+fn edit_list(
+    settings: &Settings<'_>,
+    list: denylist::List,
+    matches: &clap::ArgMatches<'_>,
+) -> Result<()> {
+    let denylist = denylist::Denylist::load(&denylist_path(settings))?;
+
+    match matches.subcommand() {
+        ("add", Some(add_matches)) => {
+            let reason = add_matches.value_of("reason").unwrap();
+            for shader_id in add_matches.values_of("id").unwrap() {
+                denylist.add(list, shader_id, denylist::Entry::new(reason, false))?;
+            }
+        }
+        ("remove", Some(remove_matches)) => {
+            for shader_id in remove_matches.values_of("id").unwrap() {
+                if !denylist.remove(list, shader_id)? {
+                    eprintln!("Shadertoy {} is not on the list", shader_id);
+                }
+            }
+        }
+        _ => {
+            for (shader_id, entry) in denylist.entries(list) {
+                println!(
+                    "{}  {}  {}{}",
+                    shader_id,
+                    entry.added,
+                    entry.reason,
+                    if entry.automatic { " (automatic)" } else { "" }
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn run() -> Result<()> {
     let matches = App::new("Shadertoy Browser")
         .version(crate_version!())
         .author("Johan Andersson <repi@repi.se>")
         .about("Downloads and views shadertoys")
+        .arg(
+            Arg::with_name("profile")
+                .short("p")
+                .long("profile")
+                .value_name("name")
+                .help("Use the settings of a profile from the configuration files, shadertoy-browser.toml in the current directory and shadertoy-browser/config.toml in the user config directory")
+                .takes_value(true)
+                .global(true),
+        )
         .arg(
             Arg::with_name("apikey")
                 .short("k")
@@ -435,7 +848,17 @@ fn run() -> Result<()> {
                 .value_name("key")
                 .default_value("BtHtWD") // be nice and have a default key so app just works
                 .help("Set shadertoy API key to use. Create your key on https://www.shadertoy.com/myapps")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("baseurl")
+                .long("baseurl")
+                .value_name("url")
+                .default_value(shadertoy::DEFAULT_BASE_URL)
+                .help("Base URL of the Shadertoy API to use, e.g. a local mock server for testing")
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("search")
@@ -443,7 +866,8 @@ fn run() -> Result<()> {
                 .long("search")
                 .value_name("string")
                 .help("Search string to filter which shadertoys to get")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("filter")
@@ -453,7 +877,8 @@ fn run() -> Result<()> {
                 .takes_value(true)
                 .multiple(true)
                 .possible_values(&["VR", "SoundOutput", "SoundInput", "Webcam", "MultiPass", "MusicStream"])
-                .case_insensitive(true),
+                .case_insensitive(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("order")
@@ -463,7 +888,8 @@ fn run() -> Result<()> {
                 .takes_value(true)
                 .default_value("Popular")
                 .possible_values(&["Name", "Love", "Popular", "Newest", "Hot"])
-                .case_insensitive(true),
+                .case_insensitive(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("limit")
@@ -472,13 +898,119 @@ fn run() -> Result<()> {
                 .help("The maximum number of shaders to download. -1 = no limit")
                 .takes_value(true)
                 .default_value("-1")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("cache_dir")
+                .long("cache-dir")
+                .value_name("dir")
+                .help("Directory to cache downloaded shadertoys, searches and build results in, can also be set with SHADERTOY_CACHE_DIR. Defaults to shadertoy-browser in the user cache directory")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("max_age")
+                .long("max-age")
+                .value_name("age")
+                .help("Request cached searches and shaders again once they are older than this, e.g. 30m, 12h or 7d. By default they are kept forever")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("refresh")
+                .long("refresh")
+                .help("Ignore cached searches and shaders and request them again")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("skip")
+                .long("skip")
+                .value_name("id")
+                .help("Shadertoy to leave out, can be given multiple times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("denylist")
+                .long("denylist")
+                .value_name("file")
+                .help("File with the shadertoys to never build and to always build. Defaults to shadertoy-browser/denylist.toml in the user config directory")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("compile_timeout")
+                .long("compile-timeout")
+                .value_name("duration")
+                .help("Stop translating shaders that take longer than this and add them to the denylist, e.g. 30s or 2m")
+                .takes_value(true)
+                .default_value("30s")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("deny_timeout")
+                .long("deny-timeout")
+                .value_name("duration")
+                .help("Add shadertoys to the denylist when building them takes longer than this, e.g. when the Metal compiler hangs")
+                .takes_value(true)
+                .default_value("2m")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("compile_memory")
+                .long("compile-memory")
+                .value_name("size")
+                .help("Maximum memory of the worker processes that translate shaders, e.g. 512M or 4G. Only enforced on Linux")
+                .takes_value(true)
+                .default_value("4G")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .help("Render backend to build and render shadertoys with. The cpu backend renders without a GPU, but only offscreen and very slowly")
+                .takes_value(true)
+                .default_value(if cfg!(target_os = "macos") { "metal" } else { "cpu" })
+                .possible_values(&["metal", "cpu"])
+                .case_insensitive(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("threads")
                 .short("t")
                 .long("threads")
                 .help("How many threads to use for downloading & processing shaders. 0 = disables threading, -1 = use all logical processors")
-                .default_value("-1"),
+                .default_value("-1")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("ratelimit")
+                .long("ratelimit")
+                .value_name("requests")
+                .help("Maximum number of requests per second to issue to shadertoy.com. 0 = no limit")
+                .takes_value(true)
+                .default_value("10")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .help("How many times to retry requests that failed due to server or network errors")
+                .takes_value(true)
+                .default_value("3")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .help("Output format of search, download and stats results, json and ndjson are meant for scripts")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json", "ndjson"])
+                .case_insensitive(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("headless")
@@ -490,39 +1022,289 @@ fn run() -> Result<()> {
             Arg::with_name("verbose")
                 .short("v")
                 .long("verbose")
-                .help("More verbose log output, including list of all shadertoys found"),
+                .help("More verbose log output, including list of all shadertoys found")
+                .global(true),
         )
         .arg(
             Arg::with_name("res_width")
                 .long("reswidth")
                 .help("Window resolution width")
                 .takes_value(true)
-                .default_value("1024"),
+                .default_value("1024")
+                .global(true),
         )
         .arg(
             Arg::with_name("res_height")
                 .long("resheight")
                 .help("Window resolution height")
                 .takes_value(true)
-                .default_value("768"),
+                .default_value("768")
+                .global(true),
         )
         .arg(
             Arg::with_name("grid_width")
                 .long("gridwidth")
                 .help("Grid width")
                 .takes_value(true)
-                .default_value("4"),
+                .default_value("4")
+                .global(true),
         )
         .arg(
             Arg::with_name("grid_height")
                 .long("gridheight")
                 .help("Grid height")
                 .takes_value(true)
-                .default_value("4"),
+                .default_value("4")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Searches for shadertoys and prints their ids, without downloading them"),
+        )
+        .subcommand(
+            SubCommand::with_name("fetch")
+                .about("Searches for and downloads shadertoys and their assets, without building them"),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Builds pipelines for the already downloaded shadertoys, without any network access"),
+        )
+        .subcommand(
+            SubCommand::with_name("view")
+                .about("Builds and views the already downloaded shadertoys, without any network access"),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Inspects or clears the cache of downloaded shadertoys")
+                .subcommand(
+                    SubCommand::with_name("info").about("Shows the disk usage of the cache"),
+                )
+                .subcommand(
+                    SubCommand::with_name("gc")
+                        .about("Removes assets no longer used by any shader, searches older than --max-age and failures of previous pipeline code versions"),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Removes parts of the cache")
+                        .arg(
+                            Arg::with_name("section")
+                                .help("Part of the cache to remove")
+                                .required(true)
+                                .multiple(true)
+                                .possible_values(&["queries", "shaders", "media", "failures", "all"])
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(list_subcommand(
+            "deny",
+            "Manages the denylist of shadertoys that are never built, e.g. because they hang compilation",
+        ))
+        .subcommand(list_subcommand(
+            "allow",
+            "Manages the allowlist of shadertoys that are built even if on the denylist, and never added to it automatically",
+        ))
+        .subcommand(
+            SubCommand::with_name("translate")
+                .about("Translates the passes of downloaded shadertoys to other shading languages and reports the diagnostics of each stage, without a GPU")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy to translate, all downloaded shadertoys if none are given")
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .value_name("target")
+                        .help("Shading language to translate to, can be given multiple times. Defaults to all")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(&["msl", "glsl-es", "hlsl", "wgsl"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks that every pass of the downloaded shadertoys compiles, without a GPU, and reports which pass and fail")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy to check, all downloaded shadertoys if none are given")
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .value_name("target")
+                        .help("Also cross-compile to this shading language, can be given multiple times")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(&["msl", "glsl-es", "hlsl", "wgsl"]),
+                )
+                .arg(
+                    Arg::with_name("fail_under")
+                        .long("fail-under")
+                        .value_name("percent")
+                        .help("Exit with an error if less than this percentage of the shadertoys passed")
+                        .takes_value(true),
+                ),
         )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Reports why downloaded shadertoys can't be built, grouped by unsupported feature and compile error")
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Compile the shadertoys without unsupported features like check does, instead of using the recorded build failures"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Shows statistics of the downloaded shadertoys, such as pass and input types"),
+        )
+        .subcommand(
+            SubCommand::with_name("screenshot")
+                .about("Builds a downloaded shadertoy and renders a frame of it offscreen to a PNG or EXR image, at --reswidth x --resheight")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy to render")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("Image file to write, EXR if it ends with .exr and PNG otherwise")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .value_name("seconds")
+                        .help("Time to render the frame at")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("frame")
+                        .long("frame")
+                        .value_name("frame")
+                        .help("Frame number, defaults to the frame at the time at 60 frames per second")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mouse")
+                        .long("mouse")
+                        .value_name("x,y")
+                        .help("Position the mouse is held down at, in pixels from the bottom left corner")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .value_name("date")
+                        .help("Date at time 0 for iDate, in RFC 3339 format like 2021-04-01T12:00:00+02:00, defaults to now")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("thumbnails")
+                .about("Searches for, downloads and builds shadertoys like the default run, renders a thumbnail of each and composes them into contact sheets laid out like the viewer's grid")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Directory to write the thumbnails and contact sheets to")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .value_name("seconds")
+                        .help("Time to render the thumbnails at")
+                        .takes_value(true)
+                        .default_value("2"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("record")
+                .about("Builds a downloaded shadertoy and records it at a fixed frame rate, at --reswidth x --resheight, to numbered PNG images or a Y4M video")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Shadertoy to record")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("Y4M video to write if it ends with .y4m, otherwise directory to write the PNG images to")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .value_name("seconds")
+                        .help("Length of the recording")
+                        .takes_value(true)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("fps")
+                        .long("fps")
+                        .value_name("fps")
+                        .help("Frames per second")
+                        .takes_value(true)
+                        .default_value("60"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .value_name("date")
+                        .help("Date at time 0 for iDate, in RFC 3339 format like 2021-04-01T12:00:00+02:00, defaults to now")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Exports all downloaded shadertoys, their assets and cached searches to an archive file")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Archive file to write")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports shadertoys from an archive file created with export")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Archive file to read")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(sandbox::worker_subcommand())
         .get_matches();
 
-    // setup log
+    // the worker runs before the log is set up, so it doesn't truncate the log of the browser
+    if let (sandbox::WORKER_SUBCOMMAND, Some(sub_matches)) = matches.subcommand() {
+        return sandbox::run_worker(sub_matches);
+    }
+
+    let settings = Settings::load(&matches)?;
+
+    // setup log, in the cache directory to not litter the working directory
+
+    let cache_paths = cache_paths(&settings);
+    std::fs::create_dir_all(cache_paths.root()).chain_err(|| {
+        format!(
+            "failed creating cache directory {}",
+            cache_paths.root().display()
+        )
+    })?;
 
     fern::Dispatch::new()
         .format(|out, message, record| {
@@ -539,71 +1321,543 @@ fn run() -> Result<()> {
                 .write(true)
                 .create(true)
                 .append(false)
-                .open("output.log")?,
+                .open(cache_paths.log_file())?,
         )
         .apply()?;
 
-    // setup renderer
+    thread_profiler::register_thread_with_profiler();
 
-    let render_backend: Option<Box<dyn RenderBackend>>;
+    let output_dir = cache_paths.root();
+    let output = Output::new(value_t!(settings, "format", OutputFormat)?);
+
+    match matches.subcommand() {
+        ("search", Some(_)) => {
+            let client = new_client(&settings)?;
+            let result = search(Some(&client), &settings, &output).chain_err(|| "search failed")?;
+            if output.is_text() {
+                for shadertoy in result.ids {
+                    println!("{}", shadertoy);
+                }
+            }
+            output.finish()
+        }
+        ("fetch", Some(_)) => {
+            let client = new_client(&settings)?;
+            let shadertoys = find_shadertoys(&client, &settings, &output)?;
+            download(Some(&client), &shadertoys, &settings, &output, &None)
+                .chain_err(|| "downloading shaders failed")?;
+            output.finish()
+        }
+        ("build", Some(_)) => {
+            let render_backend = new_render_backend(&settings)?;
+            if render_backend.is_none() {
+                warn!("No render backend available, only generating shader sources");
+            }
+            let shadertoys = select_downloaded(&settings, &output)?;
+            download(None, &shadertoys, &settings, &output, &render_backend)
+                .chain_err(|| "building shaders failed")?;
+            write_startup_profile(&cache_paths);
+            output.finish()
+        }
+        ("view", Some(_)) => {
+            // fail before building anything with a backend that can't view
+            check_viewable(&settings)?;
+            let render_backend = new_render_backend(&settings)?;
+            let shadertoys = select_downloaded(&settings, &output)?;
+            let built_shadertoys = download(None, &shadertoys, &settings, &output, &render_backend)
+                .chain_err(|| "building shaders failed")?;
+            write_startup_profile(&cache_paths);
+            output.finish()?;
+            view(&settings, render_backend, built_shadertoys)
+        }
+        ("cache", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("gc", Some(_)) => {
+                let report = cache::gc(output_dir, &cache_policy(&settings)?)?;
+                for (name, usage) in &[
+                    ("unused assets", report.assets),
+                    ("stale searches", report.queries),
+                    ("obsolete pipeline failures", report.pipeline_failures),
+                ] {
+                    println!(
+                        "Removed {} files, {} bytes of {}",
+                        usage.files, usage.bytes, name
+                    );
+                }
+                Ok(())
+            }
+            ("clear", Some(clear_matches)) => {
+                let names: Vec<&str> = clear_matches.values_of("section").unwrap().collect();
+                for section in cache::CacheSection::ALL.iter().copied() {
+                    if names.contains(&"all") || names.contains(&section.name()) {
+                        let usage = cache::clear(output_dir, section)?;
+                        println!(
+                            "Removed {} files, {} bytes of {}",
+                            usage.files,
+                            usage.bytes,
+                            section.name()
+                        );
+                    }
+                }
+                Ok(())
+            }
+            _ => {
+                for section in cache::CacheSection::ALL.iter().copied() {
+                    let usage = cache::usage(output_dir, section)?;
+                    println!(
+                        "{:<10} {:>8} files {:>14} bytes",
+                        section.name(),
+                        usage.files,
+                        usage.bytes
+                    );
+                }
+                Ok(())
+            }
+        },
+        ("deny", Some(sub_matches)) => edit_list(&settings, denylist::List::Deny, sub_matches),
+        ("allow", Some(sub_matches)) => edit_list(&settings, denylist::List::Allow, sub_matches),
+        ("translate", Some(sub_matches)) => {
+            let shadertoys = match sub_matches.values_of("id") {
+                Some(ids) => ids.map(str::to_string).collect(),
+                None => cache::downloaded_shadertoys(output_dir)?,
+            };
+            let targets = match sub_matches.values_of("target") {
+                Some(names) => names
+                    .map(str::parse)
+                    .collect::<Result<Vec<translate::Target>>>()?,
+                None => translate::Target::ALL.to_vec(),
+            };
+            translate_shadertoys(&shadertoys, &targets, &settings, &output)?;
+            output.finish()
+        }
+        ("check", Some(sub_matches)) => {
+            let shadertoys = match sub_matches.values_of("id") {
+                Some(ids) => ids.map(str::to_string).collect(),
+                None => cache::downloaded_shadertoys(output_dir)?,
+            };
+            let targets = match sub_matches.values_of("target") {
+                Some(names) => names
+                    .map(str::parse)
+                    .collect::<Result<Vec<translate::Target>>>()?,
+                None => vec![],
+            };
+            let fail_under = match sub_matches.value_of("fail_under") {
+                Some(_) => Some(value_t!(sub_matches, "fail_under", f64)?),
+                None => None,
+            };
+
+            let verbose = settings.is_present("verbose");
+            let summary = check::check_all(
+                output_dir,
+                &shadertoys,
+                &new_sandbox(&settings)?,
+                &targets,
+                value_t!(settings, "threads", i64)?,
+                |check| {
+                    if output.is_text() {
+                        let result = if check.success {
+                            format!("{:<6}", "ok").green()
+                        } else {
+                            format!("{:<6}", "failed").red()
+                        };
+                        println!(
+                            "{} {} {} by {}",
+                            result, check.id, check.name, check.username
+                        );
+                        if let Some(error) = check.error() {
+                            // the full diagnostics can be long, only show them when verbose
+                            let mut lines = error.lines();
+                            if verbose {
+                                lines.by_ref().for_each(|line| println!("    {}", line));
+                            } else if let Some(line) = lines.next() {
+                                println!("    {}", line);
+                            }
+                        }
+                    }
+                    output.record(Record::Check(check))
+                },
+            )?;
+
+            if output.is_text() {
+                print!("{}", summary);
+            }
+            output.record(Record::CheckSummary(&summary))?;
+            output.finish()?;
+
+            match fail_under {
+                Some(fail_under) => summary.fail_under(fail_under),
+                None => Ok(()),
+            }
+        }
+        ("report", Some(sub_matches)) => {
+            let denylist = denylist::Denylist::load(&denylist_path(&settings))?;
+            let sandbox = if sub_matches.is_present("check") {
+                Some(new_sandbox(&settings)?)
+            } else {
+                None
+            };
+            let report = report::Report::collect(&cache_paths, &denylist, sandbox.as_ref())?;
+            if output.is_text() {
+                if settings.is_present("verbose") {
+                    print!("{:#}", report);
+                } else {
+                    print!("{}", report);
+                }
+            }
+            output.record(Record::Report(&report))?;
+            output.finish()
+        }
+        ("stats", Some(_)) => {
+            let stats = stats::Stats::collect(output_dir)?;
+            if output.is_text() {
+                print!("{}", stats);
+            }
+            output.record(Record::Stats(&stats))?;
+            output.finish()
+        }
+        ("screenshot", Some(sub_matches)) => {
+            let id = sub_matches.value_of("id").unwrap();
+            let file = Path::new(sub_matches.value_of("file").unwrap());
+
+            let mut clock = Clock::fixed_fps(f64::from(FrameInputs::FRAME_RATE))
+                .with_start_date(parse_date(sub_matches.value_of("date"))?);
+            clock.seek(value_t!(sub_matches, "time", f64)?);
+
+            let mouse = match sub_matches.value_of("mouse") {
+                Some(mouse) => {
+                    let (x, y) = parse_mouse(mouse)?;
+                    (x, y, x, y)
+                }
+                None => (0.0, 0.0, 0.0, 0.0),
+            };
+            let mut inputs = clock.frame_inputs(mouse);
+            if sub_matches.is_present("frame") {
+                inputs.frame = value_t!(sub_matches, "frame", i32)?;
+            }
+
+            let (render_backend, built_shadertoy) = build_shadertoy(&settings, &output, id)?;
+            let image = render_backend.render_to_image(&ImageParams {
+                pipeline_handle: built_shadertoy.pipeline_handle,
+                width: value_t!(settings, "res_width", usize)?,
+                height: value_t!(settings, "res_height", usize)?,
+                inputs,
+            })?;
+            image.save(file)?;
+
+            if output.is_text() {
+                println!(
+                    "Saved {} by {} to {}",
+                    built_shadertoy.info.name,
+                    built_shadertoy.info.username,
+                    file.display()
+                );
+            }
+            Ok(())
+        }
+        ("thumbnails", Some(sub_matches)) => {
+            let dir = Path::new(sub_matches.value_of("dir").unwrap());
+            let time = value_t!(sub_matches, "time", f64)?;
+
+            let render_backend = new_render_backend(&settings)?;
+            if render_backend.is_none() {
+                bail!("no render backend available to render the thumbnails with");
+            }
+
+            let client = new_client(&settings)?;
+            let shadertoys = find_shadertoys(&client, &settings, &output)?;
+            let built_shadertoys = download(
+                Some(&client),
+                &shadertoys,
+                &settings,
+                &output,
+                &render_backend,
+            )
+            .chain_err(|| "query for shaders failed")?;
+            output.finish()?;
+
+            let params = thumbnails::ThumbnailParams {
+                sheet_size: (
+                    value_t!(settings, "res_width", usize)?,
+                    value_t!(settings, "res_height", usize)?,
+                ),
+                grid_size: (
+                    value_t!(settings, "grid_width", usize)?,
+                    value_t!(settings, "grid_height", usize)?,
+                ),
+                time,
+            };
+
+            let pb = ProgressBar::new(built_shadertoys.len() as u64);
+            if !output.is_text() {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} Rendering [{bar:40.cyan/blue}] {pos}/{len} {eta}")
+                    .progress_chars("##-"),
+            );
+
+            let shadertoys: Vec<_> = built_shadertoys
+                .iter()
+                .map(|built_shadertoy| (&built_shadertoy.info, built_shadertoy.pipeline_handle))
+                .collect();
+            let (rendered, sheets) = thumbnails::render_thumbnails(
+                render_backend.as_ref().unwrap().as_ref(),
+                &shadertoys,
+                &params,
+                dir,
+                |info, err| {
+                    pb.inc(1);
+                    if let Some(err) = err {
+                        pb.println(format!("Failed rendering {}: {}", info.id, err));
+                    }
+                },
+            )?;
+            pb.finish_and_clear();
+
+            if output.is_text() {
+                println!(
+                    "Saved {} thumbnails and {} contact sheets to {}",
+                    rendered,
+                    sheets,
+                    dir.display()
+                );
+            }
+            Ok(())
+        }
+        ("record", Some(sub_matches)) => {
+            let id = sub_matches.value_of("id").unwrap();
+            let path = Path::new(sub_matches.value_of("path").unwrap());
+            let duration = value_t!(sub_matches, "duration", f64)?;
+            let fps = value_t!(sub_matches, "fps", u32)?;
+
+            let (render_backend, built_shadertoy) = build_shadertoy(&settings, &output, id)?;
+
+            let params = record::RecordParams {
+                pipeline_handle: built_shadertoy.pipeline_handle,
+                width: value_t!(settings, "res_width", usize)?,
+                height: value_t!(settings, "res_height", usize)?,
+                fps,
+                frames: (duration * f64::from(fps)).round() as u32,
+                start_date: parse_date(sub_matches.value_of("date"))?,
+            };
+
+            let pb = ProgressBar::new(u64::from(params.frames));
+            if !output.is_text() {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} Recording [{bar:40.cyan/blue}] {pos}/{len} {eta}")
+                    .progress_chars("##-"),
+            );
+
+            let format = record::RecordFormat::from_path(path);
+            record::record(render_backend.as_ref(), &params, path, format, |_| {
+                pb.inc(1)
+            })?;
+            pb.finish_and_clear();
+
+            if output.is_text() {
+                println!(
+                    "Recorded {} frames of {} by {} to {}",
+                    params.frames,
+                    built_shadertoy.info.name,
+                    built_shadertoy.info.username,
+                    path.display()
+                );
+            }
+            Ok(())
+        }
+        ("export", Some(sub_matches)) => {
+            let file = Path::new(sub_matches.value_of("file").unwrap());
+            let manifest =
+                archive::export(output_dir, file).chain_err(|| "exporting archive failed")?;
+            println!(
+                "Exported {} shadertoys, {} assets and {} searches to {}",
+                manifest.shaders.len(),
+                manifest.assets.len(),
+                manifest.queries.len(),
+                file.display()
+            );
+            Ok(())
+        }
+        ("import", Some(sub_matches)) => {
+            let file = Path::new(sub_matches.value_of("file").unwrap());
+            let manifest =
+                archive::import(output_dir, file).chain_err(|| "importing archive failed")?;
+            println!(
+                "Imported {} shadertoys, {} assets and {} searches from {} (created {})",
+                manifest.shaders.len(),
+                manifest.assets.len(),
+                manifest.queries.len(),
+                file.display(),
+                manifest.created
+            );
+            Ok(())
+        }
+        _ => {
+            // no subcommand does everything: search, download, build and view
+
+            // without a backend to view with, only download like without a render backend
+            let render_backend =
+                if settings.is_present("headless") || check_viewable(&settings).is_ok() {
+                    new_render_backend(&settings)?
+                } else {
+                    warn!("The cpu backend can't view shadertoys, only downloading them");
+                    None
+                };
+
+            let client = new_client(&settings)?;
+            let shadertoys = find_shadertoys(&client, &settings, &output)?;
+            let built_shadertoys = download(
+                Some(&client),
+                &shadertoys,
+                &settings,
+                &output,
+                &render_backend,
+            )
+            .chain_err(|| "query for shaders failed")?;
+
+            write_startup_profile(&cache_paths);
+            output.finish()?;
+
+            if settings.is_present("headless") {
+                return Ok(());
+            }
+
+            view(&settings, render_backend, built_shadertoys)
+        }
+    }
+}
+
+/// Builds a single downloaded shadertoy with a new render backend, for rendering it offscreen.
+fn build_shadertoy(
+    settings: &Settings<'_>,
+    output: &Output,
+    id: &str,
+) -> Result<(Box<dyn RenderBackend>, BuiltShadertoy)> {
+    let render_backend = new_render_backend(settings)?;
+    if render_backend.is_none() {
+        bail!("no render backend available to render with");
+    }
+
+    let built_shadertoys = download(None, &[id.to_string()], settings, output, &render_backend)
+        .chain_err(|| "building shaders failed")?;
+    output.finish()?;
+
+    let built_shadertoy = built_shadertoys
+        .into_iter()
+        .find(|built_shadertoy| built_shadertoy.info.id == id)
+        .chain_err(|| format!("shadertoy {} failed to build", id))?;
+    Ok((render_backend.unwrap(), built_shadertoy))
+}
+
+/// Parses a date in RFC 3339 format, or now if there is none.
+fn parse_date(date: Option<&str>) -> Result<chrono::DateTime<chrono::Local>> {
+    match date {
+        Some(date) => Ok(chrono::DateTime::parse_from_rfc3339(date)
+            .chain_err(|| format!("invalid date {}, expected RFC 3339", date))?
+            .with_timezone(&chrono::Local)),
+        None => Ok(chrono::Local::now()),
+    }
+}
+
+/// Parses a mouse position given as `x,y`.
+fn parse_mouse(mouse: &str) -> Result<(f32, f32)> {
+    let coords = mouse
+        .split(',')
+        .map(|coord| coord.trim().parse::<f32>())
+        .collect::<std::result::Result<Vec<f32>, _>>();
+
+    match coords {
+        Ok(ref coords) if coords.len() == 2 => Ok((coords[0], coords[1])),
+        _ => bail!("invalid mouse position {}, expected x,y", mouse),
+    }
+}
+
+fn new_sandbox(settings: &Settings<'_>) -> Result<sandbox::Sandbox> {
+    Ok(sandbox::Sandbox {
+        timeout: cache::parse_duration(&settings.value_of("compile_timeout").unwrap())?,
+        memory_limit: match settings.value_of("compile_memory") {
+            Some(size) => Some(sandbox::parse_size(&size)?),
+            None => None,
+        },
+    })
+}
+
+fn new_render_backend(settings: &Settings<'_>) -> Result<Option<Box<dyn RenderBackend>>> {
+    let sandbox = new_sandbox(settings)?;
+
+    if is_cpu_backend(settings) {
+        return Ok(Some(Box::new(CpuRenderBackend::new(
+            sandbox,
+            value_t!(settings, "res_width", usize)?,
+            value_t!(settings, "res_height", usize)?,
+        ))));
+    }
 
     #[cfg(target_os = "macos")]
     {
-        match MetalRenderBackend::new() {
-            Ok(rb) => render_backend = Some(Box::new(rb)),
+        match MetalRenderBackend::new(sandbox) {
+            Ok(rb) => Ok(Some(Box::new(rb))),
             Err(err) => {
-                render_backend = None;
-                println!("Unable to create metal render backend, error: {}", err);
+                eprintln!("Unable to create metal render backend, error: {}", err);
+                Ok(None)
             }
         }
     }
 
     #[cfg(not(target_os = "macos"))]
     {
-        render_backend = None;
+        let _ = sandbox;
+        bail!("the metal backend is only available on macOS, use --backend cpu");
     }
+}
 
-    thread_profiler::register_thread_with_profiler();
-
-    // download and process assets
+fn write_startup_profile(cache_paths: &cache::CachePaths) {
+    let time = Instant::now();
+    let file_name = cache_paths.profile_file();
+    thread_profiler::write_profile(&file_name.to_string_lossy());
+    info!(
+        "Saved profiler log to \"{}\" [{:.1} ms]",
+        file_name.display(),
+        time.elapsed().as_fractional_millis()
+    );
+}
 
-    let mut built_shadertoy_shaders =
-        download(&matches, &render_backend).chain_err(|| "query for shaders failed")?;
+fn is_cpu_backend(settings: &Settings<'_>) -> bool {
+    settings.value_of("backend").unwrap().to_lowercase() == "cpu"
+}
 
-    // write out profiler data for startup
-    {
-        let time = Instant::now();
-        let file_name = "profile-startup.json";
-        thread_profiler::write_profile(file_name);
-        info!(
-            "Saved profiler log to \"{}\" [{:.1} ms]",
-            file_name,
-            time.elapsed().as_fractional_millis()
-        );
+/// Fails if the selected backend can't render to a window.
+fn check_viewable(settings: &Settings<'_>) -> Result<()> {
+    if is_cpu_backend(settings) {
+        bail!("the cpu backend only renders offscreen and can't view shadertoys, use --backend metal on macOS");
     }
+    Ok(())
+}
 
-    if built_shadertoy_shaders.is_empty() || matches.is_present("headless") {
+/// Opens a window to browse the built shadertoys in, runs until the window is closed.
+fn view(
+    settings: &Settings<'_>,
+    render_backend: Option<Box<dyn RenderBackend>>,
+    mut built_shadertoy_shaders: Vec<BuiltShadertoy>,
+) -> Result<()> {
+    if built_shadertoy_shaders.is_empty() {
         return Ok(());
     }
 
     let mut render_backend =
         render_backend.chain_err(|| "skipping rendering, as have no renderer available")?;
+    check_viewable(settings)?;
 
     // set up rendering window
 
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
         .with_inner_size(winit::dpi::LogicalSize::new(
-            matches
-                .value_of("res_width")
-                .unwrap()
-                .parse::<f64>()
-                .unwrap(),
-            matches
-                .value_of("res_height")
-                .unwrap()
-                .parse::<f64>()
-                .unwrap(),
+            value_t!(settings, "res_width", f64)?,
+            value_t!(settings, "res_height", f64)?,
         ))
         .with_title("Shadertoy Browser".to_string())
         .build(&event_loop)
@@ -619,19 +1873,13 @@ fn run() -> Result<()> {
     let mut mouse_click_pos = (0.0f64, 0.0f64);
     let mut mouse_lmb_pressed = false;
 
+    let mut clock = Clock::real_time();
+
     let mut shadertoy_index = 0usize;
     let mut draw_grid = true;
     let grid_size = (
-        matches
-            .value_of("grid_width")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap(),
-        matches
-            .value_of("grid_height")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap(),
+        value_t!(settings, "grid_width", usize)?,
+        value_t!(settings, "grid_height", usize)?,
     );
 
     // frame loop
@@ -693,6 +1941,18 @@ fn run() -> Result<()> {
                         Some(winit::event::VirtualKeyCode::Space) => {
                             draw_grid = !draw_grid;
                         }
+                        Some(winit::event::VirtualKeyCode::P) => {
+                            clock.set_paused(!clock.is_paused());
+                        }
+                        Some(winit::event::VirtualKeyCode::R) => {
+                            clock.seek(0.0);
+                        }
+                        Some(winit::event::VirtualKeyCode::Up) => {
+                            clock.set_speed(clock.speed() * 2.0);
+                        }
+                        Some(winit::event::VirtualKeyCode::Down) => {
+                            clock.set_speed(clock.speed() / 2.0);
+                        }
                         Some(winit::event::VirtualKeyCode::Return) => {
                             if let Some(ref shadertoy) =
                                 built_shadertoy_shaders.get_mut(shadertoy_index)
@@ -739,14 +1999,10 @@ fn run() -> Result<()> {
 
                     for index in 0..shadertoy_increment {
                         if let Some(shadertoy) = built_shadertoy_shaders.get(start_index + index) {
-                            let grid_pos = (index % grid_size.0, index / grid_size.0);
-
+                            let (pos, size) = grid_cell(index, grid_size);
                             quads.push(RenderQuad {
-                                pos: (
-                                    (grid_pos.0 as f32) / (grid_size.0 as f32),
-                                    (grid_pos.1 as f32) / (grid_size.1 as f32),
-                                ),
-                                size: (1.0 / (grid_size.0 as f32), 1.0 / (grid_size.1 as f32)),
+                                pos,
+                                size,
                                 pipeline_handle: shadertoy.pipeline_handle,
                             });
                         }
@@ -784,11 +2040,13 @@ fn run() -> Result<()> {
                 // render and present the frame
 
                 render_backend.render_frame(RenderParams {
+                    clock: &clock,
                     clear_color: (0.0, 0.0, 0.0, 0.0),
                     mouse_pos: mouse_pressed_pos,
                     mouse_click_pos,
                     quads: &quads,
                 });
+                clock.advance();
                 #[cfg(target_os = "macos")]
                 unsafe {
                     //            msg_send![pool, release];
@@ -0,0 +1,131 @@
+//! Machine-readable output of search and download results, selected with `--format`,
+//! so scripts don't have to scrape the text output.
+//!
+//! `json` prints a single array with all records when done, `ndjson` prints every record on
+//! its own line as soon as it is available. Each record has a `type` field identifying it.
+
+use crate::check::{CheckSummary, ShaderCheck};
+use crate::errors::*;
+use crate::report::Report;
+use crate::stats::Stats;
+use crate::translate::StageReport;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => bail!("unknown output format {}", s),
+        }
+    }
+}
+
+/// Download and build status of a single shadertoy.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ShadertoyStatus {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    pub pass_types: Vec<String>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Time it took to build the pipeline, if one was built.
+    pub build_time_ms: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Summary {
+    pub shadertoys: usize,
+    pub built: usize,
+    pub failed: usize,
+    pub elapsed_secs: f64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record<'a> {
+    Search {
+        params: &'a shadertoy::SearchParams<'a>,
+        limit: Option<usize>,
+        /// True if the results were read from the query cache.
+        cached: bool,
+        /// Number of shadertoys matching the search, `ids` only has up to `limit` of them.
+        total: u64,
+        ids: &'a [String],
+    },
+    Shadertoy(&'a ShadertoyStatus),
+    Summary(&'a Summary),
+    /// Translation of a pass with the `translate` subcommand.
+    Translation {
+        id: &'a str,
+        pass: &'a str,
+        success: bool,
+        stages: &'a [StageReport],
+    },
+    Stats(&'a Stats),
+    Check(&'a ShaderCheck),
+    CheckSummary(&'a CheckSummary),
+    Report(&'a Report),
+}
+
+pub struct Output {
+    format: OutputFormat,
+    records: Mutex<Vec<serde_json::Value>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Output {
+        Output {
+            format,
+            records: Mutex::new(vec![]),
+        }
+    }
+
+    /// True if output is for humans, in which case records are ignored.
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    pub fn record(&self, record: Record<'_>) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => {
+                self.records
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::to_value(&record)?);
+                Ok(())
+            }
+            OutputFormat::Ndjson => {
+                let line = serde_json::to_string(&record)?;
+                let stdout = std::io::stdout();
+                let mut stdout = stdout.lock();
+                writeln!(stdout, "{}", line)?;
+                stdout.flush()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints all records collected for the `json` format.
+    pub fn finish(&self) -> Result<()> {
+        if self.format == OutputFormat::Json {
+            let records = std::mem::take(&mut *self.records.lock().unwrap());
+            println!("{}", serde_json::to_string_pretty(&records)?);
+        }
+        Ok(())
+    }
+}
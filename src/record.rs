@@ -0,0 +1,270 @@
+//! Recording of shadertoys to image sequences and video, for the `record` subcommand.
+//!
+//! Frames are rendered offscreen with a fixed step `Clock`, so `iTime`, `iFrame` and `iTimeDelta`
+//! of every frame only depend on its number and a recording is the same however long rendering
+//! takes.
+
+use crate::clock::Clock;
+use crate::errors::*;
+use crate::image::Image;
+use crate::render::*;
+use chrono::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What a recording is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// Numbered PNG images in a directory.
+    PngSequence,
+    /// A YUV4MPEG2 video, which ffmpeg and most video tools read.
+    Y4m,
+}
+
+impl RecordFormat {
+    /// Y4M for paths with the `y4m` extension, otherwise a directory of PNG images.
+    pub fn from_path(path: &Path) -> RecordFormat {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        if extension.as_deref() == Some("y4m") {
+            RecordFormat::Y4m
+        } else {
+            RecordFormat::PngSequence
+        }
+    }
+}
+
+pub struct RecordParams {
+    pub pipeline_handle: RenderPipelineHandle,
+    pub width: usize,
+    pub height: usize,
+    pub fps: u32,
+    /// Number of frames to record, starting at time 0.
+    pub frames: u32,
+    /// Date of the first frame, see `Clock::with_start_date`.
+    pub start_date: DateTime<Local>,
+}
+
+/// Path of a frame of a PNG sequence in a directory.
+pub fn frame_path(dir: &Path, frame: u32) -> PathBuf {
+    dir.join(format!("frame-{:05}.png", frame))
+}
+
+/// Renders the frames of a recording and writes them to `path` as `format`, calling `on_frame`
+/// with the number of each frame after it is written.
+pub fn record<F>(
+    render_backend: &dyn RenderBackend,
+    params: &RecordParams,
+    path: &Path,
+    format: RecordFormat,
+    mut on_frame: F,
+) -> Result<()>
+where
+    F: FnMut(u32),
+{
+    profile_scope!("record");
+
+    if params.fps == 0 {
+        bail!("the frame rate must be at least 1 frame per second");
+    }
+
+    let mut y4m = match format {
+        RecordFormat::PngSequence => {
+            std::fs::create_dir_all(path)
+                .chain_err(|| format!("failed creating directory {}", path.display()))?;
+            None
+        }
+        RecordFormat::Y4m => {
+            let file = File::create(path)
+                .chain_err(|| format!("failed creating video file {}", path.display()))?;
+            Some(Y4mWriter::new(
+                BufWriter::new(file),
+                params.width,
+                params.height,
+                params.fps,
+            )?)
+        }
+    };
+
+    let mut clock = Clock::fixed_fps(f64::from(params.fps)).with_start_date(params.start_date);
+    for frame in 0..params.frames {
+        let image = render_backend
+            .render_to_image(&ImageParams {
+                pipeline_handle: params.pipeline_handle,
+                width: params.width,
+                height: params.height,
+                inputs: clock.frame_inputs((0.0, 0.0, 0.0, 0.0)),
+            })
+            .chain_err(|| format!("failed rendering frame {}", frame))?;
+
+        match y4m {
+            Some(ref mut y4m) => y4m.write_frame(&image)?,
+            None => image.save(&frame_path(path, frame))?,
+        }
+        on_frame(frame);
+        clock.advance();
+    }
+
+    if let Some(y4m) = y4m {
+        y4m.finish()?;
+    }
+    Ok(())
+}
+
+/// Writes images as the frames of a YUV4MPEG2 video with 4:2:0 chroma subsampling, converted
+/// from RGB with the limited range BT.601 coefficients players assume for such videos.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the header of a progressive video with square pixels.
+    pub fn new(mut writer: W, width: usize, height: usize, fps: u32) -> Result<Y4mWriter<W>> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg",
+            width, height, fps
+        )?;
+        Ok(Y4mWriter {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> Result<()> {
+        if image.width() != self.width || image.height() != self.height {
+            bail!(
+                "frame of {}x{} pixels in a video of {}x{}",
+                image.width(),
+                image.height(),
+                self.width,
+                self.height
+            );
+        }
+
+        let (width, height) = (self.width, self.height);
+        let rgb = |x: usize, y: usize| {
+            let pixel = image.pixel(x.min(width - 1), y.min(height - 1));
+            [
+                pixel[0].clamp(0.0, 1.0),
+                pixel[1].clamp(0.0, 1.0),
+                pixel[2].clamp(0.0, 1.0),
+            ]
+        };
+
+        let mut luma = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = rgb(x, y);
+                luma.push(to_u8(16.0 + 219.0 * (0.299 * r + 0.587 * g + 0.114 * b)));
+            }
+        }
+
+        // chroma of the average color of every 2x2 block, odd sizes repeat the last row or column
+        let chroma_size = (width.div_ceil(2), height.div_ceil(2));
+        let mut cb = Vec::with_capacity(chroma_size.0 * chroma_size.1);
+        let mut cr = Vec::with_capacity(chroma_size.0 * chroma_size.1);
+        for y in 0..chroma_size.1 {
+            for x in 0..chroma_size.0 {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let color = rgb(x * 2 + dx, y * 2 + dy);
+                    for (sum, c) in sum.iter_mut().zip(&color) {
+                        *sum += c / 4.0;
+                    }
+                }
+                let [r, g, b] = sum;
+                cb.push(to_u8(
+                    128.0 + 224.0 * (-0.168_736 * r - 0.331_264 * g + 0.5 * b),
+                ));
+                cr.push(to_u8(
+                    128.0 + 224.0 * (0.5 * r - 0.418_688 * g - 0.081_312 * b),
+                ));
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&luma)?;
+        self.writer.write_all(&cb)?;
+        self.writer.write_all(&cr)?;
+        Ok(())
+    }
+
+    /// Flushes the frames written so far.
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"YUV4MPEG2 W3 H3 F30:1 Ip A1:1 C420jpeg\n";
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            RecordFormat::from_path(Path::new("out.Y4M")),
+            RecordFormat::Y4m
+        );
+        assert_eq!(
+            RecordFormat::from_path(Path::new("out")),
+            RecordFormat::PngSequence
+        );
+        assert_eq!(
+            frame_path(Path::new("out"), 12),
+            Path::new("out/frame-00012.png")
+        );
+    }
+
+    #[test]
+    fn y4m_frames() {
+        // a red left column on blue, of odd size so the last chroma row and column are repeated
+        let mut image = Image::new(3, 3);
+        image.fill([0.0, 0.0, 1.0, 1.0]);
+        for y in 0..3 {
+            image.set_pixel(0, y, [1.0, 0.0, 0.0, 1.0]);
+        }
+        let mut white = Image::new(3, 3);
+        white.fill([2.0, 2.0, 2.0, 1.0]);
+
+        let mut bytes = vec![];
+        let mut writer = Y4mWriter::new(&mut bytes, 3, 3, 30).unwrap();
+        writer.write_frame(&image).unwrap();
+        writer.write_frame(&white).unwrap();
+        assert!(writer.write_frame(&Image::new(4, 3)).is_err());
+        writer.finish().unwrap();
+
+        // a 3x3 luma plane and 2x2 chroma planes per frame
+        let frame_len = b"FRAME\n".len() + 9 + 4 + 4;
+        assert_eq!(bytes.len(), HEADER.len() + 2 * frame_len);
+        assert_eq!(&bytes[..HEADER.len()], HEADER);
+
+        let frame = &bytes[HEADER.len()..HEADER.len() + frame_len];
+        let (tag, planes) = frame.split_at(6);
+        assert_eq!(tag, b"FRAME\n");
+        let (luma, chroma) = planes.split_at(9);
+        assert_eq!(luma, &[81, 41, 41, 81, 41, 41, 81, 41, 41]);
+        // the left blocks average red and blue, the right ones repeat the blue column
+        let (cb, cr) = chroma.split_at(4);
+        assert_eq!(cb, &[165, 240, 165, 240]);
+        assert_eq!(cr, &[175, 110, 175, 110]);
+
+        // colors are clamped, white has neutral chroma
+        let frame = &bytes[HEADER.len() + frame_len..];
+        assert_eq!(&frame[6..15], &[235; 9]);
+        assert_eq!(&frame[15..], &[128; 8]);
+    }
+}
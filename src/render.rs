@@ -1,7 +1,11 @@
 use std::any::Any;
 
+use crate::clock::Clock;
 use crate::errors::*;
+use crate::image::Image;
+use chrono::prelude::*;
 
+/// The uniforms of a shadertoy, laid out like the std140 uniform block in `shadertoy_header.glsl`.
 #[repr(C)]
 #[allow(non_snake_case)]
 pub struct ShadertoyConstants {
@@ -21,16 +25,70 @@ pub struct ShadertoyConstants {
     /// Current frame.
     pub iFrame: i32,
     pub pad2: [i32; 3],
-    /// Year, month, day, time in seconds in .xyzw
-    pub iDate: (f32, f32, f32, f32),
-    /// Time for channel (if video or sound), in seconds.
-    pub iChannelTime: [f32; 4],
+    /// Time for channel (if video or sound), in seconds, in x as array elements are 16 bytes.
+    pub iChannelTime: [(f32, f32, f32, f32); 4],
     /// Input texture resolution for each channel.
     pub iChannelResolution: [(f32, f32, f32, f32); 4],
+    /// Year, month, day, time in seconds in .xyzw
+    pub iDate: (f32, f32, f32, f32),
     pub iBlockOffset: f32,
     pub pad3: [f32; 3],
 }
 
+impl ShadertoyConstants {
+    /// Constants of rendering at `resolution` in pixels, which all backends use so that
+    /// shadertoys render the same on every backend and offscreen.
+    pub fn new(resolution: (f32, f32), inputs: &FrameInputs) -> ShadertoyConstants {
+        let time = inputs.time;
+        let date = inputs.date;
+
+        ShadertoyConstants {
+            iResolution: (resolution.0, resolution.1, 1.0),
+            pad1: 0.0,
+            iMouse: inputs.mouse,
+            iTime: time,
+            iTimeDelta: inputs.time_delta,
+            iFrameRate: if inputs.time_delta > 0.0 {
+                1.0 / inputs.time_delta
+            } else {
+                0.0
+            },
+            iSampleRate: 44100.0,
+            iFrame: inputs.frame,
+            pad2: [0, 0, 0],
+            iChannelTime: [(time, 0.0, 0.0, 0.0); 4], // TODO not correct
+            iChannelResolution: [(0.0, 0.0, 0.0, 0.0); 4],
+            // like on shadertoy.com, months start at 0 and the time is in seconds since midnight
+            iDate: (
+                date.year() as f32,
+                date.month0() as f32,
+                date.day() as f32,
+                date.num_seconds_from_midnight() as f32 + date.nanosecond() as f32 * 1e-9,
+            ),
+            iBlockOffset: 0.0,
+            pad3: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// Time and mouse state of a frame, the constants of every quad are computed from.
+pub struct FrameInputs {
+    /// Current time in seconds.
+    pub time: f32,
+    /// Time since the last frame in seconds.
+    pub time_delta: f32,
+    pub frame: i32,
+    /// xy is the position of the mouse while a button is down and zw where it was clicked, in
+    /// pixels from the bottom left corner, see `iMouse`.
+    pub mouse: (f32, f32, f32, f32),
+    pub date: DateTime<Local>,
+}
+
+impl FrameInputs {
+    /// Frame rate frames are assumed to be rendered at when only the time is known.
+    pub const FRAME_RATE: f32 = 60.0;
+}
+
 pub type RenderPipelineHandle = usize;
 
 pub struct RenderQuad {
@@ -42,16 +100,42 @@ pub struct RenderQuad {
     pub pipeline_handle: RenderPipelineHandle,
 }
 
+/// Position and size of the cell at `index` of a grid of `grid_size` columns and rows, in
+/// normalized [0,1] coordinates like `RenderQuad`. Cells are filled row by row from the top left.
+pub fn grid_cell(index: usize, grid_size: (usize, usize)) -> ((f32, f32), (f32, f32)) {
+    let grid_pos = (index % grid_size.0, index / grid_size.0);
+    (
+        (
+            (grid_pos.0 as f32) / (grid_size.0 as f32),
+            (grid_pos.1 as f32) / (grid_size.1 as f32),
+        ),
+        (1.0 / (grid_size.0 as f32), 1.0 / (grid_size.1 as f32)),
+    )
+}
+
 pub struct RenderParams<'a> {
+    /// Time of the frame, the caller advances it after rendering.
+    pub clock: &'a Clock,
     pub clear_color: (f32, f32, f32, f32),
     pub mouse_pos: (f64, f64),
     pub mouse_click_pos: (f64, f64),
     pub quads: &'a [RenderQuad],
 }
 
+/// Parameters of rendering a pipeline offscreen, see `RenderBackend::render_to_image`.
+pub struct ImageParams {
+    pub pipeline_handle: RenderPipelineHandle,
+    pub width: usize,
+    pub height: usize,
+    pub inputs: FrameInputs,
+}
+
 pub trait RenderBackend: Sync {
     fn init_window(&mut self, window: &dyn Any);
     fn render_frame(&mut self, params: RenderParams<'_>);
 
+    /// Renders a pipeline into an image without a window, the same way `render_frame` renders a quad.
+    fn render_to_image(&self, params: &ImageParams) -> Result<Image>;
+
     fn new_pipeline(&self, shader_path: &str, shader_source: &str) -> Result<RenderPipelineHandle>;
 }
@@ -0,0 +1,1781 @@
+//! Software render backend that runs shadertoys on the CPU, so they can be rendered without a GPU.
+//!
+//! Passes are compiled to SPIR-V in a worker process, like for the Metal backend, and parsed into
+//! naga's IR, which is interpreted for every pixel. This is orders of magnitude slower than a GPU,
+//! so it is meant for small images such as thumbnails and golden-image tests, not for viewing.
+//!
+//! Textures aren't supported yet, and derivatives such as `dFdx` and `fwidth` are always zero as
+//! pixels are shaded one at a time.
+
+use crate::errors::*;
+use crate::image::Image;
+use crate::render::*;
+use crate::sandbox::Sandbox;
+use naga::{
+    BinaryOperator, Binding, Block, BuiltIn, Expression, Function, Handle, MathFunction,
+    RelationalFunction, ScalarKind, ScalarValue, Statement, StorageClass, TypeInner, UnaryOperator,
+};
+use rayon::prelude::*;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Statements and loop iterations a single pixel may execute, to stop shaders that never finish.
+const MAX_STEPS: u64 = 1 << 22;
+
+/// Color of the quads a shader failed on.
+const ERROR_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    Float(f32),
+    Sint(i32),
+    Uint(u32),
+    Bool(bool),
+}
+
+/// Two to four components of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Vector {
+    len: usize,
+    components: [Scalar; 4],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Scalar(Scalar),
+    Vector(Vector),
+    /// Members of a struct, elements of an array or columns of a matrix.
+    Composite(Vec<Value>),
+    Pointer(Pointer),
+    /// Images and samplers, which can be declared but not sampled.
+    Opaque,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Root {
+    Global(usize),
+    /// Index in `Invocation::locals`, which has the local variables of all functions being called.
+    Local(usize),
+}
+
+/// Pointer to a variable, or to a component of it by the indices in `path`.
+#[derive(Debug, Clone, PartialEq)]
+struct Pointer {
+    root: Root,
+    path: Vec<usize>,
+}
+
+impl Scalar {
+    fn float(self) -> Result<f32> {
+        match self {
+            Scalar::Float(value) => Ok(value),
+            _ => bail!("expected a float instead of {:?}", self),
+        }
+    }
+
+    fn bool(self) -> Result<bool> {
+        match self {
+            Scalar::Bool(value) => Ok(value),
+            _ => bail!("expected a bool instead of {:?}", self),
+        }
+    }
+
+    fn index(self) -> Result<usize> {
+        match self {
+            Scalar::Sint(value) if value >= 0 => Ok(value as usize),
+            Scalar::Uint(value) => Ok(value as usize),
+            _ => bail!("invalid index {:?}", self),
+        }
+    }
+
+    fn zero(kind: ScalarKind) -> Scalar {
+        match kind {
+            ScalarKind::Float => Scalar::Float(0.0),
+            ScalarKind::Sint => Scalar::Sint(0),
+            ScalarKind::Uint => Scalar::Uint(0),
+            ScalarKind::Bool => Scalar::Bool(false),
+        }
+    }
+
+    fn kind(self) -> ScalarKind {
+        match self {
+            Scalar::Float(_) => ScalarKind::Float,
+            Scalar::Sint(_) => ScalarKind::Sint,
+            Scalar::Uint(_) => ScalarKind::Uint,
+            Scalar::Bool(_) => ScalarKind::Bool,
+        }
+    }
+}
+
+impl Value {
+    fn float(value: f32) -> Value {
+        Value::Scalar(Scalar::Float(value))
+    }
+
+    /// A float vector, or a float scalar if there is only one value.
+    fn floats(values: &[f32]) -> Value {
+        let scalars: Vec<Scalar> = values.iter().map(|value| Scalar::Float(*value)).collect();
+        Value::from_scalars(&scalars)
+    }
+
+    fn from_scalars(scalars: &[Scalar]) -> Value {
+        if let [scalar] = scalars {
+            return Value::Scalar(*scalar);
+        }
+        let mut components = [Scalar::Bool(false); 4];
+        components[..scalars.len()].copy_from_slice(scalars);
+        Value::Vector(Vector {
+            len: scalars.len(),
+            components,
+        })
+    }
+
+    fn scalar(&self) -> Result<Scalar> {
+        match self {
+            Value::Scalar(scalar) => Ok(*scalar),
+            _ => bail!("expected a scalar instead of {:?}", self),
+        }
+    }
+
+    /// Components of a scalar or vector.
+    fn scalars(&self) -> Result<&[Scalar]> {
+        match self {
+            Value::Scalar(scalar) => Ok(std::slice::from_ref(scalar)),
+            Value::Vector(vector) => Ok(&vector.components[..vector.len]),
+            _ => bail!("expected a scalar or vector instead of {:?}", self),
+        }
+    }
+
+    fn to_floats(&self) -> Result<Vec<f32>> {
+        self.scalars()?
+            .iter()
+            .map(|scalar| scalar.float())
+            .collect()
+    }
+
+    fn to_matrix(&self) -> Result<Vec<Vec<f32>>> {
+        match self {
+            Value::Composite(columns) => columns.iter().map(Value::to_floats).collect(),
+            _ => bail!("expected a matrix instead of {:?}", self),
+        }
+    }
+
+    fn from_matrix(columns: &[Vec<f32>]) -> Value {
+        Value::Composite(columns.iter().map(|column| Value::floats(column)).collect())
+    }
+
+    fn pointer(self) -> Result<Pointer> {
+        match self {
+            Value::Pointer(pointer) => Ok(pointer),
+            _ => bail!("expected a pointer instead of {:?}", self),
+        }
+    }
+
+    /// Component of a vector or composite, or a pointer to the component of a pointer.
+    fn access(self, index: usize) -> Result<Value> {
+        match self {
+            Value::Pointer(mut pointer) => {
+                pointer.path.push(index);
+                Ok(Value::Pointer(pointer))
+            }
+            Value::Vector(vector) if index < vector.len => {
+                Ok(Value::Scalar(vector.components[index]))
+            }
+            Value::Composite(mut values) if index < values.len() => Ok(values.swap_remove(index)),
+            _ => bail!("index {} out of bounds of {:?}", index, self),
+        }
+    }
+}
+
+/// Applies `f` to the components of the arguments, which are scalars, vectors of the same size,
+/// or composites such as matrices. Scalar arguments are used for every component.
+fn componentwise(args: &[&Value], f: &dyn Fn(&[Scalar]) -> Result<Scalar>) -> Result<Value> {
+    let composite_len = args.iter().find_map(|arg| match arg {
+        Value::Composite(values) => Some(values.len()),
+        _ => None,
+    });
+    if let Some(len) = composite_len {
+        let values = (0..len)
+            .map(|index| {
+                let elements = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Value::Composite(values) => {
+                            values.get(index).chain_err(|| "mismatched operands")
+                        }
+                        _ => Ok(*arg),
+                    })
+                    .collect::<Result<Vec<&Value>>>()?;
+                componentwise(&elements, f)
+            })
+            .collect::<Result<Vec<Value>>>()?;
+        return Ok(Value::Composite(values));
+    }
+
+    let len = args
+        .iter()
+        .map(|arg| match arg {
+            Value::Vector(vector) => vector.len,
+            _ => 1,
+        })
+        .max()
+        .unwrap_or(1);
+
+    let mut components = [Scalar::Bool(false); 4];
+    let mut scalars = [Scalar::Bool(false); 4];
+    for (index, component) in components.iter_mut().enumerate().take(len) {
+        for (scalar, arg) in scalars.iter_mut().zip(args) {
+            *scalar = match arg {
+                Value::Scalar(scalar) => *scalar,
+                Value::Vector(vector) if vector.len == len => vector.components[index],
+                _ => bail!("mismatched operands {:?}", args),
+            };
+        }
+        *component = f(&scalars[..args.len()])?;
+    }
+
+    Ok(if len == 1 {
+        Value::Scalar(components[0])
+    } else {
+        Value::Vector(Vector { len, components })
+    })
+}
+
+fn float_componentwise(args: &[&Value], f: &dyn Fn(&[f32]) -> f32) -> Result<Value> {
+    componentwise(args, &|scalars| {
+        let mut floats = [0.0; 4];
+        for (float, scalar) in floats.iter_mut().zip(scalars) {
+            *float = scalar.float()?;
+        }
+        Ok(Scalar::Float(f(&floats[..scalars.len()])))
+    })
+}
+
+macro_rules! int_binary {
+    ($op:expr, $a:expr, $b:expr, $variant:path) => {
+        match $op {
+            BinaryOperator::Add => $variant($a.wrapping_add($b)),
+            BinaryOperator::Subtract => $variant($a.wrapping_sub($b)),
+            BinaryOperator::Multiply => $variant($a.wrapping_mul($b)),
+            // division by zero is undefined in GLSL, but shouldn't take down the renderer
+            BinaryOperator::Divide => $variant($a.checked_div($b).unwrap_or(0)),
+            BinaryOperator::Modulo => $variant($a.checked_rem($b).unwrap_or(0)),
+            BinaryOperator::Equal => Scalar::Bool($a == $b),
+            BinaryOperator::NotEqual => Scalar::Bool($a != $b),
+            BinaryOperator::Less => Scalar::Bool($a < $b),
+            BinaryOperator::LessEqual => Scalar::Bool($a <= $b),
+            BinaryOperator::Greater => Scalar::Bool($a > $b),
+            BinaryOperator::GreaterEqual => Scalar::Bool($a >= $b),
+            BinaryOperator::And => $variant($a & $b),
+            BinaryOperator::ExclusiveOr => $variant($a ^ $b),
+            BinaryOperator::InclusiveOr => $variant($a | $b),
+            BinaryOperator::ShiftLeft => $variant($a.wrapping_shl($b as u32)),
+            BinaryOperator::ShiftRight => $variant($a.wrapping_shr($b as u32)),
+            BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr => {
+                bail!("unsupported integer operator {:?}", $op)
+            }
+        }
+    };
+}
+
+fn binary_scalar(op: BinaryOperator, left: Scalar, right: Scalar) -> Result<Scalar> {
+    Ok(match (left, right) {
+        (Scalar::Float(a), Scalar::Float(b)) => match op {
+            BinaryOperator::Add => Scalar::Float(a + b),
+            BinaryOperator::Subtract => Scalar::Float(a - b),
+            BinaryOperator::Multiply => Scalar::Float(a * b),
+            BinaryOperator::Divide => Scalar::Float(a / b),
+            // GLSL has no float remainder, so this is always its `mod`
+            BinaryOperator::Modulo => Scalar::Float(a - b * (a / b).floor()),
+            BinaryOperator::Equal => Scalar::Bool(a == b),
+            BinaryOperator::NotEqual => Scalar::Bool(a != b),
+            BinaryOperator::Less => Scalar::Bool(a < b),
+            BinaryOperator::LessEqual => Scalar::Bool(a <= b),
+            BinaryOperator::Greater => Scalar::Bool(a > b),
+            BinaryOperator::GreaterEqual => Scalar::Bool(a >= b),
+            _ => bail!("unsupported float operator {:?}", op),
+        },
+        (Scalar::Sint(a), Scalar::Sint(b)) => int_binary!(op, a, b, Scalar::Sint),
+        (Scalar::Uint(a), Scalar::Uint(b)) => int_binary!(op, a, b, Scalar::Uint),
+        // SPIR-V allows operands of different signedness, such as for shift amounts
+        (Scalar::Sint(a), Scalar::Uint(b)) => int_binary!(op, a, b as i32, Scalar::Sint),
+        (Scalar::Uint(a), Scalar::Sint(b)) => int_binary!(op, a, b as u32, Scalar::Uint),
+        (Scalar::Bool(a), Scalar::Bool(b)) => match op {
+            BinaryOperator::Equal => Scalar::Bool(a == b),
+            BinaryOperator::NotEqual | BinaryOperator::ExclusiveOr => Scalar::Bool(a != b),
+            BinaryOperator::LogicalAnd | BinaryOperator::And => Scalar::Bool(a && b),
+            BinaryOperator::LogicalOr | BinaryOperator::InclusiveOr => Scalar::Bool(a || b),
+            _ => bail!("unsupported bool operator {:?}", op),
+        },
+        _ => bail!("mismatched operands {:?} {:?} {:?}", left, op, right),
+    })
+}
+
+fn binary(op: BinaryOperator, left: &Value, right: &Value) -> Result<Value> {
+    // matrices are composites of their columns, which are multiplied as in linear algebra
+    if op == BinaryOperator::Multiply {
+        match (left, right) {
+            (Value::Composite(_), Value::Vector(_)) => {
+                let vector = right.to_floats()?;
+                return Ok(Value::floats(&matrix_times_vector(
+                    &left.to_matrix()?,
+                    &vector,
+                )));
+            }
+            (Value::Vector(_), Value::Composite(_)) => {
+                let vector = left.to_floats()?;
+                let columns: Vec<f32> = right
+                    .to_matrix()?
+                    .iter()
+                    .map(|column| dot(&vector, column))
+                    .collect();
+                return Ok(Value::floats(&columns));
+            }
+            (Value::Composite(_), Value::Composite(_)) => {
+                let matrix = left.to_matrix()?;
+                let columns: Vec<Vec<f32>> = right
+                    .to_matrix()?
+                    .iter()
+                    .map(|column| matrix_times_vector(&matrix, column))
+                    .collect();
+                return Ok(Value::from_matrix(&columns));
+            }
+            _ => {}
+        }
+    }
+
+    componentwise(&[left, right], &|scalars| {
+        binary_scalar(op, scalars[0], scalars[1])
+    })
+}
+
+fn unary(op: UnaryOperator, value: &Value) -> Result<Value> {
+    componentwise(&[value], &|scalars| {
+        Ok(match (op, scalars[0]) {
+            (UnaryOperator::Negate, Scalar::Float(value)) => Scalar::Float(-value),
+            (UnaryOperator::Negate, Scalar::Sint(value)) => Scalar::Sint(value.wrapping_neg()),
+            (UnaryOperator::Negate, Scalar::Uint(value)) => Scalar::Uint(value.wrapping_neg()),
+            (UnaryOperator::Not, Scalar::Bool(value)) => Scalar::Bool(!value),
+            (UnaryOperator::Not, Scalar::Sint(value)) => Scalar::Sint(!value),
+            (UnaryOperator::Not, Scalar::Uint(value)) => Scalar::Uint(!value),
+            (op, scalar) => bail!("unsupported operator {:?} on {:?}", op, scalar),
+        })
+    })
+}
+
+/// Conversion or, without a width to convert to, bitcast of a scalar.
+fn cast(scalar: Scalar, kind: ScalarKind, convert: Option<u8>) -> Result<Scalar> {
+    if convert.is_none() {
+        return Ok(match (kind, scalar) {
+            (ScalarKind::Float, Scalar::Sint(value)) => Scalar::Float(f32::from_bits(value as u32)),
+            (ScalarKind::Float, Scalar::Uint(value)) => Scalar::Float(f32::from_bits(value)),
+            (ScalarKind::Sint, Scalar::Float(value)) => Scalar::Sint(value.to_bits() as i32),
+            (ScalarKind::Sint, Scalar::Uint(value)) => Scalar::Sint(value as i32),
+            (ScalarKind::Uint, Scalar::Float(value)) => Scalar::Uint(value.to_bits()),
+            (ScalarKind::Uint, Scalar::Sint(value)) => Scalar::Uint(value as u32),
+            (kind, scalar) if scalar.kind() == kind => scalar,
+            (kind, scalar) => bail!("can't bitcast {:?} to {:?}", scalar, kind),
+        });
+    }
+
+    let (float, int, bool) = match scalar {
+        Scalar::Float(value) => (value, value as i64, value != 0.0),
+        Scalar::Sint(value) => (value as f32, value as i64, value != 0),
+        Scalar::Uint(value) => (value as f32, value as i64, value != 0),
+        Scalar::Bool(value) => (value as u8 as f32, value as i64, value),
+    };
+    Ok(match kind {
+        // float to integer conversions saturate, instead of being undefined when out of range
+        ScalarKind::Float => Scalar::Float(float),
+        ScalarKind::Sint if scalar.kind() == ScalarKind::Float => Scalar::Sint(float as i32),
+        ScalarKind::Uint if scalar.kind() == ScalarKind::Float => Scalar::Uint(float as u32),
+        ScalarKind::Sint => Scalar::Sint(int as i32),
+        ScalarKind::Uint => Scalar::Uint(int as u32),
+        ScalarKind::Bool => Scalar::Bool(bool),
+    })
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn matrix_times_vector(columns: &[Vec<f32>], vector: &[f32]) -> Vec<f32> {
+    let rows = columns.first().map_or(0, |column| column.len());
+    (0..rows)
+        .map(|row| {
+            columns
+                .iter()
+                .zip(vector)
+                .map(|(column, value)| column[row] * value)
+                .sum()
+        })
+        .collect()
+}
+
+fn transpose(columns: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let rows = columns.first().map_or(0, |column| column.len());
+    (0..rows)
+        .map(|row| columns.iter().map(|column| column[row]).collect())
+        .collect()
+}
+
+/// Determinant and inverse of a square matrix, by Gauss-Jordan elimination.
+fn determinant_and_inverse(columns: &[Vec<f32>]) -> (f32, Vec<Vec<f32>>) {
+    let n = columns.len();
+    // eliminating on the transpose gives the transposed inverse, whose rows are our columns
+    let mut a: Vec<Vec<f32>> = columns.to_vec();
+    let mut inverse: Vec<Vec<f32>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let mut determinant = 1.0;
+
+    for i in 0..n {
+        // NaN sorts above any number, so a column with NaN is caught below
+        let pivot = (i..n)
+            .max_by(|x, y| a[*x][i].abs().total_cmp(&a[*y][i].abs()))
+            .unwrap();
+        if a[pivot][i].is_nan() {
+            return (f32::NAN, vec![vec![f32::NAN; n]; n]);
+        }
+        if a[pivot][i] == 0.0 {
+            return (0.0, vec![vec![f32::NAN; n]; n]);
+        }
+        if pivot != i {
+            a.swap(pivot, i);
+            inverse.swap(pivot, i);
+            determinant = -determinant;
+        }
+
+        let scale = a[i][i];
+        determinant *= scale;
+        for j in 0..n {
+            a[i][j] /= scale;
+            inverse[i][j] /= scale;
+        }
+
+        for k in 0..n {
+            if k != i {
+                let factor = a[k][i];
+                for j in 0..n {
+                    a[k][j] -= factor * a[i][j];
+                    inverse[k][j] -= factor * inverse[i][j];
+                }
+            }
+        }
+    }
+
+    (determinant, inverse)
+}
+
+/// GLSL's `round`, which rounds halfway cases to the nearest even integer in SPIR-V.
+fn round_even(value: f32) -> f32 {
+    if (value - value.trunc()).abs() == 0.5 {
+        2.0 * (value / 2.0).round()
+    } else {
+        value.round()
+    }
+}
+
+fn math(fun: MathFunction, args: &[Value]) -> Result<Value> {
+    let arg = |index: usize| -> Result<&Value> {
+        args.get(index)
+            .chain_err(|| format!("missing argument of {:?}", fun))
+    };
+    let float1 = |f: fn(f32) -> f32| float_componentwise(&[arg(0)?], &|x| f(x[0]));
+    let float2 =
+        |f: fn(f32, f32) -> f32| float_componentwise(&[arg(0)?, arg(1)?], &|x| f(x[0], x[1]));
+    let float3 = |f: fn(f32, f32, f32) -> f32| {
+        float_componentwise(&[arg(0)?, arg(1)?, arg(2)?], &|x| f(x[0], x[1], x[2]))
+    };
+
+    Ok(match fun {
+        MathFunction::Abs => componentwise(&[arg(0)?], &|x| {
+            Ok(match x[0] {
+                Scalar::Float(value) => Scalar::Float(value.abs()),
+                Scalar::Sint(value) => Scalar::Sint(value.wrapping_abs()),
+                scalar => scalar,
+            })
+        })?,
+        MathFunction::Min | MathFunction::Max => componentwise(&[arg(0)?, arg(1)?], &|x| {
+            let less = binary_scalar(BinaryOperator::Less, x[1], x[0])?.bool()?;
+            Ok(if less == (fun == MathFunction::Min) {
+                x[1]
+            } else {
+                x[0]
+            })
+        })?,
+        MathFunction::Clamp => componentwise(&[arg(0)?, arg(1)?, arg(2)?], &|x| {
+            let value = if binary_scalar(BinaryOperator::Less, x[0], x[1])?.bool()? {
+                x[1]
+            } else {
+                x[0]
+            };
+            Ok(
+                if binary_scalar(BinaryOperator::Less, x[2], value)?.bool()? {
+                    x[2]
+                } else {
+                    value
+                },
+            )
+        })?,
+        MathFunction::Cos => float1(f32::cos)?,
+        MathFunction::Cosh => float1(f32::cosh)?,
+        MathFunction::Sin => float1(f32::sin)?,
+        MathFunction::Sinh => float1(f32::sinh)?,
+        MathFunction::Tan => float1(f32::tan)?,
+        MathFunction::Tanh => float1(f32::tanh)?,
+        MathFunction::Acos => float1(f32::acos)?,
+        MathFunction::Asin => float1(f32::asin)?,
+        MathFunction::Atan => float1(f32::atan)?,
+        MathFunction::Atan2 => float2(f32::atan2)?,
+        MathFunction::Asinh => float1(f32::asinh)?,
+        MathFunction::Acosh => float1(f32::acosh)?,
+        MathFunction::Atanh => float1(f32::atanh)?,
+        MathFunction::Ceil => float1(f32::ceil)?,
+        MathFunction::Floor => float1(f32::floor)?,
+        MathFunction::Round => float1(round_even)?,
+        MathFunction::Fract => float1(|x| x - x.floor())?,
+        MathFunction::Trunc => float1(f32::trunc)?,
+        MathFunction::Ldexp => componentwise(&[arg(0)?, arg(1)?], &|x| {
+            let exponent = cast(x[1], ScalarKind::Float, Some(4))?.float()?;
+            Ok(Scalar::Float(x[0].float()? * exponent.exp2()))
+        })?,
+        MathFunction::Exp => float1(f32::exp)?,
+        MathFunction::Exp2 => float1(f32::exp2)?,
+        MathFunction::Log => float1(f32::ln)?,
+        MathFunction::Log2 => float1(f32::log2)?,
+        MathFunction::Pow => float2(f32::powf)?,
+        MathFunction::Dot => Value::float(dot(&arg(0)?.to_floats()?, &arg(1)?.to_floats()?)),
+        MathFunction::Outer => {
+            let a = arg(0)?.to_floats()?;
+            let columns: Vec<Vec<f32>> = arg(1)?
+                .to_floats()?
+                .iter()
+                .map(|b| a.iter().map(|a| a * b).collect())
+                .collect();
+            Value::from_matrix(&columns)
+        }
+        MathFunction::Cross => match (&arg(0)?.to_floats()?[..], &arg(1)?.to_floats()?[..]) {
+            ([ax, ay, az], [bx, by, bz]) => {
+                Value::floats(&[ay * bz - az * by, az * bx - ax * bz, ax * by - ay * bx])
+            }
+            _ => bail!("cross needs 3 component vectors"),
+        },
+        MathFunction::Distance => {
+            let a = arg(0)?.to_floats()?;
+            let b = arg(1)?.to_floats()?;
+            let difference: Vec<f32> = a.iter().zip(&b).map(|(a, b)| a - b).collect();
+            Value::float(dot(&difference, &difference).sqrt())
+        }
+        MathFunction::Length => {
+            let a = arg(0)?.to_floats()?;
+            Value::float(dot(&a, &a).sqrt())
+        }
+        MathFunction::Normalize => {
+            let a = arg(0)?.to_floats()?;
+            let length = dot(&a, &a).sqrt();
+            let normalized: Vec<f32> = a.iter().map(|a| a / length).collect();
+            Value::floats(&normalized)
+        }
+        MathFunction::FaceForward => {
+            let n = arg(0)?;
+            if dot(&arg(2)?.to_floats()?, &arg(1)?.to_floats()?) < 0.0 {
+                n.clone()
+            } else {
+                unary(UnaryOperator::Negate, n)?
+            }
+        }
+        MathFunction::Reflect => {
+            let i = arg(0)?.to_floats()?;
+            let n = arg(1)?.to_floats()?;
+            let d = dot(&n, &i);
+            let reflected: Vec<f32> = i.iter().zip(&n).map(|(i, n)| i - 2.0 * d * n).collect();
+            Value::floats(&reflected)
+        }
+        MathFunction::Refract => {
+            let i = arg(0)?.to_floats()?;
+            let n = arg(1)?.to_floats()?;
+            let eta = arg(2)?.scalar()?.float()?;
+            let d = dot(&n, &i);
+            let k = 1.0 - eta * eta * (1.0 - d * d);
+            let refracted: Vec<f32> = if k < 0.0 {
+                vec![0.0; i.len()]
+            } else {
+                i.iter()
+                    .zip(&n)
+                    .map(|(i, n)| eta * i - (eta * d + k.sqrt()) * n)
+                    .collect()
+            };
+            Value::floats(&refracted)
+        }
+        MathFunction::Sign => componentwise(&[arg(0)?], &|x| {
+            Ok(match x[0] {
+                Scalar::Float(value) if value > 0.0 => Scalar::Float(1.0),
+                Scalar::Float(value) if value < 0.0 => Scalar::Float(-1.0),
+                Scalar::Float(_) => Scalar::Float(0.0),
+                Scalar::Sint(value) => Scalar::Sint(value.signum()),
+                scalar => scalar,
+            })
+        })?,
+        MathFunction::Fma => float3(|a, b, c| a * b + c)?,
+        MathFunction::Mix => float3(|x, y, a| x * (1.0 - a) + y * a)?,
+        MathFunction::Step => float2(|edge, x| if x < edge { 0.0 } else { 1.0 })?,
+        MathFunction::SmoothStep => float3(|edge0, edge1, x| {
+            let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        })?,
+        MathFunction::Sqrt => float1(f32::sqrt)?,
+        MathFunction::InverseSqrt => float1(|x| 1.0 / x.sqrt())?,
+        MathFunction::Inverse => {
+            let (_, inverse) = determinant_and_inverse(&arg(0)?.to_matrix()?);
+            Value::from_matrix(&inverse)
+        }
+        MathFunction::Transpose => Value::from_matrix(&transpose(&arg(0)?.to_matrix()?)),
+        MathFunction::Determinant => {
+            let (determinant, _) = determinant_and_inverse(&arg(0)?.to_matrix()?);
+            Value::float(determinant)
+        }
+        MathFunction::CountOneBits => componentwise(&[arg(0)?], &|x| {
+            Ok(match x[0] {
+                Scalar::Sint(value) => Scalar::Sint(value.count_ones() as i32),
+                Scalar::Uint(value) => Scalar::Uint(value.count_ones()),
+                scalar => bail!("can't count the bits of {:?}", scalar),
+            })
+        })?,
+        MathFunction::ReverseBits => componentwise(&[arg(0)?], &|x| {
+            Ok(match x[0] {
+                Scalar::Sint(value) => Scalar::Sint(value.reverse_bits()),
+                Scalar::Uint(value) => Scalar::Uint(value.reverse_bits()),
+                scalar => bail!("can't reverse the bits of {:?}", scalar),
+            })
+        })?,
+        _ => bail!("{:?} isn't supported by the CPU backend", fun),
+    })
+}
+
+fn relational(fun: RelationalFunction, value: &Value) -> Result<Value> {
+    let float_test =
+        |f: fn(f32) -> bool| componentwise(&[value], &|x| Ok(Scalar::Bool(f(x[0].float()?))));
+
+    match fun {
+        RelationalFunction::All | RelationalFunction::Any => {
+            let bools = value
+                .scalars()?
+                .iter()
+                .map(|scalar| scalar.bool())
+                .collect::<Result<Vec<bool>>>()?;
+            Ok(Value::Scalar(Scalar::Bool(
+                if fun == RelationalFunction::All {
+                    bools.iter().all(|value| *value)
+                } else {
+                    bools.iter().any(|value| *value)
+                },
+            )))
+        }
+        RelationalFunction::IsNan => float_test(f32::is_nan),
+        RelationalFunction::IsInf => float_test(f32::is_infinite),
+        RelationalFunction::IsFinite => float_test(f32::is_finite),
+        RelationalFunction::IsNormal => float_test(f32::is_normal),
+    }
+}
+
+/// Value of a type built from its components, which are flattened for vectors.
+fn compose(module: &naga::Module, ty: Handle<naga::Type>, components: Vec<Value>) -> Result<Value> {
+    match module.types[ty].inner {
+        TypeInner::Vector { size, .. } => {
+            let mut scalars = vec![];
+            for component in &components {
+                scalars.extend_from_slice(component.scalars()?);
+            }
+            if scalars.len() != size as usize {
+                bail!("wrong number of components for a {:?} vector", size);
+            }
+            Ok(Value::from_scalars(&scalars))
+        }
+        TypeInner::Matrix { .. } | TypeInner::Array { .. } | TypeInner::Struct { .. } => {
+            Ok(Value::Composite(components))
+        }
+        ref inner => bail!("can't compose a {:?}", inner),
+    }
+}
+
+fn zero_value(module: &naga::Module, ty: Handle<naga::Type>) -> Result<Value> {
+    Ok(match module.types[ty].inner {
+        TypeInner::Scalar { kind, .. } => Value::Scalar(Scalar::zero(kind)),
+        TypeInner::Vector { size, kind, .. } => Value::Vector(Vector {
+            len: size as usize,
+            components: [Scalar::zero(kind); 4],
+        }),
+        TypeInner::Matrix { columns, rows, .. } => {
+            Value::Composite(vec![
+                Value::floats(&vec![0.0; rows as usize]);
+                columns as usize
+            ])
+        }
+        TypeInner::Array {
+            base,
+            size: naga::ArraySize::Constant(size),
+            ..
+        } => {
+            let len = constant_value(module, size)?.scalar()?.index()?;
+            Value::Composite(vec![zero_value(module, base)?; len])
+        }
+        TypeInner::Struct { ref members, .. } => Value::Composite(
+            members
+                .iter()
+                .map(|member| zero_value(module, member.ty))
+                .collect::<Result<Vec<Value>>>()?,
+        ),
+        TypeInner::Image { .. } | TypeInner::Sampler { .. } => Value::Opaque,
+        ref inner => bail!("{:?} isn't supported by the CPU backend", inner),
+    })
+}
+
+fn constant_value(module: &naga::Module, handle: Handle<naga::Constant>) -> Result<Value> {
+    match module.constants[handle].inner {
+        naga::ConstantInner::Scalar { value, .. } => Ok(Value::Scalar(match value {
+            ScalarValue::Sint(value) => Scalar::Sint(value as i32),
+            ScalarValue::Uint(value) => Scalar::Uint(value as u32),
+            ScalarValue::Float(value) => Scalar::Float(value as f32),
+            ScalarValue::Bool(value) => Scalar::Bool(value),
+        })),
+        naga::ConstantInner::Composite { ty, ref components } => {
+            let components = components
+                .iter()
+                .map(|component| constant_value(module, *component))
+                .collect::<Result<Vec<Value>>>()?;
+            compose(module, ty, components)
+        }
+    }
+}
+
+/// Value of a member of the uniform block of our shader header, see `shadertoy_header.glsl`.
+fn uniform_member(name: &str, constants: &ShadertoyConstants) -> Option<Value> {
+    let c = constants;
+    Some(match name {
+        "iResolution" => Value::floats(&[c.iResolution.0, c.iResolution.1, c.iResolution.2]),
+        "iMouse" => Value::floats(&[c.iMouse.0, c.iMouse.1, c.iMouse.2, c.iMouse.3]),
+        "iTime" => Value::float(c.iTime),
+        "iTimeDelta" => Value::float(c.iTimeDelta),
+        "iFrameRate" => Value::float(c.iFrameRate),
+        "iSampleRate" => Value::float(c.iSampleRate),
+        "iFrame" => Value::Scalar(Scalar::Sint(c.iFrame)),
+        "iChannelTime" => {
+            Value::Composite(c.iChannelTime.iter().map(|t| Value::float(t.0)).collect())
+        }
+        "iChannelResolution" => Value::Composite(
+            c.iChannelResolution
+                .iter()
+                .map(|r| Value::floats(&[r.0, r.1, r.2]))
+                .collect(),
+        ),
+        "iDate" => Value::floats(&[c.iDate.0, c.iDate.1, c.iDate.2, c.iDate.3]),
+        "iBlockOffset" => Value::float(c.iBlockOffset),
+        _ => return None,
+    })
+}
+
+/// What to do after a statement.
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Option<Value>),
+    /// The pixel was discarded.
+    Kill,
+}
+
+/// A function being called.
+struct Frame<'a> {
+    function: &'a Function,
+    arguments: Vec<Value>,
+    /// Index of the first local variable of the function in `Invocation::locals`.
+    locals: usize,
+    /// Values of the expressions that have been evaluated, see `Statement::Emit`.
+    values: Vec<Option<Value>>,
+}
+
+/// State of shading a single pixel.
+struct Invocation<'a> {
+    pipeline: &'a CpuRenderPipeline,
+    globals: Vec<Value>,
+    locals: Vec<Value>,
+    steps: u64,
+}
+
+impl<'a> Invocation<'a> {
+    fn step(&mut self) -> Result<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            bail!(
+                "shader took more than {} steps for a pixel, it may never finish",
+                MAX_STEPS
+            );
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, function: &'a Function, arguments: Vec<Value>) -> Result<Flow> {
+        let pipeline = self.pipeline;
+
+        let locals = self.locals.len();
+        for (_, local) in function.local_variables.iter() {
+            let value = match local.init {
+                Some(init) => pipeline.constants[init.index()].clone(),
+                None => zero_value(&pipeline.module, local.ty)?,
+            };
+            self.locals.push(value);
+        }
+
+        let mut frame = Frame {
+            function,
+            arguments,
+            locals,
+            values: vec![None; function.expressions.len()],
+        };
+        let flow = self.block(&mut frame, &function.body)?;
+
+        self.locals.truncate(locals);
+        Ok(flow)
+    }
+
+    fn block(&mut self, frame: &mut Frame<'a>, block: &'a Block) -> Result<Flow> {
+        for statement in block {
+            self.step()?;
+            match self.statement(frame, statement)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, frame: &mut Frame<'a>, statement: &'a Statement) -> Result<Flow> {
+        let pipeline = self.pipeline;
+
+        match *statement {
+            Statement::Emit(ref range) => {
+                for handle in range.clone() {
+                    let value = self.evaluate(frame, handle)?;
+                    frame.values[handle.index()] = Some(value);
+                }
+            }
+            Statement::Block(ref block) => return self.block(frame, block),
+            Statement::If {
+                condition,
+                ref accept,
+                ref reject,
+            } => {
+                let condition = self.value(frame, condition)?.scalar()?.bool()?;
+                return self.block(frame, if condition { accept } else { reject });
+            }
+            Statement::Switch {
+                selector,
+                ref cases,
+                ref default,
+            } => {
+                let selector = match self.value(frame, selector)?.scalar()? {
+                    Scalar::Sint(value) => value,
+                    Scalar::Uint(value) => value as i32,
+                    scalar => bail!("invalid switch selector {:?}", scalar),
+                };
+
+                // run the matching case and the ones it falls through to, which can end in the default
+                let start = cases
+                    .iter()
+                    .position(|case| case.value == selector)
+                    .unwrap_or(cases.len());
+                for case in &cases[start..] {
+                    match self.block(frame, &case.body)? {
+                        Flow::Next if case.fall_through => {}
+                        Flow::Next | Flow::Break => return Ok(Flow::Next),
+                        flow => return Ok(flow),
+                    }
+                }
+                return match self.block(frame, default)? {
+                    Flow::Break => Ok(Flow::Next),
+                    flow => Ok(flow),
+                };
+            }
+            Statement::Loop {
+                ref body,
+                ref continuing,
+            } => loop {
+                self.step()?;
+                match self.block(frame, body)? {
+                    Flow::Next | Flow::Continue => {}
+                    Flow::Break => return Ok(Flow::Next),
+                    flow => return Ok(flow),
+                }
+                match self.block(frame, continuing)? {
+                    Flow::Next => {}
+                    flow => return Ok(flow),
+                }
+            },
+            Statement::Break => return Ok(Flow::Break),
+            Statement::Continue => return Ok(Flow::Continue),
+            Statement::Return { value } => {
+                let value = match value {
+                    Some(value) => Some(self.value(frame, value)?),
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Statement::Kill => return Ok(Flow::Kill),
+            // there is only a single invocation to synchronize
+            Statement::Barrier(_) => {}
+            Statement::Store { pointer, value } => {
+                let pointer = self.value(frame, pointer)?.pointer()?;
+                let value = self.value(frame, value)?;
+                self.store(&pointer, value)?;
+            }
+            Statement::Call {
+                function,
+                ref arguments,
+                result,
+            } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.value(frame, *argument))
+                    .collect::<Result<Vec<Value>>>()?;
+                match self.call(&pipeline.module.functions[function], arguments)? {
+                    Flow::Kill => return Ok(Flow::Kill),
+                    Flow::Return(value) => {
+                        if let Some(result) = result {
+                            frame.values[result.index()] = value;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Statement::ImageStore { .. } | Statement::Atomic { .. } => {
+                bail!("{:?} isn't supported by the CPU backend", statement)
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Value of an expression, evaluated now unless it has been already.
+    fn value(&mut self, frame: &Frame<'a>, handle: Handle<Expression>) -> Result<Value> {
+        match frame.values[handle.index()] {
+            Some(ref value) => Ok(value.clone()),
+            None => self.evaluate(frame, handle),
+        }
+    }
+
+    fn evaluate(&mut self, frame: &Frame<'a>, handle: Handle<Expression>) -> Result<Value> {
+        let pipeline = self.pipeline;
+        let module = &pipeline.module;
+
+        Ok(match frame.function.expressions[handle] {
+            Expression::Access { base, index } => {
+                let index = self.value(frame, index)?.scalar()?.index()?;
+                self.value(frame, base)?.access(index)?
+            }
+            Expression::AccessIndex { base, index } => {
+                self.value(frame, base)?.access(index as usize)?
+            }
+            Expression::Constant(constant) => pipeline.constants[constant.index()].clone(),
+            Expression::Splat { size, value } => {
+                let scalar = self.value(frame, value)?.scalar()?;
+                Value::from_scalars(&vec![scalar; size as usize])
+            }
+            Expression::Swizzle {
+                size,
+                vector,
+                pattern,
+            } => {
+                let vector = self.value(frame, vector)?;
+                let scalars = vector.scalars()?;
+                let swizzled = pattern[..size as usize]
+                    .iter()
+                    .map(|component| {
+                        scalars
+                            .get(*component as usize)
+                            .copied()
+                            .chain_err(|| "swizzle out of bounds")
+                    })
+                    .collect::<Result<Vec<Scalar>>>()?;
+                Value::from_scalars(&swizzled)
+            }
+            Expression::Compose { ty, ref components } => {
+                let components = components
+                    .iter()
+                    .map(|component| self.value(frame, *component))
+                    .collect::<Result<Vec<Value>>>()?;
+                compose(module, ty, components)?
+            }
+            Expression::FunctionArgument(index) => frame.arguments[index as usize].clone(),
+            Expression::GlobalVariable(global) => match module.global_variables[global].class {
+                StorageClass::Handle => Value::Opaque,
+                _ => Value::Pointer(Pointer {
+                    root: Root::Global(global.index()),
+                    path: vec![],
+                }),
+            },
+            Expression::LocalVariable(local) => Value::Pointer(Pointer {
+                root: Root::Local(frame.locals + local.index()),
+                path: vec![],
+            }),
+            Expression::Load { pointer } => {
+                let pointer = self.value(frame, pointer)?.pointer()?;
+                self.load(&pointer)?
+            }
+            Expression::ImageSample { .. }
+            | Expression::ImageLoad { .. }
+            | Expression::ImageQuery { .. } => {
+                bail!("textures aren't supported by the CPU backend")
+            }
+            Expression::Unary { op, expr } => unary(op, &self.value(frame, expr)?)?,
+            Expression::Binary { op, left, right } => {
+                binary(op, &self.value(frame, left)?, &self.value(frame, right)?)?
+            }
+            Expression::Select {
+                condition,
+                accept,
+                reject,
+            } => {
+                let condition = self.value(frame, condition)?;
+                let accept = self.value(frame, accept)?;
+                let reject = self.value(frame, reject)?;
+                match condition {
+                    Value::Scalar(condition) if condition.bool()? => accept,
+                    Value::Scalar(_) => reject,
+                    _ => componentwise(&[&condition, &accept, &reject], &|x| {
+                        Ok(if x[0].bool()? { x[1] } else { x[2] })
+                    })?,
+                }
+            }
+            // pixels are shaded one at a time, so there are no neighbours to take differences with
+            Expression::Derivative { expr, .. } => {
+                componentwise(&[&self.value(frame, expr)?], &|x| {
+                    Ok(Scalar::zero(x[0].kind()))
+                })?
+            }
+            Expression::Relational { fun, argument } => {
+                relational(fun, &self.value(frame, argument)?)?
+            }
+            Expression::Math {
+                fun,
+                arg,
+                arg1,
+                arg2,
+                arg3,
+            } => {
+                let args = [Some(arg), arg1, arg2, arg3]
+                    .iter()
+                    .flatten()
+                    .map(|arg| self.value(frame, *arg))
+                    .collect::<Result<Vec<Value>>>()?;
+                math(fun, &args)?
+            }
+            Expression::As {
+                expr,
+                kind,
+                convert,
+            } => componentwise(&[&self.value(frame, expr)?], &|x| cast(x[0], kind, convert))?,
+            Expression::CallResult(_) => bail!("function result used before the call"),
+            Expression::AtomicResult { .. } | Expression::ArrayLength(_) => bail!(
+                "{:?} isn't supported by the CPU backend",
+                frame.function.expressions[handle]
+            ),
+        })
+    }
+
+    fn root(&mut self, root: Root) -> &mut Value {
+        match root {
+            Root::Global(index) => &mut self.globals[index],
+            Root::Local(index) => &mut self.locals[index],
+        }
+    }
+
+    fn load(&mut self, pointer: &Pointer) -> Result<Value> {
+        let mut value: &Value = self.root(pointer.root);
+        for (depth, index) in pointer.path.iter().enumerate() {
+            value = match value {
+                Value::Composite(values) if *index < values.len() => &values[*index],
+                Value::Vector(vector) if *index < vector.len && depth + 1 == pointer.path.len() => {
+                    return Ok(Value::Scalar(vector.components[*index]))
+                }
+                _ => bail!("invalid pointer {:?}", pointer),
+            };
+        }
+        Ok(value.clone())
+    }
+
+    fn store(&mut self, pointer: &Pointer, new_value: Value) -> Result<()> {
+        let mut value = self.root(pointer.root);
+        let (last, path) = match pointer.path.split_last() {
+            Some(split) => split,
+            None => {
+                *value = new_value;
+                return Ok(());
+            }
+        };
+
+        for index in path {
+            value = match value {
+                Value::Composite(values) if *index < values.len() => &mut values[*index],
+                _ => bail!("invalid pointer {:?}", pointer),
+            };
+        }
+        match value {
+            Value::Composite(values) if *last < values.len() => values[*last] = new_value,
+            Value::Vector(vector) if *last < vector.len => {
+                vector.components[*last] = new_value.scalar()?
+            }
+            _ => bail!("invalid pointer {:?}", pointer),
+        }
+        Ok(())
+    }
+}
+
+struct CpuRenderPipeline {
+    module: naga::Module,
+    /// Index of the fragment shader in the entry points of the module.
+    entry_point: usize,
+    /// Member of the result of the entry point with the color, if it returns a struct.
+    color_member: Option<usize>,
+    /// Values of all constants of the module, by index.
+    constants: Vec<Value>,
+    /// Initial values of the global variables, by index.
+    globals: Vec<Value>,
+    /// The global variable with the uniform block of the shadertoy constants.
+    uniforms: Option<Handle<naga::GlobalVariable>>,
+    /// Whether an error has been logged, so a failing shader doesn't log every frame.
+    failed: AtomicBool,
+}
+
+impl CpuRenderPipeline {
+    fn new(spirv: &[u32]) -> Result<CpuRenderPipeline> {
+        profile_scope!("cpu_pipeline");
+
+        let bytes: Vec<u8> = spirv.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let module = naga::front::spv::parse_u8_slice(&bytes, &Default::default())
+            .map_err(|err| format!("naga SPIR-V parsing failed: {}", err))?;
+
+        let entry_point = module
+            .entry_points
+            .iter()
+            .position(|entry_point| entry_point.stage == naga::ShaderStage::Fragment)
+            .chain_err(|| "no fragment shader entry point")?;
+
+        let function = &module.entry_points[entry_point].function;
+        for argument in &function.arguments {
+            match argument.binding {
+                Some(Binding::Location { location: 0, .. })
+                | Some(Binding::BuiltIn(BuiltIn::Position)) => {}
+                ref binding => bail!("unsupported shader input {:?}", binding),
+            }
+        }
+
+        let result = function
+            .result
+            .as_ref()
+            .chain_err(|| "the shader has no output")?;
+        let color_member = match module.types[result.ty].inner {
+            TypeInner::Struct { ref members, .. } => Some(
+                members
+                    .iter()
+                    .position(|member| {
+                        matches!(member.binding, Some(Binding::Location { location: 0, .. }))
+                    })
+                    .chain_err(|| "the shader has no color output")?,
+            ),
+            _ => None,
+        };
+
+        let constants = module
+            .constants
+            .iter()
+            .map(|(handle, _)| constant_value(&module, handle))
+            .collect::<Result<Vec<Value>>>()?;
+
+        let globals = module
+            .global_variables
+            .iter()
+            .map(|(_, global)| match global.init {
+                Some(init) => Ok(constants[init.index()].clone()),
+                None => zero_value(&module, global.ty),
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        let uniforms = module
+            .global_variables
+            .iter()
+            .find(|(_, global)| {
+                global.class == StorageClass::Uniform
+                    && matches!(module.types[global.ty].inner, TypeInner::Struct { .. })
+            })
+            .map(|(handle, _)| handle);
+
+        Ok(CpuRenderPipeline {
+            module,
+            entry_point,
+            color_member,
+            constants,
+            globals,
+            uniforms,
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Initial values of the global variables, with the uniform block set to the constants.
+    fn globals(&self, constants: &ShadertoyConstants) -> Vec<Value> {
+        let mut globals = self.globals.clone();
+
+        if let Some(uniforms) = self.uniforms {
+            if let TypeInner::Struct { ref members, .. } =
+                self.module.types[self.module.global_variables[uniforms].ty].inner
+            {
+                let block = &mut globals[uniforms.index()];
+                if let Value::Composite(ref mut values) = *block {
+                    for (value, member) in values.iter_mut().zip(members) {
+                        // members we don't know of keep their zero value
+                        if let Some(member_value) = member
+                            .name
+                            .as_ref()
+                            .and_then(|name| uniform_member(name, constants))
+                        {
+                            *value = member_value;
+                        }
+                    }
+                }
+            }
+        }
+
+        globals
+    }
+
+    /// Color of a pixel, or `None` if it was discarded.
+    fn shade(&self, globals: &[Value], frag_coord: (f32, f32)) -> Result<Option<[f32; 4]>> {
+        let function = &self.module.entry_points[self.entry_point].function;
+
+        let arguments = function
+            .arguments
+            .iter()
+            .map(|argument| match argument.binding {
+                Some(Binding::BuiltIn(BuiltIn::Position)) => {
+                    Value::floats(&[frag_coord.0, frag_coord.1, 0.5, 1.0])
+                }
+                _ => Value::floats(&[frag_coord.0, frag_coord.1]),
+            })
+            .collect();
+
+        let mut invocation = Invocation {
+            pipeline: self,
+            globals: globals.to_vec(),
+            locals: vec![],
+            steps: 0,
+        };
+
+        let result = match invocation.call(function, arguments)? {
+            Flow::Kill => return Ok(None),
+            Flow::Return(Some(result)) => result,
+            _ => bail!("the shader didn't return a color"),
+        };
+        let color = match self.color_member {
+            Some(member) => result.access(member)?,
+            None => result,
+        };
+
+        match color.to_floats()?[..] {
+            [r, g, b, a] => Ok(Some([r, g, b, a])),
+            _ => bail!("the shader color isn't a vec4"),
+        }
+    }
+}
+
+pub struct CpuRenderBackend {
+    sandbox: Sandbox,
+
+    framebuffer: Image,
+
+    pipelines: Mutex<Vec<Arc<CpuRenderPipeline>>>,
+}
+
+impl CpuRenderBackend {
+    /// Creates the backend with a framebuffer of `width` x `height` pixels, shaders are compiled
+    /// to SPIR-V in worker processes limited by `sandbox`.
+    pub fn new(sandbox: Sandbox, width: usize, height: usize) -> CpuRenderBackend {
+        CpuRenderBackend {
+            sandbox,
+            framebuffer: Image::new(width, height),
+            pipelines: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.framebuffer.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.framebuffer.height()
+    }
+
+    /// The last rendered frame.
+    pub fn framebuffer(&self) -> &Image {
+        &self.framebuffer
+    }
+
+    fn pipeline(&self, handle: RenderPipelineHandle) -> Result<Arc<CpuRenderPipeline>> {
+        self.pipelines
+            .lock()
+            .unwrap()
+            .get(handle)
+            .cloned()
+            .chain_err(|| format!("unknown pipeline {}", handle))
+    }
+}
+
+/// Pixels of an image covered by a quad, the end is exclusive.
+struct Rect {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+/// Shades the pixels of `rect` in `image`, stopping at the first error as a shader that fails
+/// usually does so for every pixel.
+fn render_quad(
+    pipeline: &CpuRenderPipeline,
+    constants: &ShadertoyConstants,
+    image: &mut Image,
+    rect: &Rect,
+) -> Result<()> {
+    let globals = pipeline.globals(constants);
+    let Rect { x0, y0, x1, y1 } = *rect;
+    let width = image.width();
+
+    image
+        .pixels_mut()
+        .par_chunks_mut(width.max(1))
+        .enumerate()
+        .skip(y0)
+        .take(y1 - y0)
+        .try_for_each(|(y, row)| -> Result<()> {
+            for (x, pixel) in row[x0..x1].iter_mut().enumerate() {
+                // shadertoys have the origin in the bottom left corner
+                let frag_coord = (x as f32 + 0.5, (y1 - 1 - y) as f32 + 0.5);
+                if let Some(mut color) = pipeline.shade(&globals, frag_coord)? {
+                    // like on shadertoy.com, the image is opaque whatever the alpha
+                    color[3] = 1.0;
+                    *pixel = color;
+                }
+            }
+            Ok(())
+        })
+}
+
+impl RenderBackend for CpuRenderBackend {
+    fn init_window(&mut self, _window: &dyn Any) {
+        // only renders to the framebuffer
+    }
+
+    fn render_frame(&mut self, params: RenderParams<'_>) {
+        profile_scope!("cpu_render_frame");
+
+        let width = self.width();
+        let height = self.height();
+        let w = width as f32;
+        let h = height as f32;
+
+        self.framebuffer.fill([
+            params.clear_color.0,
+            params.clear_color.1,
+            params.clear_color.2,
+            params.clear_color.3,
+        ]);
+
+        // shadertoys have the origin in the bottom left corner
+        let mut mouse = (
+            params.mouse_pos.0 as f32,
+            params.mouse_pos.1 as f32,
+            params.mouse_click_pos.0 as f32,
+            params.mouse_click_pos.1 as f32,
+        );
+        if mouse.1 > 0.0 {
+            mouse.1 = h - mouse.1;
+        }
+        if mouse.3 > 0.0 {
+            mouse.3 = h - mouse.3;
+        }
+
+        let inputs = params.clock.frame_inputs(mouse);
+
+        for quad in params.quads {
+            let pipeline = match self.pipeline(quad.pipeline_handle) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            let rect = Rect {
+                x0: ((quad.pos.0 * w).round() as usize).min(width),
+                y0: ((quad.pos.1 * h).round() as usize).min(height),
+                x1: (((quad.pos.0 + quad.size.0) * w).round() as usize).min(width),
+                y1: (((quad.pos.1 + quad.size.1) * h).round() as usize).min(height),
+            };
+            let constants = ShadertoyConstants::new(
+                ((rect.x1 - rect.x0) as f32, (rect.y1 - rect.y0) as f32),
+                &inputs,
+            );
+
+            if let Err(err) = render_quad(&pipeline, &constants, &mut self.framebuffer, &rect) {
+                if !pipeline.failed.swap(true, Ordering::Relaxed) {
+                    error!("CPU rendering failed: {}", err);
+                }
+                for y in rect.y0..rect.y1 {
+                    for x in rect.x0..rect.x1 {
+                        self.framebuffer.set_pixel(x, y, ERROR_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_to_image(&self, params: &ImageParams) -> Result<Image> {
+        profile_scope!("cpu_render_to_image");
+
+        let pipeline = self.pipeline(params.pipeline_handle)?;
+        let constants =
+            ShadertoyConstants::new((params.width as f32, params.height as f32), &params.inputs);
+
+        let mut image = Image::new(params.width, params.height);
+        let rect = Rect {
+            x0: 0,
+            y0: 0,
+            x1: params.width,
+            y1: params.height,
+        };
+        render_quad(&pipeline, &constants, &mut image, &rect)?;
+        Ok(image)
+    }
+
+    fn new_pipeline(&self, shader_path: &str, shader_source: &str) -> Result<RenderPipelineHandle> {
+        // shaderc can hang or crash on some shaders, so compile out of process
+        let spirv = self.sandbox.compile_to_spirv(shader_path, shader_source)?;
+        let pipeline = CpuRenderPipeline::new(&spirv)?;
+
+        let mut pipelines = self.pipelines.lock().unwrap();
+        pipelines.push(Arc::new(pipeline));
+        Ok(pipelines.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate;
+
+    /// Renders a `mainImage` at a time of 1 second, compiling it in-process.
+    fn render(code: &str, width: usize, height: usize) -> Result<Image> {
+        let source = format!(
+            "{}\n{}\n{}",
+            include_str!("shadertoy_header.glsl"),
+            code,
+            include_str!("shadertoy_image_footer.glsl")
+        );
+        let (spirv, _) = translate::compile_to_spirv("test", &source)?;
+        let pipeline = CpuRenderPipeline::new(&spirv)?;
+
+        let inputs = FrameInputs {
+            time: 1.0,
+            time_delta: 0.0,
+            frame: 0,
+            mouse: (0.0, 0.0, 0.0, 0.0),
+            date: chrono::Local::now(),
+        };
+        let constants = ShadertoyConstants::new((width as f32, height as f32), &inputs);
+        let mut image = Image::new(width, height);
+        let rect = Rect {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        };
+        render_quad(&pipeline, &constants, &mut image, &rect)?;
+        Ok(image)
+    }
+
+    fn assert_pixel(image: &Image, x: usize, y: usize, expected: [f32; 4]) {
+        let pixel = image.pixel(x, y);
+        assert!(
+            pixel
+                .iter()
+                .zip(&expected)
+                .all(|(value, expected)| (value - expected).abs() < 1e-5),
+            "pixel {},{} is {:?}, expected {:?}",
+            x,
+            y,
+            pixel,
+            expected
+        );
+    }
+
+    fn vector(values: &[f32]) -> Value {
+        Value::floats(values)
+    }
+
+    #[test]
+    fn solid_color() {
+        let image = render(
+            "void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                fragColor = vec4(0.25, 0.5, 0.75, 0.0);
+            }",
+            3,
+            2,
+        )
+        .unwrap();
+
+        // the alpha is ignored like on shadertoy.com
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_pixel(&image, x, y, [0.25, 0.5, 0.75, 1.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn uv_gradient() {
+        let image = render(
+            "void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                vec2 uv = fragCoord / iResolution.xy;
+                fragColor = vec4(uv, iTime, 1.0);
+            }",
+            4,
+            2,
+        )
+        .unwrap();
+
+        // the origin of shadertoys is in the bottom left corner
+        assert_pixel(&image, 0, 0, [0.125, 0.75, 1.0, 1.0]);
+        assert_pixel(&image, 3, 0, [0.875, 0.75, 1.0, 1.0]);
+        assert_pixel(&image, 0, 1, [0.125, 0.25, 1.0, 1.0]);
+        assert_pixel(&image, 2, 1, [0.625, 0.25, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn loops_and_functions() {
+        let image = render(
+            "float sum(int n) {
+                float s = 0.0;
+                for (int i = 0; i < n; i++) {
+                    if (i == 2) {
+                        continue;
+                    }
+                    s += float(i);
+                }
+                return s;
+            }
+
+            void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                fragColor = vec4(sum(10) / 100.0, sum(int(fragCoord.x)) / 10.0, 0.0, 1.0);
+            }",
+            4,
+            1,
+        )
+        .unwrap();
+
+        // 0 + 1 + 3 + ... + 9 without the skipped 2
+        assert_pixel(&image, 0, 0, [0.43, 0.0, 0.0, 1.0]);
+        assert_pixel(&image, 1, 0, [0.43, 0.0, 0.0, 1.0]);
+        assert_pixel(&image, 3, 0, [0.43, 0.1, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn switch_fall_through() {
+        let image = render(
+            "void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                float value = 0.0;
+                switch (int(fragCoord.x)) {
+                    case 0:
+                        value += 0.1;
+                    case 1:
+                        value += 0.2;
+                        break;
+                    default:
+                        value = 0.5;
+                }
+                fragColor = vec4(value, 0.0, 0.0, 1.0);
+            }",
+            3,
+            1,
+        )
+        .unwrap();
+
+        assert_pixel(&image, 0, 0, [0.3, 0.0, 0.0, 1.0]);
+        assert_pixel(&image, 1, 0, [0.2, 0.0, 0.0, 1.0]);
+        assert_pixel(&image, 2, 0, [0.5, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn matrices() {
+        let image = render(
+            "void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                mat2 m = mat2(1.0, 2.0, 3.0, 4.0);
+                vec2 column = m * vec2(1.0, 0.5);
+                vec2 row = vec2(1.0, 0.5) * m;
+                mat2 squared = m * m;
+                fragColor = vec4(column.y, row.y, squared[1][1], 0.0) / vec4(10.0, 10.0, 100.0, 1.0);
+            }",
+            1,
+            1,
+        )
+        .unwrap();
+
+        // columns are (1, 2) and (3, 4), the squared matrix has (15, 22) as its second column
+        assert_pixel(&image, 0, 0, [0.4, 0.5, 0.22, 1.0]);
+    }
+
+    #[test]
+    fn max_steps() {
+        let err = render(
+            "void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+                float value = 0.0;
+                while (iTime > 0.0) {
+                    value += 1.0;
+                }
+                fragColor = vec4(value);
+            }",
+            1,
+            1,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("steps"), "{}", err);
+    }
+
+    #[test]
+    fn binary_operators() {
+        // scalars are used for every component
+        assert_eq!(
+            binary(
+                BinaryOperator::Add,
+                &vector(&[1.0, 2.0]),
+                &Value::float(0.5)
+            )
+            .unwrap(),
+            vector(&[1.5, 2.5])
+        );
+        // GLSL's mod takes the sign of the divisor
+        assert_eq!(
+            binary(
+                BinaryOperator::Modulo,
+                &Value::float(-1.0),
+                &Value::float(3.0)
+            )
+            .unwrap(),
+            Value::float(2.0)
+        );
+        assert_eq!(
+            binary(
+                BinaryOperator::Less,
+                &vector(&[1.0, 3.0]),
+                &vector(&[2.0, 2.0])
+            )
+            .unwrap(),
+            Value::from_scalars(&[Scalar::Bool(true), Scalar::Bool(false)])
+        );
+
+        // integer division by zero is undefined, but doesn't panic
+        let int = |value| Value::Scalar(Scalar::Sint(value));
+        assert_eq!(
+            binary(BinaryOperator::Divide, &int(7), &int(0)).unwrap(),
+            int(0)
+        );
+        assert_eq!(
+            binary(BinaryOperator::Add, &int(i32::MAX), &int(1)).unwrap(),
+            int(i32::MIN)
+        );
+
+        assert!(binary(
+            BinaryOperator::Add,
+            &vector(&[1.0, 2.0]),
+            &vector(&[1.0, 2.0, 3.0])
+        )
+        .is_err());
+        assert!(binary(BinaryOperator::Add, &Value::float(1.0), &int(1)).is_err());
+    }
+
+    #[test]
+    fn componentwise_composites() {
+        // matrices are composites of columns, added column by column
+        let matrix = Value::from_matrix(&[vec![1.0, 2.0], vec![3.0, 4.0]]);
+        assert_eq!(
+            binary(BinaryOperator::Add, &matrix, &Value::float(1.0)).unwrap(),
+            Value::from_matrix(&[vec![2.0, 3.0], vec![4.0, 5.0]])
+        );
+        assert_eq!(
+            float_componentwise(&[&matrix, &matrix], &|floats| floats[0] * floats[1]).unwrap(),
+            Value::from_matrix(&[vec![1.0, 4.0], vec![9.0, 16.0]])
+        );
+    }
+
+    #[test]
+    fn determinant_and_inverse_of_matrices() {
+        let assert_close = |values: &[Vec<f32>], expected: &[Vec<f32>]| {
+            let values: Vec<f32> = values.iter().flatten().copied().collect();
+            let expected: Vec<f32> = expected.iter().flatten().copied().collect();
+            assert!(
+                values
+                    .iter()
+                    .zip(&expected)
+                    .all(|(value, expected)| (value - expected).abs() < 1e-5),
+                "{:?} isn't {:?}",
+                values,
+                expected
+            );
+        };
+
+        let (determinant, inverse) = determinant_and_inverse(&[vec![1.0, 2.0], vec![3.0, 4.0]]);
+        assert_close(&[vec![determinant]], &[vec![-2.0]]);
+        assert_close(&inverse, &[vec![-2.0, 1.0], vec![1.5, -0.5]]);
+
+        // needs a row swap, which flips the sign of the determinant
+        let (determinant, inverse) = determinant_and_inverse(&[
+            vec![0.0, 1.0, 0.0],
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 2.0],
+        ]);
+        assert_close(&[vec![determinant]], &[vec![-2.0]]);
+        assert_close(
+            &inverse,
+            &[
+                vec![0.0, 1.0, 0.0],
+                vec![1.0, 0.0, 0.0],
+                vec![0.0, 0.0, 0.5],
+            ],
+        );
+
+        let (determinant, inverse) = determinant_and_inverse(&[vec![1.0, 2.0], vec![2.0, 4.0]]);
+        assert_eq!(determinant, 0.0);
+        assert!(inverse.iter().flatten().all(|value| value.is_nan()));
+
+        // e.g. inverse(mat2(0.0 / 0.0)), which must not panic while rendering
+        let (determinant, inverse) =
+            determinant_and_inverse(&[vec![f32::NAN, 0.0], vec![0.0, f32::NAN]]);
+        assert!(determinant.is_nan());
+        assert!(inverse.iter().flatten().all(|value| value.is_nan()));
+
+        let (determinant, inverse) =
+            determinant_and_inverse(&[vec![1.0, 0.0], vec![f32::NAN, 1.0]]);
+        assert!(determinant.is_nan());
+        assert!(inverse.iter().flatten().all(|value| value.is_nan()));
+    }
+
+    #[test]
+    fn round_halfway_to_even() {
+        assert_eq!(round_even(0.5), 0.0);
+        assert_eq!(round_even(1.5), 2.0);
+        assert_eq!(round_even(2.5), 2.0);
+        assert_eq!(round_even(-1.5), -2.0);
+        assert_eq!(round_even(-2.5), -2.0);
+        assert_eq!(round_even(2.4), 2.0);
+        assert_eq!(round_even(2.6), 3.0);
+    }
+}
@@ -1,16 +1,15 @@
 use cocoa;
 use libc;
 use metal;
-use shaderc;
-use spirv_cross;
 use winit;
 
 use crate::errors::*;
+use crate::image::Image;
 use crate::render::*;
-use chrono::prelude::*;
+use crate::sandbox::Sandbox;
+use crate::translate::Target;
 use cocoa::appkit::{NSView, NSWindow};
 use cocoa::base::id as cocoa_id;
-use floating_duration::TimeAsFloat;
 use foreign_types_shared::ForeignType;
 use objc::runtime::{Object, YES};
 use std::any::Any;
@@ -22,7 +21,6 @@ use std::io::Write;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use std::time::Instant;
 use winit::platform::macos::WindowExtMacOS;
 
 struct MetalRenderPipeline {
@@ -38,18 +36,16 @@ pub struct MetalRenderBackend {
     layer: Option<metal::MetalLayer>,
     dpi_factor: f32,
 
-    frame_index: u64,
-    time: Instant,
-    time_last_frame: Instant,
-
     vs_function: metal::Function,
+    sandbox: Sandbox,
     pipelines: Mutex<RefCell<Vec<MetalRenderPipeline>>>,
 }
 
 unsafe impl Sync for MetalRenderBackend {}
 
 impl MetalRenderBackend {
-    pub fn new() -> Result<MetalRenderBackend> {
+    /// Creates the backend, shaders are translated to Metal in worker processes limited by `sandbox`.
+    pub fn new(sandbox: Sandbox) -> Result<MetalRenderBackend> {
         let device = metal::Device::system_default().unwrap();
         let command_queue = device.new_command_queue();
 
@@ -67,10 +63,8 @@ impl MetalRenderBackend {
             command_queue,
             layer: None,
             dpi_factor: 1.0,
-            frame_index: 0,
-            time: Instant::now(),
-            time_last_frame: Instant::now(),
             vs_function: vs_function,
+            sandbox,
             pipelines: Mutex::new(RefCell::new(vec![])),
         })
     }
@@ -157,6 +151,39 @@ impl MetalRenderBackend {
         profile_scope!("new_render_pipeline_state");
         new_render_pipeline_state(&self.device, &pipeline_desc)
     }
+
+    /// Encodes drawing a pipeline into a viewport, which is covered by the quad of the vertex shader.
+    fn encode_quad(
+        &self,
+        encoder: &metal::RenderCommandEncoderRef,
+        pipeline_handle: RenderPipelineHandle,
+        constants: &ShadertoyConstants,
+        viewport: metal::MTLViewport,
+    ) -> Result<()> {
+        let pipelines_lock = self.pipelines.lock().unwrap();
+        let pipelines = pipelines_lock.borrow();
+        let pipeline = pipelines
+            .get(pipeline_handle)
+            .chain_err(|| format!("unknown pipeline {}", pipeline_handle))?;
+        let constants_ptr: *const ShadertoyConstants = constants;
+        let constants_cptr = constants_ptr as *mut libc::c_void;
+
+        encoder.set_render_pipeline_state(&pipeline.pipeline_state);
+        encoder.set_cull_mode(metal::MTLCullMode::None);
+        encoder.set_vertex_bytes(
+            0,
+            mem::size_of::<ShadertoyConstants>() as u64,
+            constants_cptr,
+        );
+        encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<ShadertoyConstants>() as u64,
+            constants_cptr,
+        );
+        encoder.set_viewport(viewport);
+        encoder.draw_primitives(metal::MTLPrimitiveType::Triangle, 0, 3);
+        Ok(())
+    }
 }
 
 impl RenderBackend for MetalRenderBackend {
@@ -211,85 +238,40 @@ impl RenderBackend for MetalRenderBackend {
                 let w = drawable.texture().width() as f32;
                 let h = drawable.texture().height() as f32;
 
+                // shadertoys have the origin in the bottom left corner
+                let mut mouse = (
+                    (params.mouse_pos.0 as f32) / self.dpi_factor,
+                    (params.mouse_pos.1 as f32) / self.dpi_factor,
+                    (params.mouse_click_pos.0 as f32) / self.dpi_factor,
+                    (params.mouse_click_pos.1 as f32) / self.dpi_factor,
+                );
+                if mouse.1 > 0.0 {
+                    mouse.1 = h - mouse.1;
+                }
+                if mouse.3 > 0.0 {
+                    mouse.3 = h - mouse.3;
+                }
+
+                let inputs = params.clock.frame_inputs(mouse);
+
                 for quad in params.quads {
-                    let constants = {
-                        let time = self.time.elapsed().as_fractional_secs() as f32;
-                        let delta_time = self.time_last_frame.elapsed().as_fractional_secs() as f32;
-
-                        let dt: DateTime<Local> = Local::now();
-
-                        let mut mouse = (
-                            (params.mouse_pos.0 as f32) / self.dpi_factor,
-                            (params.mouse_pos.1 as f32) / self.dpi_factor,
-                            (params.mouse_click_pos.0 as f32) / self.dpi_factor,
-                            (params.mouse_click_pos.1 as f32) / self.dpi_factor,
-                        );
-
-                        // flip y
-                        if mouse.1 > 0.0 {
-                            mouse.1 = h - mouse.1;
-                        }
-                        if mouse.3 > 0.0 {
-                            mouse.3 = h - mouse.3;
-                        }
-
-                        ShadertoyConstants {
-                            iResolution: ((quad.size.0 * w), (quad.size.1 * h), w / h),
-                            pad1: 0.0,
-                            iMouse: mouse,
-                            iTime: time,
-                            iTimeDelta: delta_time,
-                            iFrameRate: 1.0 / delta_time,
-                            iSampleRate: 44100.0,
-                            iFrame: self.frame_index as i32,
-                            pad2: [0, 0, 0],
-                            iDate: (
-                                dt.year() as f32,
-                                dt.month() as f32,
-                                dt.day() as f32,
-                                dt.second() as f32, // TODO unclear what seconds should be here?
-                            ),
-                            iChannelTime: [time, time, time, time], // TODO not correct
-                            iChannelResolution: [
-                                (0.0, 0.0, 0.0, 0.0),
-                                (0.0, 0.0, 0.0, 0.0),
-                                (0.0, 0.0, 0.0, 0.0),
-                                (0.0, 0.0, 0.0, 0.0),
-                            ],
-                            iBlockOffset: 0.0,
-                            pad3: [0.0, 0.0, 0.0],
-                        }
-                    };
+                    let constants =
+                        ShadertoyConstants::new((quad.size.0 * w, quad.size.1 * h), &inputs);
 
-                    let pipelines_lock = self.pipelines.lock().unwrap();
-                    let pipelines = pipelines_lock.borrow();
-                    let pipeline = &pipelines[quad.pipeline_handle];
-                    let constants_ptr: *const ShadertoyConstants = &constants;
-                    let constants_cptr = constants_ptr as *mut libc::c_void;
-
-                    encoder.set_render_pipeline_state(&pipeline.pipeline_state);
-                    encoder.set_cull_mode(metal::MTLCullMode::None);
-                    encoder.set_vertex_bytes(
-                        0,
-                        mem::size_of::<ShadertoyConstants>() as u64,
-                        constants_cptr,
-                    );
-                    encoder.set_fragment_bytes(
-                        0,
-                        mem::size_of::<ShadertoyConstants>() as u64,
-                        constants_cptr,
-                    );
-
-                    encoder.set_viewport(metal::MTLViewport {
+                    let viewport = metal::MTLViewport {
                         originX: (quad.pos.0 * w).into(),
                         originY: (quad.pos.1 * h).into(),
                         width: (quad.size.0 * w).into(),
                         height: (quad.size.1 * h).into(),
                         znear: 0.0,
                         zfar: 1.0,
-                    });
+                    };
 
-                    encoder.draw_primitives(metal::MTLPrimitiveType::Triangle, 0, 3);
+                    if let Err(err) =
+                        self.encode_quad(encoder, quad.pipeline_handle, &constants, viewport)
+                    {
+                        error!("{}", err);
+                    }
                 }
 
                 encoder.end_encoding();
@@ -297,13 +279,82 @@ impl RenderBackend for MetalRenderBackend {
 
                 command_buffer.present_drawable(drawable);
                 command_buffer.commit();
-
-                self.frame_index += 1;
-                self.time_last_frame = Instant::now();
             }
         }
     }
 
+    fn render_to_image(&self, params: &ImageParams) -> Result<Image> {
+        profile_scope!("metal_render_to_image");
+
+        let (width, height) = (params.width as u64, params.height as u64);
+
+        // same format as the window, so the pipelines can render to both, which means the image
+        // is clamped to [0,1] and 8 bits per component, unlike with the cpu backend
+        let texture_desc = metal::TextureDescriptor::new();
+        texture_desc.set_pixel_format(metal::MTLPixelFormat::BGRA8Unorm);
+        texture_desc.set_width(width);
+        texture_desc.set_height(height);
+        texture_desc.set_storage_mode(metal::MTLStorageMode::Managed);
+        texture_desc.set_usage(metal::MTLTextureUsage::RenderTarget);
+        let texture = self.device.new_texture(&texture_desc);
+
+        let render_pass_descriptor = metal::RenderPassDescriptor::new();
+        let color_attachment = render_pass_descriptor
+            .color_attachments()
+            .object_at(0)
+            .unwrap();
+        color_attachment.set_texture(Some(&texture));
+        color_attachment.set_load_action(metal::MTLLoadAction::Clear);
+        color_attachment.set_clear_color(metal::MTLClearColor::new(0.0, 0.0, 0.0, 1.0));
+        color_attachment.set_store_action(metal::MTLStoreAction::Store);
+
+        let command_buffer = self.command_queue.new_command_buffer();
+        let encoder = command_buffer.new_render_command_encoder(render_pass_descriptor);
+
+        let constants =
+            ShadertoyConstants::new((params.width as f32, params.height as f32), &params.inputs);
+        let viewport = metal::MTLViewport {
+            originX: 0.0,
+            originY: 0.0,
+            width: width as f64,
+            height: height as f64,
+            znear: 0.0,
+            zfar: 1.0,
+        };
+        let result = self.encode_quad(encoder, params.pipeline_handle, &constants, viewport);
+        encoder.end_encoding();
+        result?;
+
+        // copy the managed texture back to the CPU
+        let blit_encoder = command_buffer.new_blit_command_encoder();
+        blit_encoder.synchronize_resource(&texture);
+        blit_encoder.end_encoding();
+
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
+
+        let stride = params.width * 4;
+        let mut bgra = vec![0u8; stride * params.height];
+        texture.get_bytes(
+            bgra.as_mut_ptr() as *mut libc::c_void,
+            stride as u64,
+            metal::MTLRegion::new_2d(0, 0, width, height),
+            0,
+        );
+
+        let mut image = Image::new(params.width, params.height);
+        for (pixel, bgra) in image.pixels_mut().iter_mut().zip(bgra.chunks_exact(4)) {
+            // like on shadertoy.com, the image is opaque whatever the alpha
+            *pixel = [
+                f32::from(bgra[2]) / 255.0,
+                f32::from(bgra[1]) / 255.0,
+                f32::from(bgra[0]) / 255.0,
+                1.0,
+            ];
+        }
+        Ok(image)
+    }
+
     fn new_pipeline(&self, shader_path: &str, shader_source: &str) -> Result<RenderPipelineHandle> {
         // save out the generated Metal file, for debugging
 
@@ -317,7 +368,10 @@ impl RenderBackend for MetalRenderBackend {
                 .chain_err(|| "failed reading metal shader file")?;
             metal_source = str;
         } else {
-            metal_source = convert_glsl_to_metal("unknown name", "main", shader_source)?;
+            // shaderc and spirv-cross can hang or crash on some shaders, so translate out of process
+            metal_source = self
+                .sandbox
+                .translate(Target::Msl, "unknown name", shader_source)?;
             write_file(&metal_path, metal_source.as_bytes())?;
         }
 
@@ -417,45 +471,6 @@ fn new_render_pipeline_state(
     }
 }
 
-fn convert_glsl_to_metal(name: &str, entry_point: &str, source: &str) -> Result<String> {
-    profile_scope!("convert_glsl_to_metal");
-
-    // convert to SPIR-V using shaderc
-
-    let mut compiler = shaderc::Compiler::new().unwrap();
-    let options = shaderc::CompileOptions::new().unwrap();
-
-    let binary_result = compiler
-        .compile_into_spirv(
-            source,
-            shaderc::ShaderKind::Fragment,
-            name,
-            entry_point,
-            Some(&options),
-        )
-        .chain_err(|| "shaderc compilation to SPIRV failed")?;
-
-    // convert SPIR-V to MSL
-
-    let module = spirv_cross::spirv::Module::from_words(binary_result.as_binary());
-
-    let mut ast = spirv_cross::spirv::Ast::<spirv_cross::msl::Target>::parse(&module).unwrap();
-
-    //    ast.compile().chain_err(|| "spirv-cross compilation failed")?
-
-    profile_scope!("spirv_compile_to_metal");
-
-    match ast.compile() {
-        Ok(str) => Ok(str),
-        Err(e) => match e {
-            spirv_cross::ErrorCode::Unhandled => Err("spirv-cross handled error".into()),
-            spirv_cross::ErrorCode::CompilationError(str) => {
-                Err(format!("spirv-cross error: {}", str).into())
-            }
-        },
-    }
-}
-
 fn write_file<P: AsRef<Path>>(path: P, buf: &[u8]) -> Result<()> {
     if let Some(parent_path) = path.as_ref().parent() {
         std::fs::create_dir_all(parent_path)?;
@@ -0,0 +1,305 @@
+//! Compatibility report of the downloaded shadertoys, for the `report` subcommand.
+//!
+//! Classifies every shadertoy by the features that keep it from being built, such as multiple
+//! passes or texture inputs, and for the remaining ones by the category of their compile error.
+//! This shows which features are worth supporting next.
+
+use crate::cache::{self, CachePaths};
+use crate::check;
+use crate::denylist::Denylist;
+use crate::errors::*;
+use crate::sandbox::Sandbox;
+use crate::translate;
+use serde::Serialize;
+use shadertoy::{ChannelType, PassType};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Why a shadertoy can't be built.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Blocker {
+    /// More than one pass, such as buffers or a common pass.
+    MultiPass,
+    /// No image pass to render, e.g. only a buffer or common pass.
+    NoImagePass,
+    CubemapPass,
+    SoundPass,
+    /// Texture, volume or cubemap inputs.
+    Textures,
+    Keyboard,
+    /// Music, music stream or microphone inputs.
+    SoundInput,
+    /// Video or webcam inputs.
+    Video,
+    UnknownInput(String),
+    /// On the denylist, see `Denylist`.
+    Denied,
+    /// Failed to compile, with the category of the error, see `compile_error_category`.
+    CompileError(&'static str),
+}
+
+impl fmt::Display for Blocker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blocker::MultiPass => write!(f, "multipass"),
+            Blocker::NoImagePass => write!(f, "no image pass"),
+            Blocker::CubemapPass => write!(f, "cubemap pass"),
+            Blocker::SoundPass => write!(f, "sound pass"),
+            Blocker::Textures => write!(f, "textures"),
+            Blocker::Keyboard => write!(f, "keyboard"),
+            Blocker::SoundInput => write!(f, "sound input"),
+            Blocker::Video => write!(f, "video"),
+            Blocker::UnknownInput(ctype) => write!(f, "unknown input {}", ctype),
+            Blocker::Denied => write!(f, "denied"),
+            Blocker::CompileError(category) => write!(f, "compile error: {}", category),
+        }
+    }
+}
+
+/// Features of a shadertoy that aren't supported yet, empty if it can be built.
+///
+/// Only single-pass image shaders without inputs are currently supported.
+pub fn blockers(shader: &shadertoy::Shader) -> Vec<Blocker> {
+    let mut blockers = vec![];
+
+    if shader.renderpass.len() > 1 {
+        blockers.push(Blocker::MultiPass);
+    }
+
+    if !shader
+        .renderpass
+        .iter()
+        .any(|pass| pass.pass_type == PassType::Image)
+    {
+        blockers.push(Blocker::NoImagePass);
+    }
+
+    for pass in &shader.renderpass {
+        match pass.pass_type {
+            PassType::Cubemap => blockers.push(Blocker::CubemapPass),
+            PassType::Sound => blockers.push(Blocker::SoundPass),
+            _ => {}
+        }
+
+        for input in &pass.inputs {
+            blockers.push(match input.ctype {
+                ChannelType::Texture | ChannelType::Volume | ChannelType::Cubemap => {
+                    Blocker::Textures
+                }
+                // buffers are only available with multiple passes
+                ChannelType::Buffer => Blocker::MultiPass,
+                ChannelType::Keyboard => Blocker::Keyboard,
+                ChannelType::Music | ChannelType::MusicStream | ChannelType::Mic => {
+                    Blocker::SoundInput
+                }
+                ChannelType::Video | ChannelType::Webcam => Blocker::Video,
+                ChannelType::Unknown(ref ctype) => Blocker::UnknownInput(ctype.clone()),
+            });
+        }
+    }
+
+    blockers.sort();
+    blockers.dedup();
+    blockers
+}
+
+/// Coarse category of a compile error message, to group similar failures.
+pub fn compile_error_category(error: &str) -> &'static str {
+    let error = error.to_lowercase();
+    let categories = [
+        ("timed out", "timeout"),
+        ("crashed", "crash"),
+        ("undeclared identifier", "undeclared identifier"),
+        ("no matching overloaded function", "no matching overload"),
+        ("syntax error", "syntax error"),
+        ("redefinition", "redefinition"),
+        ("cannot convert", "type conversion"),
+        ("wrong operand types", "type conversion"),
+        ("spirv-cross", "spirv-cross"),
+        ("naga", "naga"),
+        ("shaderc", "glsl"),
+        ("newrenderpipelinestate", "metal pipeline"),
+        ("program_source", "metal"),
+    ];
+
+    categories
+        .iter()
+        .find(|(pattern, _)| error.contains(pattern))
+        .map_or("other", |(_, category)| category)
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub shaders: usize,
+    /// Shaders without any blockers.
+    pub supported: usize,
+    /// Ids of the shaders with each blocker, a shader can have multiple blockers.
+    pub blockers: BTreeMap<String, Vec<String>>,
+}
+
+impl Report {
+    /// Classifies all downloaded shadertoys.
+    ///
+    /// Compile errors are taken from the recorded pipeline failures, unless a `sandbox` is
+    /// given to check the shadertoys that have no other blockers with, like `check` does.
+    pub fn collect(
+        cache_paths: &CachePaths,
+        denylist: &Denylist,
+        sandbox: Option<&Sandbox>,
+    ) -> Result<Report> {
+        profile_scope!("report");
+
+        let root = cache_paths.root();
+        let mut report = Report::default();
+
+        for shader_id in cache::downloaded_shadertoys(root)? {
+            let shader = cache::load_shader(root, &shader_id)?;
+
+            let mut shader_blockers = blockers(&shader);
+            if shader_blockers.is_empty() {
+                if denylist.denied(&shader_id).is_some() {
+                    shader_blockers.push(Blocker::Denied);
+                } else if let Some(error) = compile_error(cache_paths, &shader, sandbox)? {
+                    shader_blockers.push(Blocker::CompileError(compile_error_category(&error)));
+                }
+            }
+
+            report.shaders += 1;
+            if shader_blockers.is_empty() {
+                report.supported += 1;
+            }
+            for blocker in shader_blockers {
+                report
+                    .blockers
+                    .entry(blocker.to_string())
+                    .or_default()
+                    .push(shader_id.clone());
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// The compile error of a shadertoy without other blockers, if it failed.
+fn compile_error(
+    cache_paths: &CachePaths,
+    shader: &shadertoy::Shader,
+    sandbox: Option<&Sandbox>,
+) -> Result<Option<String>> {
+    if let Some(sandbox) = sandbox {
+        let check = check::check_shader(cache_paths.root(), &shader.info.id, sandbox, &[])?;
+        return Ok(check.error());
+    }
+
+    for pass in &shader.renderpass {
+        let source = translate::assemble_pass(shader, pass);
+        let error_path = cache_paths.pipeline_failure(&cache::source_hash(&source));
+        if error_path.exists() {
+            return Ok(Some(std::fs::read_to_string(&error_path)?));
+        }
+    }
+    Ok(None)
+}
+
+impl fmt::Display for Report {
+    /// Lists the first few ids of each blocker, or all of them with the alternate flag `{:#}`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} shadertoys, {} supported, {} blocked",
+            self.shaders,
+            self.supported,
+            self.shaders - self.supported
+        )?;
+
+        // most common blockers first
+        let mut blockers: Vec<(&String, &Vec<String>)> = self.blockers.iter().collect();
+        blockers.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+
+        let max_ids = if f.alternate() { usize::MAX } else { 5 };
+        for (blocker, ids) in blockers {
+            write!(f, "  {:<36} {:>6} ", blocker, ids.len())?;
+            for id in ids.iter().take(max_ids) {
+                write!(f, " {}", id)?;
+            }
+            if ids.len() > max_ids {
+                write!(f, " and {} more", ids.len() - max_ids)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_shader(passes: serde_json::Value) -> shadertoy::Shader {
+        serde_json::from_value(serde_json::json!({
+            "info": { "id": "test" },
+            "renderpass": passes,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn supported() {
+        let shader = test_shader(serde_json::json!([{ "type": "image" }]));
+        assert!(blockers(&shader).is_empty());
+    }
+
+    #[test]
+    fn single_pass_without_image() {
+        for pass_type in &["buffer", "common"] {
+            let shader = test_shader(serde_json::json!([{ "type": pass_type }]));
+            assert_eq!(blockers(&shader), vec![Blocker::NoImagePass]);
+        }
+
+        let shader = test_shader(serde_json::json!([{ "type": "sound" }]));
+        assert_eq!(
+            blockers(&shader),
+            vec![Blocker::NoImagePass, Blocker::SoundPass]
+        );
+
+        assert_eq!(
+            blockers(&test_shader(serde_json::json!([]))),
+            vec![Blocker::NoImagePass]
+        );
+    }
+
+    #[test]
+    fn multipass_and_inputs() {
+        let shader = test_shader(serde_json::json!([
+            {
+                "type": "image",
+                "inputs": [
+                    { "ctype": "buffer", "channel": 0 },
+                    { "ctype": "texture", "channel": 1 },
+                    { "ctype": "keyboard", "channel": 2 },
+                ],
+            },
+            { "type": "buffer" },
+        ]));
+        assert_eq!(
+            blockers(&shader),
+            vec![Blocker::MultiPass, Blocker::Textures, Blocker::Keyboard]
+        );
+    }
+
+    #[test]
+    fn compile_error_categories() {
+        assert_eq!(
+            compile_error_category("shader translation timed out after 30 s"),
+            "timeout"
+        );
+        assert_eq!(
+            compile_error_category(
+                "shaderc compilation to SPIRV failed: 'foo' : undeclared identifier"
+            ),
+            "undeclared identifier"
+        );
+        assert_eq!(compile_error_category("something else"), "other");
+    }
+}
@@ -0,0 +1,351 @@
+//! Runs shader translation in a separate worker process, so a shader that makes shaderc or
+//! spirv-cross hang, crash or run out of memory can't take down the browser.
+//!
+//! The worker is this executable started with the hidden `translate-worker` subcommand. It reads
+//! the GLSL source from stdin, writes the translated source, the SPIR-V binary or a JSON report of
+//! the stages to stdout and the error to stderr.
+//! Timeouts and crashes of the worker are returned as normal errors, so they end up in the
+//! `pipeline_fail` cache like any other build failure.
+
+use crate::errors::*;
+use crate::translate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// Name of the hidden subcommand that runs the worker.
+pub const WORKER_SUBCOMMAND: &str = "translate-worker";
+
+/// How often to check if the worker has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Reply of a worker run with `--report`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct WorkerReport {
+    stages: Vec<translate::StageReport>,
+    /// Source of every target that succeeded by the name of the target, only with `--sources`.
+    #[serde(default)]
+    sources: BTreeMap<String, String>,
+}
+
+/// Limits of the worker process.
+#[derive(Debug, Clone, Copy)]
+pub struct Sandbox {
+    /// Wall-clock time after which the worker is killed.
+    pub timeout: Duration,
+    /// Maximum address space of the worker in bytes, only enforced on Linux.
+    pub memory_limit: Option<u64>,
+}
+
+/// Parses a size such as `512M` or `4G`, a plain number is in bytes.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, unit_bytes) = match size.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&size[..index], 1 << 10),
+        Some((index, 'M')) | Some((index, 'm')) => (&size[..index], 1 << 20),
+        Some((index, 'G')) | Some((index, 'g')) => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+
+    let number: u64 = number
+        .trim()
+        .parse()
+        .chain_err(|| format!("invalid size {}, expected e.g. 512M or 4G", size))?;
+    match number.checked_mul(unit_bytes) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("size {} is too large", size),
+    }
+}
+
+impl Sandbox {
+    /// Translates a GLSL fragment shader to `target` in a worker process.
+    pub fn translate(&self, target: translate::Target, name: &str, source: &str) -> Result<String> {
+        profile_scope!("sandbox_translate");
+
+        let output = self.run_worker(&["--target", target.name(), "--name", name], source)?;
+        String::from_utf8(output).chain_err(|| "invalid source from the translation worker")
+    }
+
+    /// Compiles a GLSL fragment shader to SPIR-V in a worker process.
+    pub fn compile_to_spirv(&self, name: &str, source: &str) -> Result<Vec<u32>> {
+        profile_scope!("sandbox_compile_to_spirv");
+
+        let output = self.run_worker(&["--spirv", "--name", name], source)?;
+        if output.len() % 4 != 0 {
+            bail!("invalid SPIR-V from the translation worker");
+        }
+        Ok(output
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect())
+    }
+
+    /// Translates a GLSL fragment shader to the targets in a worker process, and returns the
+    /// reports of the stages instead of the translated source.
+    pub fn check(
+        &self,
+        targets: &[translate::Target],
+        name: &str,
+        source: &str,
+    ) -> Result<Vec<translate::StageReport>> {
+        profile_scope!("sandbox_check");
+
+        Ok(self.report(targets, &["--report"], name, source)?.stages)
+    }
+
+    /// Translates a GLSL fragment shader to the targets in a worker process, with the reports of
+    /// the stages and the source of every target that succeeded.
+    pub fn translate_all(
+        &self,
+        targets: &[translate::Target],
+        name: &str,
+        source: &str,
+    ) -> Result<translate::Translation> {
+        profile_scope!("sandbox_translate_all");
+
+        let mut report = self.report(targets, &["--report", "--sources"], name, source)?;
+        let mut outputs = vec![];
+        for target in targets {
+            if let Some(source) = report.sources.remove(target.name()) {
+                outputs.push((*target, source));
+            }
+        }
+
+        Ok(translate::Translation {
+            stages: report.stages,
+            spirv: None,
+            outputs,
+        })
+    }
+
+    fn report(
+        &self,
+        targets: &[translate::Target],
+        flags: &[&str],
+        name: &str,
+        source: &str,
+    ) -> Result<WorkerReport> {
+        let mut args = flags.to_vec();
+        args.extend_from_slice(&["--name", name]);
+        for target in targets {
+            args.push("--target");
+            args.push(target.name());
+        }
+
+        let report = self.run_worker(&args, source)?;
+        serde_json::from_slice(&report).chain_err(|| "invalid report from the translation worker")
+    }
+
+    /// Runs a worker with the arguments and the source on stdin, returns its output.
+    fn run_worker(&self, args: &[&str], source: &str) -> Result<Vec<u8>> {
+        let exe = std::env::current_exe().chain_err(|| "failed finding the executable")?;
+        let mut command = Command::new(exe);
+        command
+            .arg(WORKER_SUBCOMMAND)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(memory_limit) = self.memory_limit {
+                set_memory_limit(&mut command, memory_limit);
+            }
+        }
+
+        let mut child = command
+            .spawn()
+            .chain_err(|| "failed starting the translation worker")?;
+
+        // stdin and the outputs are handled on their own threads, so a full pipe can't block the worker
+        let mut stdin = child.stdin.take().unwrap();
+        let source = source.to_string();
+        let stdin_thread = std::thread::spawn(move || {
+            // fails if the worker exits without reading everything, which is reported below
+            let _r_ = stdin.write_all(source.as_bytes());
+        });
+        let stdout_thread = read_thread(child.stdout.take().unwrap());
+        let stderr_thread = read_thread(child.stderr.take().unwrap());
+
+        let time = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if time.elapsed() >= self.timeout {
+                // the worker may have exited just now, in which case killing it fails
+                let _r_ = child.kill();
+                child.wait()?;
+                break None;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+
+        let _r_ = stdin_thread.join();
+        let stdout = stdout_thread.join().unwrap_or_default();
+        let stderr =
+            String::from_utf8_lossy(&stderr_thread.join().unwrap_or_default()).into_owned();
+
+        match status {
+            None => bail!(ErrorKind::WorkerTimeout(self.timeout)),
+            Some(status) if status.success() => Ok(stdout),
+            Some(status) => match (exit_signal(status), stderr.trim()) {
+                (Some(signal), _) if cfg!(target_os = "linux") && self.memory_limit.is_some() => bail!(
+                    "shader translation crashed with signal {}, possibly by exceeding the memory limit",
+                    signal
+                ),
+                (Some(signal), _) => bail!("shader translation crashed with signal {}", signal),
+                (None, "") => bail!("shader translation failed with {}", status),
+                (None, error) => Err(error.into()),
+            },
+        }
+    }
+}
+
+fn read_thread<R: Read + Send + 'static>(mut reader: R) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = vec![];
+        let _r_ = reader.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+// other unixes accept RLIMIT_AS but don't enforce it, e.g. macOS
+#[cfg(target_os = "linux")]
+fn set_memory_limit(command: &mut Command, memory_limit: u64) {
+    use std::os::unix::process::CommandExt;
+
+    let limit = libc::rlimit {
+        rlim_cur: memory_limit as libc::rlim_t,
+        rlim_max: memory_limit as libc::rlim_t,
+    };
+
+    // only calls the async-signal-safe setrlimit between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The hidden subcommand that runs the worker.
+pub fn worker_subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(WORKER_SUBCOMMAND)
+        .setting(clap::AppSettings::Hidden)
+        .about("Translates a GLSL shader from stdin to stdout, used to sandbox shader translation")
+        .arg(
+            clap::Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("name")
+                .long("name")
+                .takes_value(true)
+                .default_value("shader"),
+        )
+        .arg(
+            clap::Arg::with_name("report")
+                .long("report")
+                .help("Print the reports of the stages as JSON, instead of the translated source"),
+        )
+        .arg(
+            clap::Arg::with_name("sources")
+                .long("sources")
+                .requires("report")
+                .help("Include the translated source of every target in the report"),
+        )
+        .arg(
+            clap::Arg::with_name("spirv")
+                .long("spirv")
+                .conflicts_with_all(&["target", "report"])
+                .help("Write the SPIR-V binary, instead of the translated source"),
+        )
+}
+
+/// Runs the worker, the error is printed to stderr by `main`.
+pub fn run_worker(matches: &clap::ArgMatches<'_>) -> Result<()> {
+    let mut source = String::new();
+    std::io::stdin().read_to_string(&mut source)?;
+
+    let targets = values_t!(matches, "target", translate::Target).unwrap_or_default();
+    let translation = translate::translate(matches.value_of("name").unwrap(), &source, &targets);
+
+    let output = if matches.is_present("report") {
+        let sources = if matches.is_present("sources") {
+            translation
+                .outputs
+                .iter()
+                .map(|(target, source)| (target.name().to_string(), source.clone()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        serde_json::to_vec(&WorkerReport {
+            stages: translation.stages,
+            sources,
+        })?
+    } else {
+        if let Some(error) = translation.error() {
+            bail!(error);
+        }
+        match targets.as_slice() {
+            [] if matches.is_present("spirv") => translation
+                .spirv
+                .unwrap()
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect(),
+            [target] => translation.output(*target).unwrap().as_bytes().to_vec(),
+            _ => bail!("expected a single target to translate to"),
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("4k").unwrap(), 4 << 10);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size(" 4 G ").unwrap(), 4 << 30);
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-1M").is_err());
+        assert!(parse_size("4T").is_err());
+        assert!(parse_size("17179869184G").is_err());
+        assert!(parse_size(&format!("{}K", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,10 @@
+layout(location = 0) in vec2 _fragCoord;
+layout(location = 0) out vec4 _fragColor;
+
+void main()
+{
+	// the face being rendered isn't passed in yet, so this always renders the +Z face
+	vec2 uv = (_fragCoord / iResolution.xy) * 2.0 - 1.0;
+	vec3 rayDir = normalize(vec3(uv, 1.0));
+	mainCubemap(_fragColor, _fragCoord, vec3(0.0), rayDir);
+}
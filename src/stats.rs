@@ -0,0 +1,99 @@
+//! Statistics over the shadertoys downloaded to the output directory, to get an idea of which
+//! features are worth supporting next.
+
+use crate::cache;
+use crate::errors::*;
+use crate::report;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Stats {
+    pub shaders: usize,
+    /// Shaders with a single image pass without inputs, the only ones currently built.
+    pub supported: usize,
+    pub passes: BTreeMap<String, usize>,
+    pub inputs: BTreeMap<String, usize>,
+    pub tags: BTreeMap<String, usize>,
+    pub authors: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    /// Collects the statistics of all downloaded shadertoys in the `root` output directory.
+    pub fn collect(root: &Path) -> Result<Stats> {
+        profile_scope!("stats");
+
+        let mut stats = Stats::default();
+
+        for shader_id in cache::downloaded_shadertoys(root)? {
+            let shader = cache::load_shader(root, &shader_id)?;
+
+            stats.shaders += 1;
+            if report::blockers(&shader).is_empty() {
+                stats.supported += 1;
+            }
+
+            for pass in &shader.renderpass {
+                *stats
+                    .passes
+                    .entry(pass.pass_type.as_str().to_string())
+                    .or_default() += 1;
+                for input in &pass.inputs {
+                    *stats
+                        .inputs
+                        .entry(input.ctype.as_str().to_string())
+                        .or_default() += 1;
+                }
+            }
+            for tag in &shader.info.tags {
+                *stats.tags.entry(tag.to_lowercase()).or_default() += 1;
+            }
+            *stats
+                .authors
+                .entry(shader.info.username.clone())
+                .or_default() += 1;
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Entries sorted by descending count, then name.
+fn most_common(counts: &BTreeMap<String, usize>, limit: usize) -> Vec<(&str, usize)> {
+    let mut entries: Vec<(&str, usize)> = counts
+        .iter()
+        .map(|(name, count)| (name.as_str(), *count))
+        .collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    entries.truncate(limit);
+    entries
+}
+
+fn write_counts(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    counts: &BTreeMap<String, usize>,
+    limit: usize,
+) -> fmt::Result {
+    writeln!(f, "{}:", title)?;
+    for (name, count) in most_common(counts, limit) {
+        writeln!(f, "  {:<20} {}", name, count)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} shadertoys, {} supported (single image pass without inputs)",
+            self.shaders, self.supported
+        )?;
+        write_counts(f, "Passes", &self.passes, usize::MAX)?;
+        write_counts(f, "Inputs", &self.inputs, usize::MAX)?;
+        write_counts(f, "Top tags", &self.tags, 10)?;
+        write_counts(f, "Top authors", &self.authors, 10)
+    }
+}
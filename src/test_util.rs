@@ -0,0 +1,15 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// An empty directory for a test to write files to, unique to the test process and `name`.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "shadertoy-browser-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _r_ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
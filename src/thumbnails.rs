@@ -0,0 +1,334 @@
+//! Contact sheets of shadertoy thumbnails, for the `thumbnails` subcommand.
+//!
+//! The thumbnails are laid out in the same grid as in the viewer, see `grid_cell`, with the name
+//! and author of each shadertoy drawn over the bottom of its thumbnail in a small bitmap font.
+
+use crate::clock::Clock;
+use crate::errors::*;
+use crate::image::Image;
+use crate::render::*;
+use std::path::Path;
+
+/// Width and height of the glyphs of `FONT` in pixels.
+const GLYPH_SIZE: (usize, usize) = (5, 7);
+
+/// Glyphs of the printable ASCII characters from ' ' to '~', as 5 columns from the left with the
+/// top row in the lowest bit.
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00], [0x14, 0x08, 0x3e, 0x08, 0x14], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31], [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x09, 0x01],
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x0c, 0x52, 0x52, 0x52, 0x3e],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x7f, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+/// Pixels between the glyphs and around the text of a label, before scaling.
+const LABEL_PADDING: usize = 1;
+
+const LABEL_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// How much the thumbnail behind a label is darkened, so the label is readable on any image.
+const LABEL_BACKGROUND_OPACITY: f32 = 0.6;
+
+/// A rendered shadertoy and the label to annotate it with.
+pub struct Thumbnail {
+    pub image: Image,
+    pub label: String,
+}
+
+pub struct ThumbnailParams {
+    /// Size of the contact sheets in pixels.
+    pub sheet_size: (usize, usize),
+    /// Columns and rows of thumbnails on a contact sheet, like the grid of the viewer.
+    pub grid_size: (usize, usize),
+    /// Time in seconds all thumbnails are rendered at.
+    pub time: f64,
+}
+
+/// Renders a thumbnail of each shadertoy into `dir`, and contact sheets with as many thumbnails
+/// as the viewer shows in its grid. Calls `on_thumbnail` after each shadertoy, with the error if
+/// it failed to render. Returns the number of thumbnails and contact sheets saved.
+pub fn render_thumbnails<F>(
+    render_backend: &dyn RenderBackend,
+    shadertoys: &[(&shadertoy::ShaderInfo, RenderPipelineHandle)],
+    params: &ThumbnailParams,
+    dir: &Path,
+    mut on_thumbnail: F,
+) -> Result<(usize, usize)>
+where
+    F: FnMut(&shadertoy::ShaderInfo, Option<&Error>),
+{
+    profile_scope!("render_thumbnails");
+
+    let grid_size = params.grid_size;
+    if grid_size.0 == 0 || grid_size.1 == 0 {
+        bail!("the grid needs at least one column and row");
+    }
+    let size = thumbnail_size(params.sheet_size, grid_size);
+
+    // all thumbnails show the same moment
+    let mut clock = Clock::fixed_fps(f64::from(FrameInputs::FRAME_RATE));
+    clock.seek(params.time);
+
+    std::fs::create_dir_all(dir)
+        .chain_err(|| format!("failed creating directory {}", dir.display()))?;
+
+    let mut rendered = vec![];
+    for (info, pipeline_handle) in shadertoys {
+        let image = render_backend.render_to_image(&ImageParams {
+            pipeline_handle: *pipeline_handle,
+            width: size.0,
+            height: size.1,
+            inputs: clock.frame_inputs((0.0, 0.0, 0.0, 0.0)),
+        });
+
+        // a shadertoy that fails to render is left out, like one that fails to build
+        let image = match image {
+            Ok(image) => image,
+            Err(err) => {
+                error!("Failed rendering {}: {}", info.id, err);
+                on_thumbnail(info, Some(&err));
+                continue;
+            }
+        };
+        image.save(&dir.join(format!("{}.png", info.id)))?;
+        on_thumbnail(info, None);
+
+        rendered.push(Thumbnail {
+            image,
+            label: format!("{} by {}", info.name, info.username),
+        });
+    }
+
+    let pages = rendered.chunks(grid_size.0 * grid_size.1);
+    let sheets = pages.len();
+    for (index, page) in pages.enumerate() {
+        let sheet = contact_sheet(page, params.sheet_size, grid_size);
+        sheet.save(&dir.join(format!("contact-sheet-{:03}.png", index + 1)))?;
+    }
+
+    Ok((rendered.len(), sheets))
+}
+
+/// Size of the thumbnails of contact sheets of `sheet_size` pixels, so that they fill the cells
+/// of a grid of `grid_size` like the quads in the viewer.
+pub fn thumbnail_size(sheet_size: (usize, usize), grid_size: (usize, usize)) -> (usize, usize) {
+    let (pos, size) = grid_cell(0, grid_size);
+    let (_, (width, height)) = pixel_rect(pos, size, sheet_size);
+    (width.max(1), height.max(1))
+}
+
+/// Pixel position and size of a rect in normalized coordinates, rounded like the render backends.
+fn pixel_rect(
+    pos: (f32, f32),
+    size: (f32, f32),
+    image_size: (usize, usize),
+) -> ((usize, usize), (usize, usize)) {
+    let (w, h) = (image_size.0 as f32, image_size.1 as f32);
+    let x0 = ((pos.0 * w).round() as usize).min(image_size.0);
+    let y0 = ((pos.1 * h).round() as usize).min(image_size.1);
+    let x1 = (((pos.0 + size.0) * w).round() as usize).min(image_size.0);
+    let y1 = (((pos.1 + size.1) * h).round() as usize).min(image_size.1);
+    ((x0, y0), (x1 - x0, y1 - y0))
+}
+
+/// Composes up to `grid_size.0 * grid_size.1` thumbnails into a contact sheet of `sheet_size`
+/// pixels, each labeled at its bottom. Cells without a thumbnail stay black.
+pub fn contact_sheet(
+    thumbnails: &[Thumbnail],
+    sheet_size: (usize, usize),
+    grid_size: (usize, usize),
+) -> Image {
+    let mut sheet = Image::new(sheet_size.0, sheet_size.1);
+    sheet.fill([0.0, 0.0, 0.0, 1.0]);
+
+    for (index, thumbnail) in thumbnails
+        .iter()
+        .take(grid_size.0 * grid_size.1)
+        .enumerate()
+    {
+        let (pos, size) = grid_cell(index, grid_size);
+        let ((x, y), (width, height)) = pixel_rect(pos, size, sheet_size);
+
+        let mut cell = thumbnail.image.clone();
+        draw_label(&mut cell, &thumbnail.label);
+        // cells can differ by a pixel due to rounding, the copy is clipped to the cell
+        let mut clipped = Image::new(width, height);
+        clipped.copy_from(&cell, 0, 0);
+        sheet.copy_from(&clipped, x, y);
+    }
+
+    sheet
+}
+
+/// Draws a label over the bottom of an image, cut off at the right edge if it doesn't fit.
+///
+/// The text is scaled up in larger images, characters the font doesn't have are drawn as `?`.
+pub fn draw_label(image: &mut Image, label: &str) {
+    let scale = (image.height() / 100).max(1);
+    let label_height = (GLYPH_SIZE.1 + 2 * LABEL_PADDING) * scale;
+    if image.height() < label_height {
+        return;
+    }
+
+    // darken the background of the label
+    let y0 = image.height() - label_height;
+    for y in y0..image.height() {
+        for x in 0..image.width() {
+            let mut color = image.pixel(x, y);
+            for channel in &mut color[..3] {
+                *channel *= 1.0 - LABEL_BACKGROUND_OPACITY;
+            }
+            image.set_pixel(x, y, color);
+        }
+    }
+
+    let advance = (GLYPH_SIZE.0 + LABEL_PADDING) * scale;
+    for (index, c) in label.chars().enumerate() {
+        let x = LABEL_PADDING * scale + index * advance;
+        if x + GLYPH_SIZE.0 * scale > image.width() {
+            break;
+        }
+        draw_glyph(image, c, x, y0 + LABEL_PADDING * scale, scale);
+    }
+}
+
+fn draw_glyph(image: &mut Image, c: char, x: usize, y: usize, scale: usize) {
+    let glyph = match c {
+        ' '..='~' => &FONT[c as usize - ' ' as usize],
+        _ => &FONT['?' as usize - ' ' as usize],
+    };
+
+    for (column, bits) in glyph.iter().enumerate() {
+        for row in 0..GLYPH_SIZE.1 {
+            if bits & (1 << row) == 0 {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    image.set_pixel(x + column * scale + dx, y + row * scale + dy, LABEL_COLOR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+    const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+    fn thumbnail(size: (usize, usize), color: [f32; 4]) -> Thumbnail {
+        let mut image = Image::new(size.0, size.1);
+        image.fill(color);
+        Thumbnail {
+            image,
+            label: "test".to_string(),
+        }
+    }
+
+    #[test]
+    fn thumbnail_sizes() {
+        assert_eq!(thumbnail_size((800, 450), (4, 3)), (200, 150));
+        // rounded like the quads in the viewer
+        assert_eq!(thumbnail_size((10, 10), (3, 3)), (3, 3));
+        assert_eq!(thumbnail_size((1, 1), (4, 4)), (1, 1));
+    }
+
+    #[test]
+    fn contact_sheet_layout() {
+        let thumbnails = [
+            thumbnail((2, 2), RED),
+            thumbnail((2, 2), GREEN),
+            thumbnail((2, 2), BLUE),
+        ];
+        let sheet = contact_sheet(&thumbnails, (4, 4), (2, 2));
+
+        // filled row by row from the top left, the cell without a thumbnail stays black
+        for (x, y, color) in &[(0, 0, RED), (2, 0, GREEN), (0, 2, BLUE), (2, 2, BLACK)] {
+            for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                assert_eq!(sheet.pixel(x + dx, y + dy), *color);
+            }
+        }
+
+        // only as many thumbnails as there are cells
+        let sheet = contact_sheet(&thumbnails, (2, 2), (1, 1));
+        assert!(sheet.pixels().iter().all(|pixel| *pixel == RED));
+    }
+
+    #[test]
+    fn contact_sheet_clipping() {
+        // the cells of a 5 pixel wide sheet are 3 and 2 pixels wide
+        let size = thumbnail_size((5, 4), (2, 2));
+        assert_eq!(size, (3, 2));
+        let sheet = contact_sheet(
+            &[thumbnail(size, RED), thumbnail(size, GREEN)],
+            (5, 4),
+            (2, 2),
+        );
+        assert_eq!(sheet.pixel(2, 0), RED);
+        assert_eq!(sheet.pixel(3, 0), GREEN);
+        assert_eq!(sheet.pixel(4, 1), GREEN);
+        assert_eq!(sheet.pixel(0, 2), BLACK);
+
+        // a thumbnail larger than its cell doesn't cover the others
+        let sheet = contact_sheet(&[thumbnail((4, 4), RED)], (4, 4), (2, 2));
+        assert_eq!(sheet.pixel(1, 1), RED);
+        assert_eq!(sheet.pixel(2, 0), BLACK);
+        assert_eq!(sheet.pixel(0, 2), BLACK);
+    }
+
+    #[test]
+    fn labels() {
+        let gray = [0.5, 0.5, 0.5, 1.0];
+        let dark = 0.5 * (1.0 - LABEL_BACKGROUND_OPACITY);
+        let mut image = Image::new(20, 10);
+        image.fill(gray);
+        draw_label(&mut image, "I");
+
+        // the label covers the bottom 9 rows, the glyph starts after the padding
+        assert_eq!(image.pixel(0, 0), gray);
+        assert_eq!(image.pixel(1, 2), [dark, dark, dark, 1.0]);
+        assert_eq!(image.pixel(3, 2), LABEL_COLOR);
+        assert_eq!(image.pixel(3, 8), LABEL_COLOR);
+        assert_eq!(image.pixel(3, 9), [dark, dark, dark, 1.0]);
+
+        // images lower than a label are left as they are
+        let mut image = Image::new(20, 8);
+        image.fill(gray);
+        draw_label(&mut image, "I");
+        assert!(image.pixels().iter().all(|pixel| *pixel == gray));
+    }
+}
@@ -0,0 +1,424 @@
+//! Translation of shadertoy passes from GLSL to the shading languages of the render backends.
+//!
+//! A pass is first assembled into a complete GLSL fragment shader, see `assemble_pass`, which
+//! is compiled to SPIR-V with shaderc. The SPIR-V is then cross-compiled to each target, with
+//! spirv-cross for MSL, GLSL ES and HLSL and with naga for WGSL. None of this needs a GPU, so
+//! it works the same on every OS. Every stage reports its own diagnostics, see `StageReport`.
+
+use crate::errors::*;
+use floating_duration::TimeAsFloat;
+use serde::{Deserialize, Serialize};
+use shadertoy::{ChannelType, PassType};
+use std::time::Instant;
+
+/// A shading language to translate to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Msl,
+    GlslEs,
+    Hlsl,
+    Wgsl,
+}
+
+impl Target {
+    pub const ALL: [Target; 4] = [Target::Msl, Target::GlslEs, Target::Hlsl, Target::Wgsl];
+
+    /// Name used on the command-line and in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Msl => "msl",
+            Target::GlslEs => "glsl-es",
+            Target::Hlsl => "hlsl",
+            Target::Wgsl => "wgsl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Target> {
+        Target::ALL
+            .iter()
+            .copied()
+            .find(|target| target.name() == name)
+    }
+
+    /// Extension of the files the translated source is saved to.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Target::Msl => "metal",
+            Target::GlslEs => "es.glsl",
+            Target::Hlsl => "hlsl",
+            Target::Wgsl => "wgsl",
+        }
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Target> {
+        Target::from_name(&s.to_lowercase())
+            .chain_err(|| format!("unknown translation target {}", s))
+    }
+}
+
+/// Whether the passes of a shader can be translated, the common pass is only included in the others.
+pub fn is_translated(pass: &shadertoy::RenderPass) -> bool {
+    pass.pass_type != PassType::Common
+}
+
+/// Assembles the complete GLSL source of a pass: our header with the shadertoy constants, the
+/// sampler declarations of its inputs, the code of the common pass, the code of the pass itself
+/// and a footer with the `main` function for the type of pass.
+pub fn assemble_pass(shader: &shadertoy::Shader, pass: &shadertoy::RenderPass) -> String {
+    // generate a GLSL snippet containing the sampler declarations
+    // as they are dependent on the renderpass inputs in the JSON
+    // for exaxmple:
+    //     uniform sampler2D iChannel0;
+    //     uniform sampler2D iChannel1;
+    //     uniform sampler2D iChannel2;
+    //     uniform sampler2D iChannel3;
+
+    let mut sampler_source = String::new();
+    for input in &pass.inputs {
+        let glsl_type = match input.ctype {
+            ChannelType::Volume => "sampler3D",
+            ChannelType::Cubemap => "samplerCube",
+            ChannelType::Texture
+            | ChannelType::Buffer
+            | ChannelType::Video
+            | ChannelType::Webcam
+            | ChannelType::Keyboard
+            | ChannelType::Music
+            | ChannelType::MusicStream
+            | ChannelType::Mic => "sampler2D",
+            ChannelType::Unknown(ref ctype) => {
+                warn!(
+                    "Unknown ctype {} in shadertoy {}, assuming sampler2D",
+                    ctype, shader.info.id
+                );
+                "sampler2D"
+            }
+        };
+        sampler_source.push_str(&format!(
+            "uniform {} iChannel{};\n",
+            glsl_type, input.channel
+        ));
+    }
+
+    // the common pass has code shared by all other passes
+    let common_source: String = shader
+        .renderpass
+        .iter()
+        .filter(|pass| pass.pass_type == PassType::Common)
+        .map(|pass| format!("{}\n", pass.code))
+        .collect();
+
+    let header_source = include_str!("shadertoy_header.glsl");
+    let footer_source = match pass.pass_type {
+        PassType::Sound => include_str!("shadertoy_sound_footer.glsl"),
+        PassType::Cubemap => include_str!("shadertoy_cubemap_footer.glsl"),
+        _ => include_str!("shadertoy_image_footer.glsl"),
+    };
+
+    // add our header source first which includes shadertoy constant & resource definitions
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        header_source, sampler_source, common_source, pass.code, footer_source
+    )
+}
+
+/// Outcome of one stage of a translation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StageReport {
+    /// `spirv` for the compilation to SPIR-V, otherwise the name of the target.
+    pub stage: String,
+    pub success: bool,
+    pub time_ms: f64,
+    /// Warnings of a successful stage, or the error of a failed one.
+    pub diagnostics: Option<String>,
+}
+
+/// Error of the first stage that failed, prefixed with the name of the stage.
+pub fn first_error(stages: &[StageReport]) -> Option<String> {
+    stages
+        .iter()
+        .find(|stage| !stage.success)
+        .map(|stage| match stage.diagnostics {
+            Some(ref diagnostics) => format!("{} failed: {}", stage.stage, diagnostics),
+            None => format!("{} failed", stage.stage),
+        })
+}
+
+/// Result of translating a fragment shader to a number of targets.
+#[derive(Debug, Default)]
+pub struct Translation {
+    pub stages: Vec<StageReport>,
+    pub spirv: Option<Vec<u32>>,
+    pub outputs: Vec<(Target, String)>,
+}
+
+impl Translation {
+    pub fn success(&self) -> bool {
+        self.stages.iter().all(|stage| stage.success)
+    }
+
+    /// Error of the first stage that failed.
+    pub fn error(&self) -> Option<String> {
+        first_error(&self.stages)
+    }
+
+    pub fn output(&self, target: Target) -> Option<&str> {
+        self.outputs
+            .iter()
+            .find(|(output_target, _)| *output_target == target)
+            .map(|(_, source)| source.as_str())
+    }
+}
+
+/// Translates a GLSL fragment shader to SPIR-V and then to each of the targets. The targets are
+/// only attempted if the compilation to SPIR-V succeeded, and fail independently of each other.
+pub fn translate(name: &str, source: &str, targets: &[Target]) -> Translation {
+    profile_scope!("translate");
+
+    let mut translation = Translation::default();
+
+    let time = Instant::now();
+    let spirv = compile_to_spirv(name, source);
+    translation.stages.push(StageReport {
+        stage: "spirv".to_string(),
+        success: spirv.is_ok(),
+        time_ms: time.elapsed().as_fractional_millis(),
+        diagnostics: match spirv {
+            Ok((_, ref warnings)) if !warnings.is_empty() => Some(warnings.clone()),
+            Ok(_) => None,
+            Err(ref err) => Some(error_message(err)),
+        },
+    });
+
+    let spirv = match spirv {
+        Ok((spirv, _)) => spirv,
+        Err(_) => return translation,
+    };
+
+    for target in targets {
+        let time = Instant::now();
+        let output = cross_compile(&spirv, *target);
+        translation.stages.push(StageReport {
+            stage: target.name().to_string(),
+            success: output.is_ok(),
+            time_ms: time.elapsed().as_fractional_millis(),
+            diagnostics: output.as_ref().err().map(error_message),
+        });
+        if let Ok(output) = output {
+            translation.outputs.push((*target, output));
+        }
+    }
+
+    translation.spirv = Some(spirv);
+    translation
+}
+
+/// The error with all its causes, as the causes have the details of compilation errors.
+fn error_message(err: &Error) -> String {
+    err.iter()
+        .map(|err| err.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Compiles a GLSL fragment shader to SPIR-V, returning the words and the warnings.
+pub fn compile_to_spirv(name: &str, source: &str) -> Result<(Vec<u32>, String)> {
+    profile_scope!("compile_to_spirv");
+
+    let mut compiler = shaderc::Compiler::new().chain_err(|| "failed creating shaderc compiler")?;
+    let mut options =
+        shaderc::CompileOptions::new().chain_err(|| "failed creating shaderc options")?;
+    // the sampler declarations don't have bindings, assign them in order
+    options.set_auto_bind_uniforms(true);
+
+    let binary_result = compiler
+        .compile_into_spirv(
+            source,
+            shaderc::ShaderKind::Fragment,
+            name,
+            "main",
+            Some(&options),
+        )
+        .chain_err(|| "shaderc compilation to SPIRV failed")?;
+
+    Ok((
+        binary_result.as_binary().to_vec(),
+        binary_result.get_warning_messages(),
+    ))
+}
+
+/// Cross-compiles SPIR-V to the source of a target.
+pub fn cross_compile(spirv: &[u32], target: Target) -> Result<String> {
+    profile_scope!("cross_compile");
+
+    let module = spirv_cross::spirv::Module::from_words(spirv);
+
+    let output = match target {
+        Target::Msl => {
+            let mut ast = spirv_cross::spirv::Ast::<spirv_cross::msl::Target>::parse(&module)
+                .map_err(spirv_cross_error)?;
+            ast.compile().map_err(spirv_cross_error)?
+        }
+        Target::GlslEs => {
+            let mut ast = spirv_cross::spirv::Ast::<spirv_cross::glsl::Target>::parse(&module)
+                .map_err(spirv_cross_error)?;
+            let mut options = spirv_cross::glsl::CompilerOptions::default();
+            options.version = spirv_cross::glsl::Version::V3_00Es;
+            ast.set_compiler_options(&options)
+                .map_err(spirv_cross_error)?;
+            ast.compile().map_err(spirv_cross_error)?
+        }
+        Target::Hlsl => {
+            let mut ast = spirv_cross::spirv::Ast::<spirv_cross::hlsl::Target>::parse(&module)
+                .map_err(spirv_cross_error)?;
+            let mut options = spirv_cross::hlsl::CompilerOptions::default();
+            options.shader_model = spirv_cross::hlsl::ShaderModel::V5_0;
+            ast.set_compiler_options(&options)
+                .map_err(spirv_cross_error)?;
+            ast.compile().map_err(spirv_cross_error)?
+        }
+        Target::Wgsl => {
+            let words: Vec<u8> = spirv.iter().flat_map(|word| word.to_le_bytes()).collect();
+            let module = naga::front::spv::parse_u8_slice(&words, &Default::default())
+                .map_err(|err| format!("naga SPIR-V parsing failed: {}", err))?;
+            let info = naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::empty(),
+            )
+            .validate(&module)
+            .map_err(|err| format!("naga validation failed: {}", err))?;
+            naga::back::wgsl::write_string(&module, &info)
+                .map_err(|err| format!("naga WGSL generation failed: {}", err))?
+        }
+    };
+
+    Ok(output)
+}
+
+fn spirv_cross_error(err: spirv_cross::ErrorCode) -> Error {
+    match err {
+        spirv_cross::ErrorCode::Unhandled => "spirv-cross unhandled error".into(),
+        spirv_cross::ErrorCode::CompilationError(str) => {
+            format!("spirv-cross error: {}", str).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_shader(passes: serde_json::Value) -> shadertoy::Shader {
+        serde_json::from_value(serde_json::json!({
+            "info": { "id": "test" },
+            "renderpass": passes,
+        }))
+        .unwrap()
+    }
+
+    fn stage(stage: &str, success: bool, diagnostics: Option<&str>) -> StageReport {
+        StageReport {
+            stage: stage.to_string(),
+            success,
+            time_ms: 0.0,
+            diagnostics: diagnostics.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn targets() {
+        for target in Target::ALL.iter() {
+            assert_eq!(target.name().parse::<Target>().unwrap(), *target);
+        }
+        assert_eq!("MSL".parse::<Target>().unwrap(), Target::Msl);
+        assert_eq!("glsl-es".parse::<Target>().unwrap(), Target::GlslEs);
+        assert_eq!(Target::GlslEs.extension(), "es.glsl");
+        assert!("spirv".parse::<Target>().is_err());
+        assert!(Target::from_name("HLSL").is_none());
+    }
+
+    #[test]
+    fn assemble_pass_sections() {
+        let shader = test_shader(serde_json::json!([
+            { "type": "common", "code": "// common code" },
+            {
+                "type": "image",
+                "code": "// image code",
+                "inputs": [
+                    { "ctype": "texture", "channel": 0 },
+                    { "ctype": "cubemap", "channel": 1 },
+                ],
+            },
+            { "type": "sound", "code": "// sound code" },
+        ]));
+        assert!(!is_translated(&shader.renderpass[0]));
+        assert!(is_translated(&shader.renderpass[1]));
+
+        // header, samplers, common pass, pass and footer
+        let source = assemble_pass(&shader, &shader.renderpass[1]);
+        let sections = [
+            "#version 440",
+            "uniform sampler2D iChannel0;",
+            "uniform samplerCube iChannel1;",
+            "// common code",
+            "// image code",
+            "mainImage(_fragColor, _fragCoord);",
+        ];
+        let positions: Vec<usize> = sections
+            .iter()
+            .map(|section| source.find(section).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let source = assemble_pass(&shader, &shader.renderpass[2]);
+        assert!(source.contains("// common code"));
+        assert!(!source.contains("iChannel0"));
+        assert!(source.contains("mainSound( t )"));
+    }
+
+    #[test]
+    fn first_errors() {
+        assert_eq!(first_error(&[stage("spirv", true, Some("warning"))]), None);
+        assert_eq!(
+            first_error(&[
+                stage("spirv", true, None),
+                stage("msl", false, None),
+                stage("wgsl", false, Some("naga failed")),
+            ]),
+            Some("msl failed".to_string())
+        );
+        assert_eq!(
+            first_error(&[stage("wgsl", false, Some("naga failed"))]),
+            Some("wgsl failed: naga failed".to_string())
+        );
+    }
+
+    #[test]
+    fn translate_stages() {
+        let shader = test_shader(serde_json::json!([{
+            "type": "image",
+            "code": "void mainImage(out vec4 fragColor, in vec2 fragCoord) { fragColor = vec4(1.0); }",
+        }]));
+        let source = assemble_pass(&shader, &shader.renderpass[0]);
+        let translation = translate("test", &source, &[Target::Wgsl]);
+        assert!(translation.success(), "{:?}", translation.error());
+        assert_eq!(translation.stages.len(), 2);
+        assert_eq!(translation.stages[1].stage, "wgsl");
+        assert!(translation.spirv.is_some());
+        assert!(translation.output(Target::Wgsl).is_some());
+        assert!(translation.output(Target::Msl).is_none());
+
+        // the targets aren't attempted without SPIR-V
+        let translation = translate("test", "not a shader", &Target::ALL);
+        assert!(!translation.success());
+        assert_eq!(translation.stages.len(), 1);
+        assert_eq!(translation.stages[0].stage, "spirv");
+        assert!(translation.error().unwrap().starts_with("spirv failed: "));
+        assert!(translation.spirv.is_none());
+        assert!(translation.outputs.is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Runs the browser against a `MockServer`, to test searching, downloading and building
+//! shadertoys end to end without network access.
+
+use serde_json::Value;
+use shadertoy::MockServer;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shadertoy/tests/fixtures");
+
+/// An empty cache directory for a test.
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "shadertoy-browser-download-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _r_ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the browser with ndjson output against the server.
+fn browser_output(server: &MockServer, cache_dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_shadertoy-browser"))
+        .arg("--baseurl")
+        .arg(server.url())
+        .args(["--apikey", "mock", "--format", "ndjson", "--threads", "0"])
+        .arg("--cache-dir")
+        .arg(cache_dir)
+        .arg("--denylist")
+        .arg(cache_dir.join("denylist.toml"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs the browser with ndjson output against the server and returns the output records.
+fn run_browser(server: &MockServer, cache_dir: &Path, args: &[&str]) -> Vec<Value> {
+    let output = browser_output(server, cache_dir, args);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}\n{}",
+        args,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn records<'a>(records: &'a [Value], record_type: &str) -> Vec<&'a Value> {
+    records
+        .iter()
+        .filter(|record| record["type"] == record_type)
+        .collect()
+}
+
+#[test]
+fn search_fetch_build() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("search-fetch-build");
+
+    let output = run_browser(&server, &cache_dir, &["search", "--search", "car"]);
+    let search = records(&output, "search");
+    assert_eq!(search.len(), 1);
+    assert_eq!(search[0]["total"], 2);
+    assert_eq!(search[0]["ids"], serde_json::json!(["4d2BDy", "MdX3Rr"]));
+    assert_eq!(search[0]["cached"], false);
+
+    // the search is cached, only the shaders and their assets are requested
+    let output = run_browser(&server, &cache_dir, &["fetch", "--search", "car"]);
+    assert_eq!(records(&output, "search")[0]["cached"], true);
+    let fetched = records(&output, "shadertoy");
+    assert_eq!(fetched.len(), 2);
+    assert!(fetched.iter().all(|status| status["success"] == true));
+    assert!(cache_dir.join("shader/4d2BDy/4d2BDy.json").is_file());
+    assert!(cache_dir.join("shader/MdX3Rr/MdX3Rr.json").is_file());
+    assert!(cache_dir.join("media/a/checker.png").is_file());
+    assert!(cache_dir.join("media/previz/buffer00.png").is_file());
+
+    // building only uses the downloaded shadertoys, there are no more requests to the server
+    drop(server);
+    let server = MockServer::start(FIXTURES_DIR, "other").unwrap();
+    let output = run_browser(&server, &cache_dir, &["build", "--backend", "cpu"]);
+    let built = records(&output, "shadertoy");
+    assert_eq!(built.len(), 2);
+    let status = |id: &str| *built.iter().find(|status| status["id"] == id).unwrap();
+    assert_eq!(status("4d2BDy")["success"], true);
+    assert!(status("4d2BDy")["build_time_ms"].is_number());
+    // multiple passes aren't supported, so there is nothing to build
+    assert!(status("MdX3Rr")["build_time_ms"].is_null());
+
+    let summary = records(&output, "summary");
+    assert_eq!(summary[0]["shadertoys"], 2);
+    assert_eq!(summary[0]["built"], 1);
+    assert_eq!(summary[0]["failed"], 0);
+}
+
+#[test]
+fn build_search() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("build-search");
+    run_browser(&server, &cache_dir, &["fetch"]);
+    run_browser(&server, &cache_dir, &["fetch", "--search", "texture"]);
+
+    // only the shadertoys of the cached search are built
+    drop(server);
+    let server = MockServer::start(FIXTURES_DIR, "other").unwrap();
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["build", "--backend", "cpu", "--search", "texture"],
+    );
+    assert_eq!(records(&output, "search")[0]["cached"], true);
+    let built = records(&output, "shadertoy");
+    assert_eq!(built.len(), 1);
+    assert_eq!(built[0]["id"], "MdX3Rr");
+
+    // searches that weren't fetched aren't requested
+    let output = browser_output(
+        &server,
+        &cache_dir,
+        &["build", "--backend", "cpu", "--search", "car"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn cpu_backend_does_not_view() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("cpu-backend-view");
+
+    // the default run only downloads, as the cpu backend can't view what it would build
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["--backend", "cpu", "--search", "car"],
+    );
+    assert_eq!(records(&output, "shadertoy").len(), 2);
+    let summary = records(&output, "summary");
+    assert_eq!(summary[0]["shadertoys"], 2);
+    assert_eq!(summary[0]["built"], 0);
+
+    // view fails before building anything
+    let output = browser_output(&server, &cache_dir, &["view", "--backend", "cpu"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("can't view shadertoys"));
+}
+
+#[test]
+fn skip_shadertoys() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("skip");
+
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["fetch", "--search", "car", "--skip", "MdX3Rr"],
+    );
+    let fetched = records(&output, "shadertoy");
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0]["id"], "4d2BDy");
+
+    // skipped shadertoys aren't counted
+    let summary = records(&output, "summary");
+    assert_eq!(summary[0]["shadertoys"], 1);
+    assert_eq!(summary[0]["failed"], 0);
+    assert!(!cache_dir.join("shader/MdX3Rr").exists());
+}
+
+#[test]
+fn translate() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("translate");
+    run_browser(&server, &cache_dir, &["fetch", "--search", "car"]);
+
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["translate", "4d2BDy", "--target", "wgsl", "--target", "msl"],
+    );
+    let translations = records(&output, "translation");
+    assert_eq!(translations.len(), 1);
+    assert_eq!(translations[0]["success"], true);
+    let stages: Vec<&Value> = translations[0]["stages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stage| &stage["stage"])
+        .collect();
+    assert_eq!(stages, ["spirv", "wgsl", "msl"]);
+
+    // the sources of every target come back from the worker
+    let pass_path = cache_dir.join("shader/4d2BDy/4d2BDyImage");
+    for extension in &["glsl", "wgsl", "metal"] {
+        let path = pass_path.with_extension(extension);
+        assert!(std::fs::metadata(&path).unwrap().len() > 0, "{:?}", path);
+    }
+    assert!(!pass_path.with_extension("hlsl").exists());
+}
+
+#[test]
+fn check() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("check");
+    run_browser(&server, &cache_dir, &["fetch", "--search", "car"]);
+
+    // a copy of a downloaded shadertoy that doesn't compile
+    let mut shader: Value = serde_json::from_reader(
+        std::fs::File::open(cache_dir.join("shader/4d2BDy/4d2BDy.json")).unwrap(),
+    )
+    .unwrap();
+    shader["info"]["id"] = "broken".into();
+    shader["renderpass"][0]["code"] =
+        "void mainImage(out vec4 fragColor, in vec2 fragCoord) {".into();
+    std::fs::create_dir_all(cache_dir.join("shader/broken")).unwrap();
+    std::fs::write(
+        cache_dir.join("shader/broken/broken.json"),
+        serde_json::to_string(&shader).unwrap(),
+    )
+    .unwrap();
+
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["check", "4d2BDy", "broken", "--fail-under", "50"],
+    );
+    let checks = records(&output, "check");
+    assert_eq!(checks.len(), 2);
+    assert_eq!(checks[0]["id"], "4d2BDy");
+    assert_eq!(checks[0]["success"], true);
+    assert_eq!(checks[1]["id"], "broken");
+    assert_eq!(checks[1]["success"], false);
+    assert_eq!(checks[1]["passes"][0]["stages"][0]["stage"], "spirv");
+    assert_eq!(checks[1]["passes"][0]["stages"][0]["success"], false);
+
+    let summary = records(&output, "check_summary");
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0]["shaders"], 2);
+    assert_eq!(summary[0]["passed"], 1);
+    assert_eq!(summary[0]["failed"], 1);
+    assert_eq!(summary[0]["failed_stages"]["spirv"], 1);
+
+    // the records are still written when the check fails
+    let output = browser_output(
+        &server,
+        &cache_dir,
+        &["check", "4d2BDy", "broken", "--fail-under", "60"],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"check_summary\""));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("50.0% of the shadertoys passed the check, less than 60%"));
+}
+
+#[test]
+fn ratelimit() {
+    let server = MockServer::start(FIXTURES_DIR, "mock").unwrap();
+    let cache_dir = cache_dir("ratelimit");
+
+    // a rate that low only delays the requests after the first one
+    let output = run_browser(
+        &server,
+        &cache_dir,
+        &["--ratelimit", "1e-20", "search", "--search", "car"],
+    );
+    assert_eq!(records(&output, "search")[0]["total"], 2);
+
+    for ratelimit in &["--ratelimit=-1", "--ratelimit=NaN", "--ratelimit=inf"] {
+        let output = browser_output(
+            &server,
+            &cache_dir,
+            &[ratelimit, "search", "--search", "car"],
+        );
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("invalid --ratelimit"));
+    }
+}
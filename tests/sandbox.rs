@@ -0,0 +1,123 @@
+//! Builds downloaded shadertoys with the limits of the translation workers set very low, to test
+//! that workers which time out, crash or fail are reported like any other build error.
+
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shadertoy/tests/fixtures");
+
+/// A cache directory for a test with the 4d2BDy fixture downloaded, with the code of its image
+/// pass replaced by `code` if given.
+fn cache_dir(name: &str, code: Option<&str>) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "shadertoy-browser-sandbox-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _r_ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("shader/4d2BDy")).unwrap();
+
+    let fixture = Path::new(FIXTURES_DIR).join("shaders/4d2BDy.json");
+    let mut shader: Value = serde_json::from_reader(std::fs::File::open(fixture).unwrap()).unwrap();
+    if let Some(code) = code {
+        shader["renderpass"][0]["code"] = code.into();
+    }
+    std::fs::write(
+        dir.join("shader/4d2BDy/4d2BDy.json"),
+        serde_json::to_string(&shader).unwrap(),
+    )
+    .unwrap();
+    dir
+}
+
+/// Builds the downloaded shadertoy with the cpu backend and returns its status record, the
+/// build errors of single shadertoys don't fail the run.
+fn build(cache_dir: &Path, args: &[&str]) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_shadertoy-browser"))
+        .args(["--format", "ndjson", "--threads", "0"])
+        .arg("--cache-dir")
+        .arg(cache_dir)
+        .arg("--denylist")
+        .arg(cache_dir.join("denylist.toml"))
+        .args(args)
+        .args(["build", "--backend", "cpu"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}\n{}",
+        args,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut statuses = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|record| record["type"] == "shadertoy");
+    let status = statuses.next().unwrap();
+    assert!(statuses.next().is_none());
+    assert_eq!(status["id"], "4d2BDy");
+    status
+}
+
+/// Entry of the shadertoy in the denylist, if it was added.
+fn denylist_entry(cache_dir: &Path) -> Option<toml::Value> {
+    let toml_str = std::fs::read_to_string(cache_dir.join("denylist.toml")).ok()?;
+    let lists: toml::Value = toml::from_str(&toml_str).unwrap();
+    lists["deny"].get("4d2BDy").cloned()
+}
+
+#[test]
+fn worker_timeout() {
+    let cache_dir = cache_dir("timeout", None);
+
+    let status = build(&cache_dir, &["--compile-timeout", "0s"]);
+    assert_eq!(status["success"], false);
+    let error = status["error"].as_str().unwrap();
+    assert!(error.contains("shader translation timed out"), "{}", error);
+
+    // timeouts are denied automatically, so they aren't tried again
+    let entry = denylist_entry(&cache_dir).unwrap();
+    assert_eq!(entry["reason"].as_str(), Some(error));
+    assert_eq!(entry["automatic"].as_bool(), Some(true));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn worker_crash() {
+    let cache_dir = cache_dir("crash", None);
+
+    let status = build(&cache_dir, &["--compile-memory", "1M"]);
+    assert_eq!(status["success"], false);
+    let error = status["error"].as_str().unwrap();
+    assert!(
+        error.contains("possibly by exceeding the memory limit"),
+        "{}",
+        error
+    );
+    assert!(denylist_entry(&cache_dir).is_none());
+}
+
+#[test]
+fn worker_error() {
+    let cache_dir = cache_dir(
+        "error",
+        Some("void mainImage(out vec4 fragColor, in vec2 fragCoord) {"),
+    );
+
+    // the worker exits with the compile error
+    let status = build(&cache_dir, &[]);
+    assert_eq!(status["success"], false);
+    let error = status["error"].as_str().unwrap();
+    assert!(!error.is_empty());
+    assert!(!error.contains("timed out"), "{}", error);
+    assert!(!error.contains("crashed"), "{}", error);
+    assert!(denylist_entry(&cache_dir).is_none());
+
+    // the failure is recorded, so the next build doesn't start a worker
+    let status = build(&cache_dir, &["--compile-timeout", "0s"]);
+    assert_eq!(status["error"].as_str(), Some(error));
+}